@@ -0,0 +1,1105 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    convert::TryFrom,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FromIterator,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use fehler::{throw, throws};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{alphanumeric1, char, crlf, digit1, none_of, one_of},
+    combinator::{all_consuming, cut, map, map_res, opt, peek, recognize, verify},
+    multi::{count, many0, many1, many_m_n},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use nom_locate::LocatedSpan;
+
+use crate::{mdns::hidden_address, Error, ICE_CHARS};
+
+type Span<'a> = LocatedSpan<&'a str>;
+
+struct Foundation(String);
+
+fn foundation(input: Span) -> IResult<Span, Foundation> {
+    map(
+        terminated(many_m_n(1, 32, one_of(ICE_CHARS)), char(' ')),
+        |chars| Foundation(String::from_iter(&chars)),
+    )(input)
+}
+
+struct ComponentId(u16);
+
+fn component_id(input: Span) -> IResult<Span, ComponentId> {
+    let (remainder, id) = map_res(
+        terminated(recognize(many_m_n(1, 5, digit1)), char(' ')),
+        |digits: Span| (*digits.fragment()).parse(),
+    )(input)?;
+
+    Ok((remainder, ComponentId(id)))
+}
+
+//  token       =  1*(alphanum / "-" / "." / "!" / "%" / "*"
+//                 / "_" / "+" / "`" / "'" / "~" )
+//
+// https://tools.ietf.org/html/rfc3261#section-25.1
+fn token(input: Span) -> IResult<Span, Span> {
+    recognize(many1(alt((
+        alphanumeric1,
+        recognize(many1(one_of("-.!%*_+`'~"))),
+    ))))(input)
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transport = match self {
+            Self::Udp => "udp",
+            Self::Tcp => "tcp",
+        };
+
+        write!(f, "{}", transport)
+    }
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+
+    #[throws]
+    fn from_str(token: &str) -> Self {
+        match token {
+            "udp" | "UDP" => Self::Udp,
+            "tcp" | "TCP" => Self::Tcp,
+            _ => throw!(Error::UnsupportedTransport(token.to_string())),
+        }
+    }
+}
+
+fn transport(input: Span) -> IResult<Span, Transport> {
+    map_res(terminated(token, char(' ')), |token: Span| {
+        (*token.fragment()).parse()
+    })(input)
+}
+
+struct Priority(u32);
+
+fn priority(input: Span) -> IResult<Span, Priority> {
+    let (remainder, priority) = map_res(
+        terminated(recognize(many_m_n(1, 10, digit1)), char(' ')),
+        |digits: Span| (*digits.fragment()).parse(),
+    )(input)?;
+
+    Ok((remainder, Priority(priority)))
+}
+
+fn ipv4_address(input: Span) -> IResult<Span, IpAddr> {
+    map_res(
+        recognize(pair(count(terminated(digit1, char('.')), 3), digit1)),
+        |addr: Span| (*addr.fragment()).parse(),
+    )(input)
+}
+
+fn ipv6_address(input: Span) -> IResult<Span, IpAddr> {
+    map_res(
+        recognize(many1(one_of("0123456789abcdefABCDEF:."))),
+        |addr: Span| (*addr.fragment()).parse(),
+    )(input)
+}
+
+//   FQDN =               4*(alpha-numeric / "-" / ".")
+//                        ; fully qualified domain name as specified
+//                        ; in RFC 1035 (and updates)
+//
+// https://tools.ietf.org/html/rfc4566#section-9
+fn fqdn(input: Span) -> IResult<Span, String> {
+    map(
+        verify(
+            recognize(many1(alt((alphanumeric1, recognize(one_of("-.")))))),
+            |fqdn: &Span| fqdn.fragment().len() >= 4,
+        ),
+        |fqdn: Span| fqdn.fragment().to_string(),
+    )(input)
+}
+
+// The connection address of a candidate, which may be a hostname (such
+// as an mDNS name) that needs resolving before the candidate is paired.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionAddress {
+    Ip(IpAddr),
+    Hostname(String),
+}
+
+impl fmt::Display for ConnectionAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "{}", ip),
+            Self::Hostname(hostname) => write!(f, "{}", hostname),
+        }
+    }
+}
+
+//   connection-address =  multicast-address / unicast-address
+//   unicast-address =     IP4-address / IP6-address / FQDN / extn-addr
+//
+// Each alternative has to end at the following space, so that the start
+// of a hostname isn't taken for an address.
+//
+// https://tools.ietf.org/html/rfc4566#section-9
+fn connection_address(input: Span) -> IResult<Span, ConnectionAddress> {
+    alt((
+        map(
+            terminated(ipv4_address, peek(char(' '))),
+            ConnectionAddress::Ip,
+        ),
+        map(
+            terminated(ipv6_address, peek(char(' '))),
+            ConnectionAddress::Ip,
+        ),
+        map(
+            terminated(fqdn, peek(char(' '))),
+            ConnectionAddress::Hostname,
+        ),
+    ))(input)
+}
+
+type Port = u16;
+
+fn port(input: Span) -> IResult<Span, Port> {
+    map_res(recognize(many_m_n(1, 5, digit1)), |digits: Span| {
+        (*digits.fragment()).parse()
+    })(input)
+}
+
+fn connection_address_and_port(input: Span) -> IResult<Span, (ConnectionAddress, Port)> {
+    pair(
+        terminated(connection_address, char(' ')),
+        terminated(port, char(' ')),
+    )(input)
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq)]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+    Relayed,
+    PeerReflexive,
+}
+
+impl fmt::Display for CandidateType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let token = match self {
+            Self::Host => "host",
+            Self::ServerReflexive => "srflx",
+            Self::Relayed => "relay",
+            Self::PeerReflexive => "prflx",
+        };
+
+        write!(f, "{}", token)
+    }
+}
+
+impl FromStr for CandidateType {
+    type Err = Error;
+
+    #[throws]
+    fn from_str(token: &str) -> Self {
+        match token {
+            "host" => Self::Host,
+            "srflx" => Self::ServerReflexive,
+            "relay" => Self::Relayed,
+            "prflx" => Self::PeerReflexive,
+            _ => throw!(Error::UnsupportedCandidateType(token.to_string())),
+        }
+    }
+}
+
+fn candidate_type(input: Span) -> IResult<Span, CandidateType> {
+    map_res(preceded(tag("typ "), token), |token: Span| {
+        (*token.fragment()).parse()
+    })(input)
+}
+
+fn related_address(input: Span) -> IResult<Span, ConnectionAddress> {
+    preceded(tag(" raddr "), connection_address)(input)
+}
+
+fn related_port(input: Span) -> IResult<Span, Port> {
+    preceded(tag(" rport "), port)(input)
+}
+
+// https://tools.ietf.org/html/rfc6544#section-4.5
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TcpType {
+    Active,
+    Passive,
+    SimultaneousOpen,
+}
+
+impl TcpType {
+    // The type of the remote candidate a local one of this type can be
+    // paired with.
+    //
+    // https://tools.ietf.org/html/rfc6544#section-6.2
+    pub(crate) fn reversed(self) -> Self {
+        match self {
+            Self::Active => Self::Passive,
+            Self::Passive => Self::Active,
+            Self::SimultaneousOpen => Self::SimultaneousOpen,
+        }
+    }
+}
+
+impl fmt::Display for TcpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tcp_type = match self {
+            Self::Active => "active",
+            Self::Passive => "passive",
+            Self::SimultaneousOpen => "so",
+        };
+
+        write!(f, "{}", tcp_type)
+    }
+}
+
+impl FromStr for TcpType {
+    type Err = Error;
+
+    #[throws]
+    fn from_str(token: &str) -> Self {
+        match token {
+            "active" => Self::Active,
+            "passive" => Self::Passive,
+            "so" => Self::SimultaneousOpen,
+            _ => throw!(Error::UnsupportedTcpType(token.to_string())),
+        }
+    }
+}
+
+//   tcp-type-ext = "tcptype" SP tcp-type
+//   tcp-type     = "active" / "passive" / "so"
+//
+// https://tools.ietf.org/html/rfc6544#section-4.5
+// Once the keyword has been seen, a value that isn't a known type fails
+// the candidate rather than being taken for an extension attribute.
+fn tcp_type(input: Span) -> IResult<Span, TcpType> {
+    preceded(
+        tag(" tcptype "),
+        cut(map_res(token, |token: Span| (*token.fragment()).parse())),
+    )(input)
+}
+
+fn extension_attribute(input: Span) -> IResult<Span, (String, String)> {
+    map(
+        pair(
+            preceded(char(' '), many1(none_of(" \r\n"))),
+            preceded(char(' '), many1(none_of(" \r\n"))),
+        ),
+        |(name, value)| (String::from_iter(&name), String::from_iter(&value)),
+    )(input)
+}
+
+// https://tools.ietf.org/html/rfc8445#section-5.1.2.2
+const HOST_PREFERENCE: u8 = 126;
+const TCP_HOST_PREFERENCE: u8 = 90;
+const PEER_REFLEXIVE_PREFERENCE: u8 = 110;
+const SERVER_REFLEXIVE_PREFERENCE: u8 = 100;
+const RELAYED_PREFERENCE: u8 = 0;
+const MAX_TYPE_PREFERENCE: u8 = 126;
+
+// The type preference of each kind of candidate, from 0 to 126, and the
+// local preference of any addresses that shouldn't be ordered the way
+// address selection would order them.
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.2.2
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preferences {
+    pub host: u8,
+    pub peer_reflexive: u8,
+    pub server_reflexive: u8,
+    pub relayed: u8,
+    pub tcp_host: u8,
+    pub local: HashMap<IpAddr, u16>,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            host: HOST_PREFERENCE,
+            peer_reflexive: PEER_REFLEXIVE_PREFERENCE,
+            server_reflexive: SERVER_REFLEXIVE_PREFERENCE,
+            relayed: RELAYED_PREFERENCE,
+            tcp_host: TCP_HOST_PREFERENCE,
+            local: HashMap::new(),
+        }
+    }
+}
+
+impl Preferences {
+    fn type_preference(&self, ty: CandidateType, transport: Transport) -> u32 {
+        let preference = match (ty, transport) {
+            (CandidateType::Host, Transport::Udp) => self.host,
+            (CandidateType::Host, Transport::Tcp) => self.tcp_host,
+            (CandidateType::PeerReflexive, _) => self.peer_reflexive,
+            (CandidateType::ServerReflexive, _) => self.server_reflexive,
+            (CandidateType::Relayed, _) => self.relayed,
+        };
+
+        u32::from(preference.min(MAX_TYPE_PREFERENCE))
+    }
+
+    fn local_preference(&self, base: SocketAddr) -> u32 {
+        match self.local.get(&base.ip()) {
+            Some(preference) => u32::from(*preference),
+            None => local_preference(base),
+        }
+    }
+
+    // The direction preference favours active candidates, since they don't
+    // need the remote side to accept connections.
+    //
+    // https://tools.ietf.org/html/rfc6544#section-4.2
+    fn tcp_local_preference(&self, tcp_type: TcpType, base: SocketAddr) -> u32 {
+        let direction_preference = match tcp_type {
+            TcpType::Active => 6,
+            TcpType::Passive => 4,
+            TcpType::SimultaneousOpen => 2,
+        };
+
+        (direction_preference << 13) + (self.local_preference(base) >> 3)
+    }
+
+    fn priority(
+        &self,
+        ty: CandidateType,
+        transport: Transport,
+        local_preference: u32,
+        component_id: u16,
+    ) -> u32 {
+        candidate_priority(
+            self.type_preference(ty, transport),
+            local_preference,
+            component_id,
+        )
+    }
+}
+
+// Precedence from the default policy table for address selection, where
+// IPv4 addresses are looked up as IPv4-mapped IPv6 addresses.
+//
+//   Prefix        Precedence Label
+//   ::1/128               50     0
+//   ::/0                  40     1
+//   ::ffff:0:0/96         35     4
+//   2002::/16             30     2
+//   2001::/32              5     5
+//   fc00::/7               3    13
+//   ::/96                  1     3
+//   fec0::/10              1    11
+//   3ffe::/16              1    12
+//
+// https://tools.ietf.org/html/rfc6724#section-2.1
+fn ip_precedence(address: IpAddr) -> u32 {
+    let addr = match address {
+        IpAddr::V4(_) => return 35,
+        IpAddr::V6(addr) => addr,
+    };
+
+    let segments = addr.segments();
+    if addr.is_loopback() {
+        50
+    } else if segments[..5] == [0; 5] && segments[5] == 0x_FFFF {
+        35
+    } else if segments[0] == 0x_2002 {
+        30
+    } else if segments[0] == 0x_2001 && segments[1] == 0 {
+        5
+    } else if segments[0] & 0x_FE00 == 0x_FC00 {
+        3
+    } else if segments[..6] == [0; 6] || segments[0] & 0x_FFC0 == 0x_FEC0 || segments[0] == 0x_3FFE
+    {
+        1
+    } else {
+        40
+    }
+}
+
+// Dual-stack agents prefer candidates the way address selection would,
+// leaving the low bits to order addresses of the same precedence.
+//
+// https://tools.ietf.org/html/rfc8421#section-4
+fn local_preference(base: SocketAddr) -> u32 {
+    (ip_precedence(base.ip()) << 10) + 1023
+}
+
+// priority = (2^24)*(type preference) +
+//            (2^8)*(local preference) +
+//            (2^0)*(256 - component ID)
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.2.1
+fn candidate_priority(type_preference: u32, local_preference: u32, component_id: u16) -> u32 {
+    (1 << 24) * type_preference + (1 << 8) * local_preference + (256 - u32::from(component_id))
+}
+
+// Candidates share a foundation when they have the same type, base IP
+// address, STUN or TURN server and transport.
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.1.3
+fn compute_foundation(
+    ty: CandidateType,
+    base: IpAddr,
+    server: Option<IpAddr>,
+    transport: Transport,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    (ty, base, server, transport).hash(&mut hasher);
+
+    (hasher.finish() as u32).to_string()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LocalCandidate {
+    pub(crate) foundation: String,
+    pub(crate) component_id: u16,
+    pub(crate) priority: u32,
+    pub(crate) address: SocketAddr,
+    pub(crate) base: SocketAddr,
+    pub(crate) related_address: Option<SocketAddr>,
+    pub(crate) ty: CandidateType,
+    pub(crate) transport: Transport,
+    pub(crate) tcp_type: Option<TcpType>,
+    // the mDNS name that stands in for the address of a host candidate
+    pub(crate) hostname: Option<String>,
+}
+
+impl LocalCandidate {
+    pub(crate) fn host(preferences: &Preferences, component_id: u16, address: SocketAddr) -> Self {
+        let (ty, transport) = (CandidateType::Host, Transport::Udp);
+
+        Self {
+            foundation: compute_foundation(ty, address.ip(), None, transport),
+            component_id,
+            priority: preferences.priority(
+                ty,
+                transport,
+                preferences.local_preference(address),
+                component_id,
+            ),
+            address,
+            base: address,
+            related_address: None,
+            ty,
+            transport,
+            tcp_type: None,
+            hostname: None,
+        }
+    }
+
+    // Active candidates are signalled with the discard port, which is
+    // also used as their base, since they connect from a new port each
+    // time.
+    pub(crate) fn tcp_host(
+        preferences: &Preferences,
+        component_id: u16,
+        address: SocketAddr,
+        tcp_type: TcpType,
+    ) -> Self {
+        let (ty, transport) = (CandidateType::Host, Transport::Tcp);
+
+        Self {
+            foundation: compute_foundation(ty, address.ip(), None, transport),
+            component_id,
+            priority: preferences.priority(
+                ty,
+                transport,
+                preferences.tcp_local_preference(tcp_type, address),
+                component_id,
+            ),
+            address,
+            base: address,
+            related_address: None,
+            ty,
+            transport,
+            tcp_type: Some(tcp_type),
+            hostname: None,
+        }
+    }
+
+    pub(crate) fn server_reflexive(
+        preferences: &Preferences,
+        component_id: u16,
+        address: SocketAddr,
+        base: SocketAddr,
+        server: IpAddr,
+    ) -> Self {
+        let (ty, transport) = (CandidateType::ServerReflexive, Transport::Udp);
+
+        Self {
+            foundation: compute_foundation(ty, base.ip(), Some(server), transport),
+            component_id,
+            priority: preferences.priority(
+                ty,
+                transport,
+                preferences.local_preference(base),
+                component_id,
+            ),
+            address,
+            base,
+            related_address: Some(base),
+            ty,
+            transport,
+            tcp_type: None,
+            hostname: None,
+        }
+    }
+
+    // The base of a relayed candidate is the candidate itself, and its
+    // related address is the mapped address of the allocation. Its
+    // foundation is taken from the host candidate it was allocated from.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-5.1.1.2
+    pub(crate) fn relayed(
+        preferences: &Preferences,
+        component_id: u16,
+        address: SocketAddr,
+        related_address: SocketAddr,
+        host: SocketAddr,
+        server: IpAddr,
+    ) -> Self {
+        let (ty, transport) = (CandidateType::Relayed, Transport::Udp);
+
+        Self {
+            foundation: compute_foundation(ty, host.ip(), Some(server), transport),
+            component_id,
+            priority: preferences.priority(
+                ty,
+                transport,
+                preferences.local_preference(address),
+                component_id,
+            ),
+            address,
+            base: address,
+            related_address: Some(related_address),
+            ty,
+            transport,
+            tcp_type: None,
+            hostname: None,
+        }
+    }
+
+    pub(crate) fn peer_reflexive(
+        preferences: &Preferences,
+        component_id: u16,
+        address: SocketAddr,
+        base: SocketAddr,
+    ) -> Self {
+        let (ty, transport) = (CandidateType::PeerReflexive, Transport::Udp);
+
+        Self {
+            foundation: compute_foundation(ty, base.ip(), None, transport),
+            component_id,
+            priority: Self::peer_reflexive_priority(preferences, component_id, base),
+            address,
+            base,
+            related_address: Some(base),
+            ty,
+            transport,
+            tcp_type: None,
+            hostname: None,
+        }
+    }
+
+    // The priority sent in the PRIORITY attribute of a check.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-7.1.1
+    pub(crate) fn peer_reflexive_priority(
+        preferences: &Preferences,
+        component_id: u16,
+        base: SocketAddr,
+    ) -> u32 {
+        preferences.priority(
+            CandidateType::PeerReflexive,
+            Transport::Udp,
+            preferences.local_preference(base),
+            component_id,
+        )
+    }
+
+    // The candidate as it's signalled to the remote agent. Addresses in
+    // hidden are those of host candidates with mDNS names, which mustn't
+    // be given away as the related address of other candidates.
+    pub(crate) fn to_candidate(&self, hidden: &[SocketAddr]) -> Candidate {
+        let connection_address = match &self.hostname {
+            Some(hostname) => ConnectionAddress::Hostname(hostname.clone()),
+            None => ConnectionAddress::Ip(self.address.ip()),
+        };
+        let related_address = self.related_address.map(|related_address| {
+            if hidden.contains(&related_address) {
+                hidden_address(related_address)
+            } else {
+                related_address
+            }
+        });
+
+        Candidate {
+            foundation: self.foundation.clone(),
+            component_id: self.component_id,
+            transport: self.transport,
+            priority: self.priority,
+            connection_address,
+            port: self.address.port(),
+            ty: self.ty,
+            related_address: related_address.map(|address| ConnectionAddress::Ip(address.ip())),
+            related_port: related_address.map(|address| address.port()),
+            tcp_type: self.tcp_type,
+            extensions: vec![],
+        }
+    }
+}
+
+// A candidate as it appears in an a=candidate attribute, whether it's
+// one of ours or one of the remote agent's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub foundation: String,
+    pub component_id: u16,
+    pub transport: Transport,
+    pub priority: u32,
+    pub connection_address: ConnectionAddress,
+    pub port: u16,
+    pub ty: CandidateType,
+    pub related_address: Option<ConnectionAddress>,
+    pub related_port: Option<u16>,
+    pub tcp_type: Option<TcpType>,
+    pub extensions: Vec<(String, String)>,
+}
+
+type CandidateArgs = (
+    Foundation,
+    ComponentId,
+    Transport,
+    Priority,
+    (ConnectionAddress, Port),
+    CandidateType,
+    Option<ConnectionAddress>,
+    Option<Port>,
+    Option<TcpType>,
+    Vec<(String, String)>,
+);
+
+impl Candidate {
+    fn from_tuple(args: CandidateArgs) -> Self {
+        Self {
+            foundation: (args.0).0,
+            component_id: (args.1).0,
+            transport: args.2,
+            priority: (args.3).0,
+            connection_address: (args.4).0,
+            port: (args.4).1,
+            ty: args.5,
+            related_address: args.6,
+            related_port: args.7,
+            tcp_type: args.8,
+            extensions: args.9,
+        }
+    }
+
+    // None until a hostname has been resolved.
+    pub fn address(&self) -> Option<SocketAddr> {
+        match self.connection_address {
+            ConnectionAddress::Ip(ip) => Some(SocketAddr::new(ip, self.port)),
+            ConnectionAddress::Hostname(_) => None,
+        }
+    }
+
+    // Ignores the scope of link-local addresses, which is never signalled.
+    pub(crate) fn has_address(&self, address: SocketAddr) -> bool {
+        self.connection_address == ConnectionAddress::Ip(address.ip())
+            && self.port == address.port()
+    }
+
+    pub(crate) fn peer_reflexive(
+        foundation: String,
+        component_id: u16,
+        priority: u32,
+        address: SocketAddr,
+    ) -> Self {
+        Self {
+            foundation,
+            component_id,
+            transport: Transport::Udp,
+            priority,
+            connection_address: ConnectionAddress::Ip(address.ip()),
+            port: address.port(),
+            ty: CandidateType::PeerReflexive,
+            related_address: None,
+            related_port: None,
+            tcp_type: None,
+            extensions: vec![],
+        }
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} typ {}",
+            self.foundation,
+            self.component_id,
+            self.transport,
+            self.priority,
+            self.connection_address,
+            self.port,
+            self.ty,
+        )?;
+        if let Some(related_address) = &self.related_address {
+            write!(f, " raddr {}", related_address)?;
+        }
+        if let Some(related_port) = self.related_port {
+            write!(f, " rport {}", related_port)?;
+        }
+        if let Some(tcp_type) = self.tcp_type {
+            write!(f, " tcptype {}", tcp_type)?;
+        }
+        for (name, value) in &self.extensions {
+            write!(f, " {} {}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+//   candidate-attribute   = "candidate" ":" foundation SP component-id SP
+//                           transport SP
+//                           priority SP
+//                           connection-address SP     ;from RFC 4566
+//                           port         ;port from RFC 4566
+//                           SP cand-type
+//                           [SP rel-addr]
+//                           [SP rel-port]
+//                           [SP tcp-type-ext]
+//                           *(SP extension-att-name SP
+//                                extension-att-value)
+//
+//   foundation            = 1*32ice-char
+//   component-id          = 1*5DIGIT
+//   transport             = "UDP" / transport-extension
+//   transport-extension   = token              ; from RFC 3261
+//   priority              = 1*10DIGIT
+//   cand-type             = "typ" SP candidate-types
+//   candidate-types       = "host" / "srflx" / "prflx" / "relay" / token
+//   rel-addr              = "raddr" SP connection-address
+//   rel-port              = "rport" SP port
+//   extension-att-name    = byte-string    ;from RFC 4566
+//   extension-att-value   = byte-string
+//   ice-char              = ALPHA / DIGIT / "+" / "/"
+//
+// https://tools.ietf.org/html/rfc5245#section-15.1
+fn candidate(input: Span) -> IResult<Span, Candidate> {
+    map(
+        tuple((
+            foundation,
+            component_id,
+            transport,
+            priority,
+            connection_address_and_port,
+            candidate_type,
+            opt(related_address),
+            opt(related_port),
+            opt(tcp_type),
+            many0(extension_attribute),
+        )),
+        Candidate::from_tuple,
+    )(input)
+}
+
+impl FromStr for Candidate {
+    type Err = Error;
+
+    #[throws(Error)]
+    fn from_str(s: &str) -> Self {
+        let input = Span::new(s);
+        let (_, candidate) = all_consuming(candidate)(input)
+            .map_err(|err| Error::InvalidCandidate(err.to_string()))?;
+
+        candidate
+    }
+}
+
+fn candidate_attribute(input: Span) -> IResult<Span, Candidate> {
+    delimited(tag("a=candidate:"), candidate, crlf)(input)
+}
+
+impl TryFrom<sdp::Attribute> for Candidate {
+    type Error = Error;
+
+    #[throws(Error)]
+    fn try_from(attribute: sdp::Attribute) -> Self {
+        let attr_string = attribute.to_string();
+        let input = Span::new(&attr_string);
+        let (_, candidate) = all_consuming(candidate_attribute)(input)
+            .map_err(|err| Error::InvalidCandidate(err.to_string()))?;
+
+        candidate
+    }
+}
+
+impl From<Candidate> for sdp::Attribute {
+    fn from(candidate: Candidate) -> Self {
+        sdp::Attribute::value("candidate", &candidate.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[test]
+    #[throws]
+    fn remote_candidate_from_attribute() {
+        let candidate_attribute = sdp::Attribute::Value("candidate".to_string(), "1853887674 2 udp 1518280447 47.61.61.61 36768 typ srflx raddr 192.168.0.196 rport 36768 generation 0".to_string());
+        let _candidate: Candidate = candidate_attribute.try_into()?;
+    }
+
+    #[test]
+    #[throws]
+    fn candidates_round_trip() {
+        let candidate_strings = [
+            "1853887674 2 udp 1518280447 47.61.61.61 36768 typ srflx raddr 192.168.0.196 rport 36768 generation 0",
+            "1 1 udp 2122260223 2001:db8::1 50000 typ host",
+            "2 1 tcp 1518280447 abcd-1234.local 9 typ host tcptype active generation 0 network-id 1",
+            "3 1 udp 25108223 198.51.100.1 60000 typ relay raddr 0.0.0.0 rport 0",
+        ];
+
+        for candidate_string in &candidate_strings {
+            let candidate: Candidate = candidate_string.parse()?;
+            assert_eq!(candidate.to_string(), *candidate_string);
+        }
+    }
+
+    #[test]
+    #[throws]
+    fn candidate_fields_are_kept() {
+        let candidate: Candidate = "1853887674 2 UDP 1518280447 47.61.61.61 36768 typ srflx raddr 192.168.0.196 rport 36768 generation 0".parse()?;
+        assert_eq!(
+            candidate,
+            Candidate {
+                foundation: "1853887674".to_string(),
+                component_id: 2,
+                transport: Transport::Udp,
+                priority: 1518280447,
+                connection_address: ConnectionAddress::Ip(IpAddr::from([47, 61, 61, 61])),
+                port: 36768,
+                ty: CandidateType::ServerReflexive,
+                related_address: Some(ConnectionAddress::Ip(IpAddr::from([192, 168, 0, 196]))),
+                related_port: Some(36768),
+                tcp_type: None,
+                extensions: vec![("generation".to_string(), "0".to_string())],
+            }
+        );
+    }
+
+    #[test]
+    #[throws]
+    fn remote_candidate_from_attribute_with_caps_transport() {
+        let candidate_attribute = sdp::Attribute::Value(
+            "candidate".to_string(),
+            "3 2 UDP 1686052862 47.61.61.61 64346 typ srflx raddr 192.168.0.196 rport 64346"
+                .to_string(),
+        );
+        let _candidate: Candidate = candidate_attribute.try_into()?;
+    }
+
+    #[test]
+    #[throws]
+    fn remote_candidate_from_str() {
+        let candidate_string = "4 2 TCP 2105458942 10.10.10.10 9 typ host tcptype active";
+        let candidate: Candidate = candidate_string.parse()?;
+        assert_eq!(candidate.transport, Transport::Tcp);
+        assert_eq!(candidate.tcp_type, Some(TcpType::Active));
+    }
+
+    #[test]
+    fn remote_candidate_with_tcp_type_and_extensions() {
+        let candidate: Candidate =
+            "1 1 tcp 1518280447 192.0.2.1 50000 typ host tcptype so generation 0"
+                .parse()
+                .unwrap();
+        assert_eq!(candidate.tcp_type, Some(TcpType::SimultaneousOpen));
+
+        let result: Result<Candidate, _> =
+            "1 1 tcp 1518280447 192.0.2.1 50000 typ host tcptype sideways".parse();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[throws]
+    fn tcp_candidates_round_trip() {
+        let preferences = Preferences::default();
+        let address = "192.0.2.1:9".parse().unwrap();
+        let active = LocalCandidate::tcp_host(&preferences, 1, address, TcpType::Active);
+        let passive = LocalCandidate::tcp_host(&preferences, 1, address, TcpType::Passive);
+        assert!(active.priority > passive.priority);
+
+        let udp = LocalCandidate::host(&preferences, 1, address);
+        assert!(udp.priority > active.priority);
+
+        let attribute = sdp::Attribute::from(active.to_candidate(&[]));
+        assert_eq!(
+            attribute,
+            sdp::Attribute::value(
+                "candidate",
+                &format!(
+                    "{} 1 tcp 1523711999 192.0.2.1 9 typ host tcptype active",
+                    active.foundation
+                )
+            )
+        );
+
+        let candidate: Candidate = attribute.try_into()?;
+        assert_eq!(candidate.transport, Transport::Tcp);
+        assert_eq!(candidate.tcp_type, Some(TcpType::Active));
+        assert_eq!(candidate.address(), Some(address));
+    }
+
+    #[test]
+    #[throws]
+    fn remote_candidate_with_hostname() {
+        let candidate: Candidate =
+            "1 1 udp 2122260223 2f3b84a9-7f4f-4a6c-9c2d-5e4dc1a4b2f0.local 54321 typ host"
+                .parse()?;
+        assert_eq!(
+            candidate.connection_address,
+            ConnectionAddress::Hostname("2f3b84a9-7f4f-4a6c-9c2d-5e4dc1a4b2f0.local".to_string())
+        );
+        assert_eq!(candidate.address(), None);
+
+        let candidate: Candidate =
+            "2 1 udp 1686052607 198.51.100.1 54321 typ srflx raddr abcd.local rport 9".parse()?;
+        assert_eq!(
+            candidate.address(),
+            Some("198.51.100.1:54321".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn precedence_follows_address_selection() {
+        let precedence = |address: &str| ip_precedence(address.parse().unwrap());
+
+        assert_eq!(precedence("::1"), 50);
+        assert_eq!(precedence("2600::1"), 40);
+        assert_eq!(precedence("192.0.2.1"), 35);
+        assert_eq!(precedence("2002::1"), 30);
+        assert_eq!(precedence("2001::1"), 5);
+        assert_eq!(precedence("fd00::1"), 3);
+        assert_eq!(precedence("fec0::1"), 1);
+
+        let preferences = Preferences::default();
+        let ipv6 = LocalCandidate::host(&preferences, 1, "[2600::1]:5000".parse().unwrap());
+        let ipv4 = LocalCandidate::host(&preferences, 1, "192.0.2.1:5000".parse().unwrap());
+        assert!(ipv6.priority > ipv4.priority);
+    }
+
+    #[test]
+    fn hidden_related_addresses() {
+        let base = "192.168.0.2:5000".parse().unwrap();
+        let mapped = "192.0.2.1:6000".parse().unwrap();
+        let server = IpAddr::from([198, 51, 100, 1]);
+        let candidate =
+            LocalCandidate::server_reflexive(&Preferences::default(), 1, mapped, base, server);
+
+        let attribute = sdp::Attribute::from(candidate.to_candidate(&[base]));
+        assert_eq!(
+            attribute,
+            sdp::Attribute::value(
+                "candidate",
+                &format!(
+                    "{} 1 udp 1687158783 192.0.2.1 6000 typ srflx raddr 0.0.0.0 rport 0",
+                    candidate.foundation
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn priorities_follow_preferences() {
+        let base = "192.0.2.1:5000".parse().unwrap();
+        let mapped = "198.51.100.2:6000".parse().unwrap();
+        let server = IpAddr::from([198, 51, 100, 1]);
+
+        let preferences = Preferences::default();
+        let host = LocalCandidate::host(&preferences, 1, base);
+        let rtcp = LocalCandidate::host(&preferences, 2, base);
+        let prflx = LocalCandidate::peer_reflexive(&preferences, 1, mapped, base);
+        let srflx = LocalCandidate::server_reflexive(&preferences, 1, mapped, base, server);
+        let relayed = LocalCandidate::relayed(&preferences, 1, mapped, mapped, base, server);
+        assert_eq!(host.priority, 2_123_366_399);
+        assert_eq!(rtcp.priority, 2_123_366_398);
+        assert_eq!(prflx.priority, 1_854_930_943);
+        assert_eq!(srflx.priority, 1_687_158_783);
+        assert_eq!(relayed.priority, 9_437_183);
+
+        let preferences = Preferences {
+            host: 10,
+            relayed: 200,
+            local: HashMap::from_iter(vec![(base.ip(), 1)]),
+            ..Preferences::default()
+        };
+        let host = LocalCandidate::host(&preferences, 1, base);
+        let relayed = LocalCandidate::relayed(&preferences, 1, mapped, mapped, base, server);
+        assert_eq!(host.priority, (10 << 24) + (1 << 8) + 255);
+        assert_eq!(relayed.priority >> 24, 126);
+    }
+
+    #[test]
+    fn foundations_follow_type_base_server_and_transport() {
+        let preferences = Preferences::default();
+        let base = "192.0.2.1:5000".parse().unwrap();
+        let other_port = "192.0.2.1:5001".parse().unwrap();
+        let other_ip = "192.0.2.2:5000".parse().unwrap();
+        let mapped = "198.51.100.2:6000".parse().unwrap();
+        let server = IpAddr::from([198, 51, 100, 1]);
+        let other_server = IpAddr::from([198, 51, 100, 3]);
+
+        let host = LocalCandidate::host(&preferences, 1, base);
+        assert_eq!(
+            host.foundation,
+            LocalCandidate::host(&preferences, 2, other_port).foundation
+        );
+        assert_ne!(
+            host.foundation,
+            LocalCandidate::host(&preferences, 1, other_ip).foundation
+        );
+        assert_ne!(
+            host.foundation,
+            LocalCandidate::tcp_host(&preferences, 1, base, TcpType::Passive).foundation
+        );
+
+        let srflx = LocalCandidate::server_reflexive(&preferences, 1, mapped, base, server);
+        assert_ne!(host.foundation, srflx.foundation);
+        assert_ne!(
+            srflx.foundation,
+            LocalCandidate::server_reflexive(&preferences, 1, mapped, base, other_server)
+                .foundation
+        );
+
+        let relayed = LocalCandidate::relayed(&preferences, 1, mapped, mapped, base, server);
+        assert_ne!(srflx.foundation, relayed.foundation);
+        assert_eq!(
+            relayed.foundation,
+            LocalCandidate::relayed(&preferences, 2, mapped, mapped, other_port, server).foundation
+        );
+    }
+}
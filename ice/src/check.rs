@@ -0,0 +1,720 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, trace, warn};
+use tokio::{task, time};
+
+use crate::{
+    candidate::LocalCandidate,
+    checklist::{CandidatePair, Check, ChecklistState, PairState},
+    rand_ice_string,
+    relay::Allocation,
+    role::{resolve_conflict, Resolution, Role},
+    tcp::connect,
+    xor_mapped_address, Candidate, ConnectionState, NominationMode, State, TcpType, Transport,
+};
+
+// https://tools.ietf.org/html/rfc8445#section-14.2
+pub(crate) const TA: Duration = Duration::from_millis(50);
+
+// How a check gets to the remote side of its pair.
+#[derive(Debug)]
+enum Route {
+    Client(stun::Client<dyn stun::Transport>),
+    // active and simultaneous-open TCP candidates have to connect first
+    Connect(TcpType),
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingCheck {
+    // the generation whose checklist index is in
+    generation: u32,
+    index: usize,
+    nominate: bool,
+    role: Role,
+    route: Route,
+    allocation: Option<Arc<Allocation>>,
+    request: stun::Message,
+    local: SocketAddr,
+    remote: SocketAddr,
+}
+
+// Sends one check every Ta until a pair has been selected, or every pair
+// has failed and no more candidates are on their way, or ICE restarts.
+// With renomination, the selected pair can still change afterwards, so
+// triggered checks carry on being sent until ICE restarts.
+//
+// https://tools.ietf.org/html/rfc8445#section-6.1.4.2
+pub(crate) async fn run_checks(state: Arc<Mutex<State>>) {
+    let generation = state.lock().unwrap().generation;
+
+    let mut completed = false;
+    let mut ta = time::interval(TA);
+    loop {
+        ta.tick().await;
+
+        let maybe_check = {
+            let mut state = state.lock().unwrap();
+            if state.generation != generation {
+                debug!("Abandoning checks for generation {}", generation);
+                break;
+            }
+
+            let checklist_state = state.checklist.state();
+            let finished = match checklist_state {
+                ChecklistState::Running => false,
+                ChecklistState::Completed => true,
+                ChecklistState::Failed => !state.trickling(),
+            };
+            if finished && !completed {
+                debug!("Connectivity checks finished: {:?}", checklist_state);
+                match checklist_state {
+                    ChecklistState::Completed => {
+                        state.set_connection_state(ConnectionState::Completed)
+                    }
+                    _ => state.set_connection_state(ConnectionState::Failed),
+                }
+
+                let renomination = state.renomination && state.remote_renomination;
+                if checklist_state != ChecklistState::Completed || !renomination {
+                    break;
+                }
+                completed = true;
+            }
+
+            next_check(&mut state)
+        };
+
+        if let Some(check) = maybe_check {
+            task::spawn(perform_check(Arc::clone(&state), check));
+        }
+    }
+}
+
+fn next_check(state: &mut State) -> Option<PendingCheck> {
+    let Check { index, nominate } = state.checklist.next(state.role)?;
+
+    pending_check(state, index, nominate)
+}
+
+// A check that nominates a pair outside of the usual checks, for
+// switching the selected pair once they've finished.
+pub(crate) fn nomination_check(state: &mut State, index: usize) -> Option<PendingCheck> {
+    pending_check(state, index, true)
+}
+
+fn pending_check(state: &mut State, index: usize, nominate: bool) -> Option<PendingCheck> {
+    let remote_credentials = state.remote_credentials.clone()?;
+    let role = state.role;
+    let pair = state.checklist.pair(index).clone();
+
+    let maybe_client = match pair.transport {
+        Transport::Udp => state.clients.get(&pair.local).cloned(),
+        Transport::Tcp => state.connections.get(&(pair.local, pair.remote)).cloned(),
+    };
+    let route = match (maybe_client, state.tcp_type(pair.local)) {
+        (Some(client), _) => Route::Client(client),
+        (None, Some(tcp_type)) if pair.transport == Transport::Tcp => {
+            if tcp_type == TcpType::Passive {
+                warn!("No connection from {} to {}", pair.remote, pair.local);
+                state.checklist.failed(index);
+
+                return None;
+            }
+
+            Route::Connect(tcp_type)
+        }
+        (None, _) => {
+            warn!("No socket bound to {}", pair.local);
+            state.checklist.failed(index);
+
+            return None;
+        }
+    };
+    let allocation = state.allocation(pair.local);
+
+    let username = format!(
+        "{}:{}",
+        remote_credentials.ufrag, state.local_credentials.ufrag
+    );
+    let mut attributes = vec![
+        stun::Attribute::username(&username),
+        stun::Attribute::priority(LocalCandidate::peer_reflexive_priority(
+            &state.preferences,
+            pair.component_id,
+            pair.local,
+        )),
+        role.attribute(state.tie_breaker),
+    ];
+    let aggressive =
+        role == Role::Controlling && state.nomination_mode == NominationMode::Aggressive;
+    if nominate || aggressive {
+        attributes.push(stun::Attribute::use_candidate());
+    }
+
+    // Only the nominations we make on purpose are numbered, so that the
+    // remote agent takes the latest of them over whichever pair it had.
+    //
+    // https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+    if nominate && role == Role::Controlling && state.renomination && state.remote_renomination {
+        state.nomination += 1;
+        attributes.push(stun::Attribute::nomination(state.nomination));
+        state
+            .nominations
+            .insert(pair.component_id, (state.nomination, index));
+    }
+
+    let request = stun::Message::base(stun::Header::new(
+        stun::Method::Binding,
+        stun::Class::Request,
+        stun::TransactionId::new(),
+    ))
+    .with_attributes(attributes)
+    .with_message_integrity(remote_credentials.pwd.as_bytes())
+    .with_fingerprint();
+
+    trace!("Checking {} -> {}: {:?}", pair.local, pair.remote, request);
+
+    Some(PendingCheck {
+        generation: state.generation,
+        index,
+        nominate: nominate || aggressive,
+        role,
+        route,
+        allocation,
+        request,
+        local: pair.local,
+        remote: pair.remote,
+    })
+}
+
+pub(crate) async fn perform_check(state: Arc<Mutex<State>>, check: PendingCheck) {
+    // the TURN server only relays to peers we've installed a permission for
+    //
+    // https://tools.ietf.org/html/rfc8656#section-9
+    if let Some(allocation) = &check.allocation {
+        let peer = check.remote.ip();
+        if !allocation.has_permission(peer) {
+            if let Err(err) = allocation.create_permission(&[peer]).await {
+                warn!("Failed to install permission for {}: {}", peer, err);
+            }
+        }
+    }
+
+    let client = match &check.route {
+        Route::Client(client) => client.clone(),
+        Route::Connect(tcp_type) => {
+            match connect(&state, check.local, *tcp_type, check.remote).await {
+                Ok(client) => client,
+                Err(err) => {
+                    let result = Err(stun::Error::Transport(err.kind()));
+                    handle_check_result(&mut state.lock().unwrap(), check, result);
+
+                    return;
+                }
+            }
+        }
+    };
+
+    let result = client.request(&check.request, check.remote).await;
+
+    let mut state = state.lock().unwrap();
+    handle_check_result(&mut state, check, result);
+}
+
+// https://tools.ietf.org/html/rfc8445#section-7.2.5
+fn handle_check_result(
+    state: &mut State,
+    check: PendingCheck,
+    result: Result<stun::Message, stun::Error>,
+) {
+    // A restart replaces the checklist while checks are still in flight,
+    // and the results of those have no pair left to update.
+    let index = check.index;
+    let pair = match state.checklist.get(index) {
+        Some(pair)
+            if state.generation == check.generation
+                && (pair.local, pair.remote) == (check.local, check.remote) =>
+        {
+            pair.clone()
+        }
+        _ => {
+            debug!(
+                "Dropping result of check {} -> {} from generation {}",
+                check.local, check.remote, check.generation
+            );
+            return;
+        }
+    };
+
+    let response = match result {
+        Ok(response) => response,
+        Err(err) => {
+            debug!("Check {} -> {} failed: {}", pair.local, pair.remote, err);
+            state.checklist.failed(index);
+            state.checklist.nominate_best(state.role);
+
+            return;
+        }
+    };
+
+    let key = match &state.remote_credentials {
+        Some(remote_credentials) => remote_credentials.pwd.clone(),
+        None => return,
+    };
+    if let Err(err) = response.verify_integrity(key.as_bytes()) {
+        warn!("Dropping response from {}: {}", pair.remote, err);
+        state.checklist.failed(index);
+
+        return;
+    }
+
+    match response.header.class {
+        stun::Class::Error => {
+            let role_conflict = response.attributes.iter().any(|attribute| match attribute {
+                stun::Attribute::ErrorCode(error_code) => {
+                    error_code.numeric_code() == stun::NumericCode::RoleConflict
+                }
+                _ => false,
+            });
+
+            if role_conflict {
+                // https://tools.ietf.org/html/rfc8445#section-7.2.5.1
+                state.role = check.role.reversed();
+                debug!("Switched role to {:?} after 487 response", state.role);
+
+                state.checklist.trigger(Check {
+                    index,
+                    nominate: false,
+                });
+            } else {
+                state.checklist.failed(index);
+            }
+        }
+        stun::Class::Success => {
+            // Active TCP candidates connect from a new port every time, so
+            // only UDP checks discover peer reflexive candidates.
+            //
+            // https://tools.ietf.org/html/rfc8445#section-7.2.5.3.1
+            let maybe_mapped = match pair.transport {
+                Transport::Udp => xor_mapped_address(&response),
+                Transport::Tcp => None,
+            };
+            if let Some(mapped) = maybe_mapped {
+                if !state.local_candidates.iter().any(|c| c.address == mapped) {
+                    debug!("Discovered peer reflexive candidate {}", mapped);
+
+                    let candidate = LocalCandidate::peer_reflexive(
+                        &state.preferences,
+                        pair.component_id,
+                        mapped,
+                        pair.local,
+                    );
+                    state.local_candidates.push(candidate);
+                }
+            }
+
+            state.checklist.succeeded(index);
+            debug!("Check {} -> {} succeeded", pair.local, pair.remote);
+
+            let nominated = match state.role {
+                Role::Controlling => check.nominate,
+                Role::Controlled => pair.nominate_on_success,
+            };
+            if nominated {
+                state.nominate(index);
+                debug!("Selected pair {} -> {}", pair.local, pair.remote);
+            }
+        }
+        _ => state.checklist.failed(index),
+    }
+
+    if state.role == Role::Controlling {
+        state.checklist.nominate_best(state.role);
+    }
+}
+
+struct Authenticated {
+    username: String,
+    // the password the request was signed with, for signing the reply
+    key: String,
+    // false for credentials from before an ICE restart
+    current: bool,
+}
+
+// Checks the USERNAME and MESSAGE-INTEGRITY of a request against our
+// credentials, or those from before an ICE restart. Requests that fail
+// are either rejected with the returned reply or dropped.
+fn authenticate(
+    state: &State,
+    source: SocketAddr,
+    request: &stun::Message,
+) -> Result<Authenticated, Option<stun::Message>> {
+    // https://tools.ietf.org/html/rfc5389#section-10.1.2
+    let maybe_username = request
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            stun::Attribute::Username(username) => Some(username.as_str().to_string()),
+            _ => None,
+        });
+    let has_message_integrity = request
+        .attributes
+        .iter()
+        .any(|attribute| matches!(attribute, stun::Attribute::MessageIntegrity(_)));
+    let username = match maybe_username {
+        Some(username) if has_message_integrity => username,
+        _ => {
+            debug!("Rejecting unauthenticated check from {}", source);
+            let reply = error_response(
+                &request.header,
+                stun::NumericCode::BadRequest,
+                "Bad Request",
+            )
+            .with_fingerprint();
+
+            return Err(Some(reply));
+        }
+    };
+
+    let (key, current) = if state.accepts_username(&username) {
+        (state.local_credentials.pwd.clone(), true)
+    } else if let Some(key) = state.previous_password(&username) {
+        (key, false)
+    } else {
+        warn!(
+            "Dropping connectivity check from {} for {}",
+            source, username
+        );
+        return Err(None);
+    };
+
+    if let Err(err) = request.verify_integrity(key.as_bytes()) {
+        warn!("Dropping connectivity check from {}: {}", source, err);
+        return Err(None);
+    }
+
+    Ok(Authenticated {
+        username,
+        key,
+        current,
+    })
+}
+
+// Answers an inbound Binding request, scheduling a triggered check back
+// to its source. Returns None if the request should be dropped.
+//
+// https://tools.ietf.org/html/rfc8445#section-7.3
+pub(crate) fn handle_request(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    request: &stun::Message,
+) -> Option<stun::Message> {
+    if let Err(err) = request.verify_fingerprint() {
+        warn!("Dropping connectivity check from {}: {}", source, err);
+        return None;
+    }
+
+    let Authenticated {
+        username,
+        key,
+        current,
+    } = match authenticate(state, source, request) {
+        Ok(authenticated) => authenticated,
+        Err(reply) => return reply,
+    };
+
+    // Checks with the credentials from before an ICE restart are only
+    // answered, so that consent on the old pairs is kept.
+    if !current {
+        return Some(success_response(request, &username, source, &key));
+    }
+
+    // Lite agents are always controlled, and never send checks of their
+    // own, so only full agents need to repair role conflicts and schedule
+    // triggered checks.
+    if state.lite {
+        lite_check(state, transport, base, source, &request.attributes);
+    } else {
+        match resolve_conflict(state.role, state.tie_breaker, &request.attributes) {
+            Resolution::NoConflict => {}
+            Resolution::SwitchRole => {
+                state.role = state.role.reversed();
+                debug!(
+                    "Switched role to {:?} after conflict with {}",
+                    state.role, source
+                );
+            }
+            Resolution::RoleConflictError => {
+                let reply = error_response(
+                    &request.header,
+                    stun::NumericCode::RoleConflict,
+                    "Role Conflict",
+                )
+                .with_message_integrity(key.as_bytes())
+                .with_fingerprint();
+
+                return Some(reply);
+            }
+        }
+
+        triggered_check(state, transport, base, source, &request.attributes);
+    }
+
+    Some(success_response(request, &username, source, &key))
+}
+
+fn success_response(
+    request: &stun::Message,
+    username: &str,
+    source: SocketAddr,
+    key: &str,
+) -> stun::Message {
+    stun::Message::base(stun::Header::new(
+        stun::Method::Binding,
+        stun::Class::Success,
+        request.header.transaction_id,
+    ))
+    .with_attributes(vec![
+        stun::Attribute::username(username),
+        stun::Attribute::xor_mapped_address(source.ip(), source.port()),
+    ])
+    .with_message_integrity(key.as_bytes())
+    .with_fingerprint()
+}
+
+// An error response to request, ready for any MESSAGE-INTEGRITY and
+// FINGERPRINT to be added.
+pub(crate) fn error_response(
+    request: &stun::Header,
+    numeric_code: stun::NumericCode,
+    reason_phrase: &str,
+) -> stun::Message {
+    stun::Message::base(stun::Header::new(
+        request.method,
+        stun::Class::Error,
+        request.transaction_id,
+    ))
+    .with_attributes(vec![stun::Attribute::error_code(
+        numeric_code,
+        reason_phrase,
+    )])
+}
+
+fn use_candidate(attributes: &[stun::Attribute]) -> bool {
+    attributes
+        .iter()
+        .any(|attribute| matches!(attribute, stun::Attribute::UseCandidate(_)))
+}
+
+// With renomination, the controlling agent numbers its nominations, and
+// the latest one for a component is selected whatever its priority.
+// Returns false for a nomination that's older than one we've already had.
+//
+// https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+fn accepts_nomination(state: &mut State, index: usize, attributes: &[stun::Attribute]) -> bool {
+    let maybe_nomination = attributes.iter().find_map(|attribute| match attribute {
+        stun::Attribute::Nomination(nomination) => Some(nomination.as_u32()),
+        _ => None,
+    });
+    let nomination = match maybe_nomination {
+        Some(nomination) if state.renomination => nomination,
+        _ => return true,
+    };
+
+    let component_id = state.checklist.pair(index).component_id;
+    if let Some((latest, _)) = state.nominations.get(&component_id) {
+        if nomination < *latest {
+            debug!("Ignoring stale nomination {}", nomination);
+            return false;
+        }
+    }
+
+    state.nominations.insert(component_id, (nomination, index));
+    true
+}
+
+// The pair a check came in on, which is added to the checklist if it
+// isn't already there, along with any peer reflexive candidate its
+// source is.
+//
+// https://tools.ietf.org/html/rfc8445#section-7.3.1.3
+fn inbound_pair(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    attributes: &[stun::Attribute],
+) -> Option<usize> {
+    let local = state
+        .local_candidates
+        .iter()
+        .find(|c| c.transport == transport && c.address == base)?
+        .clone();
+
+    let remote = match state
+        .remote_candidates
+        .iter()
+        .find(|c| c.transport == transport && c.has_address(source))
+    {
+        Some(remote) => remote.clone(),
+        None => {
+            let priority = attributes.iter().find_map(|attribute| match attribute {
+                stun::Attribute::Priority(priority) => Some(priority.as_u32()),
+                _ => None,
+            })?;
+
+            debug!("Learned peer reflexive candidate {}", source);
+
+            // Checks over TCP are answered over the connection they came
+            // in on, so the remote candidate takes the opposite type to
+            // the local one.
+            //
+            // https://tools.ietf.org/html/rfc6544#section-7.2
+            let mut remote =
+                Candidate::peer_reflexive(rand_ice_string(8), local.component_id, priority, source);
+            remote.transport = transport;
+            remote.tcp_type = local.tcp_type.map(TcpType::reversed);
+            state.remote_candidates.push(remote.clone());
+
+            remote
+        }
+    };
+
+    let index = match state.checklist.find(transport, base, source) {
+        Some(index) => index,
+        None => {
+            let pair = CandidatePair::new(
+                format!("{}:{}", local.foundation, remote.foundation),
+                local.component_id,
+                base,
+                local.priority,
+                source,
+                remote.priority,
+            )
+            .with_transport(transport);
+
+            state.checklist.add(pair, state.role)
+        }
+    };
+
+    Some(index)
+}
+
+// https://tools.ietf.org/html/rfc8445#section-7.3.1.4
+fn triggered_check(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    attributes: &[stun::Attribute],
+) {
+    let index = match inbound_pair(state, transport, base, source, attributes) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let pair_state = state.checklist.pair(index).state;
+    if pair_state != PairState::Succeeded && pair_state != PairState::InProgress {
+        state.checklist.trigger(Check {
+            index,
+            nominate: false,
+        });
+    }
+
+    // https://tools.ietf.org/html/rfc8445#section-7.3.1.5
+    let nominated = use_candidate(attributes) && state.role == Role::Controlled;
+    if nominated && accepts_nomination(state, index, attributes) {
+        if pair_state == PairState::Succeeded {
+            state.nominate(index);
+            debug!("Selected pair {} -> {}", base, source);
+        } else {
+            state.checklist.set_nominate_on_success(index);
+        }
+    }
+}
+
+// Lite agents never check pairs themselves, so a check from the
+// controlling agent is all it takes for its pair to be valid, and the
+// highest priority pair it nominates for each component is selected.
+//
+// https://tools.ietf.org/html/rfc8445#section-7.3.1.5
+fn lite_check(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    attributes: &[stun::Attribute],
+) {
+    if state.connection_state == ConnectionState::New {
+        state.set_connection_state(ConnectionState::Checking);
+    }
+
+    if !use_candidate(attributes) {
+        return;
+    }
+
+    let index = match inbound_pair(state, transport, base, source, attributes) {
+        Some(index) => index,
+        None => return,
+    };
+    if !accepts_nomination(state, index, attributes) {
+        return;
+    }
+
+    state.checklist.succeeded(index);
+    state.nominate(index);
+    debug!("Nominated pair {} -> {}", base, source);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_flight_check(state: &State, index: usize) -> PendingCheck {
+        let pair = state.checklist.pair(index);
+
+        PendingCheck {
+            generation: state.generation,
+            index,
+            nominate: false,
+            role: state.role,
+            route: Route::Connect(TcpType::Active),
+            allocation: None,
+            request: stun::Message::base(stun::Header::new(
+                stun::Method::Binding,
+                stun::Class::Request,
+                stun::TransactionId::new(),
+            )),
+            local: pair.local,
+            remote: pair.remote,
+        }
+    }
+
+    #[test]
+    fn results_from_before_a_restart_are_dropped() {
+        let mut state = State::default();
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let remote = "127.0.0.1:6000".parse().unwrap();
+        let pair = CandidatePair::new("1:1".to_string(), 1, local, 100, remote, 100);
+        let index = state.checklist.add(pair.clone(), state.role);
+        let first = in_flight_check(&state, index);
+        let second = in_flight_check(&state, index);
+
+        state.restart();
+
+        // the new checklist has no pair at the old index
+        handle_check_result(&mut state, first, Err(stun::Error::TransactionTimedOut));
+
+        // or one that only looks like the same pair
+        let index = state.checklist.add(pair, state.role);
+        let pair_state = state.checklist.pair(index).state;
+        handle_check_result(&mut state, second, Err(stun::Error::TransactionTimedOut));
+        assert_eq!(state.checklist.pair(index).state, pair_state);
+    }
+}
@@ -0,0 +1,587 @@
+use std::{collections::VecDeque, net::SocketAddr};
+
+use crate::{role::Role, Transport};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum PairState {
+    Frozen,
+    Waiting,
+    InProgress,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum ChecklistState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CandidatePair {
+    pub(crate) foundation: String,
+    pub(crate) component_id: u16,
+    pub(crate) transport: Transport,
+    // The local side of a pair is always a base, since that's where
+    // checks are sent from.
+    pub(crate) local: SocketAddr,
+    pub(crate) remote: SocketAddr,
+    pub(crate) local_priority: u32,
+    pub(crate) remote_priority: u32,
+    pub(crate) state: PairState,
+    pub(crate) valid: bool,
+    // Set when the controlling agent has asked us to nominate this pair
+    // before our own check on it has succeeded.
+    pub(crate) nominate_on_success: bool,
+}
+
+impl CandidatePair {
+    pub(crate) fn new(
+        foundation: String,
+        component_id: u16,
+        local: SocketAddr,
+        local_priority: u32,
+        remote: SocketAddr,
+        remote_priority: u32,
+    ) -> Self {
+        Self {
+            foundation,
+            component_id,
+            transport: Transport::Udp,
+            local,
+            remote,
+            local_priority,
+            remote_priority,
+            state: PairState::Frozen,
+            valid: false,
+            nominate_on_success: false,
+        }
+    }
+
+    pub(crate) fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub(crate) fn priority(&self, role: Role) -> u64 {
+        let (g, d) = match role {
+            Role::Controlling => (self.local_priority, self.remote_priority),
+            Role::Controlled => (self.remote_priority, self.local_priority),
+        };
+
+        pair_priority(g, d)
+    }
+}
+
+// pair priority = 2^32*MIN(G,D) + 2*MAX(G,D) + (G>D?1:0)
+//
+// https://tools.ietf.org/html/rfc8445#section-6.1.2.3
+pub(crate) fn pair_priority(g: u32, d: u32) -> u64 {
+    let (g, d) = (u64::from(g), u64::from(d));
+
+    (1 << 32) * g.min(d) + 2 * g.max(d) + if g > d { 1 } else { 0 }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Check {
+    pub(crate) index: usize,
+    pub(crate) nominate: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Checklist {
+    pairs: Vec<CandidatePair>,
+    triggered: VecDeque<Check>,
+    // at most one pair for each component
+    nominating: Vec<usize>,
+    selected: Vec<usize>,
+}
+
+impl Checklist {
+    pub(crate) fn pair(&self, index: usize) -> &CandidatePair {
+        &self.pairs[index]
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&CandidatePair> {
+        self.pairs.get(index)
+    }
+
+    pub(crate) fn find(
+        &self,
+        transport: Transport,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> Option<usize> {
+        self.pairs
+            .iter()
+            .position(|p| p.transport == transport && p.local == local && p.remote == remote)
+    }
+
+    // Adds a pair, unless one with the same local base and remote
+    // candidate already exists, in which case the higher priority one is
+    // kept.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.2.4
+    pub(crate) fn add(&mut self, pair: CandidatePair, role: Role) -> usize {
+        match self.find(pair.transport, pair.local, pair.remote) {
+            Some(index) => {
+                let existing = &mut self.pairs[index];
+                if existing.state == PairState::Frozen
+                    && pair.priority(role) > existing.priority(role)
+                {
+                    *existing = pair;
+                }
+
+                index
+            }
+            None => {
+                self.pairs.push(pair);
+
+                self.pairs.len() - 1
+            }
+        }
+    }
+
+    // For each foundation, the pair with the lowest component ID (and then
+    // highest priority) starts out in the Waiting state; all the others
+    // remain Frozen.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.2.6
+    pub(crate) fn unfreeze_initial(&mut self, role: Role) {
+        let mut first_by_foundation: Vec<usize> = vec![];
+        for (index, pair) in self.pairs.iter().enumerate() {
+            if pair.state != PairState::Frozen {
+                continue;
+            }
+
+            let existing = first_by_foundation
+                .iter_mut()
+                .find(|i| self.pairs[**i].foundation == pair.foundation);
+            match existing {
+                Some(i) => {
+                    let current = &self.pairs[*i];
+                    if (pair.component_id, u64::MAX - pair.priority(role))
+                        < (current.component_id, u64::MAX - current.priority(role))
+                    {
+                        *i = index;
+                    }
+                }
+                None => first_by_foundation.push(index),
+            }
+        }
+
+        for index in first_by_foundation {
+            self.pairs[index].state = PairState::Waiting;
+        }
+    }
+
+    pub(crate) fn trigger(&mut self, check: Check) {
+        let pair = &mut self.pairs[check.index];
+        if pair.state == PairState::InProgress || pair.state == PairState::Failed {
+            pair.state = PairState::Waiting;
+        }
+
+        if !self.triggered.contains(&check) {
+            self.triggered.push_back(check);
+        }
+    }
+
+    // Picks the next pair to check: anything in the triggered check queue
+    // first, then the highest priority Waiting pair, unfreezing a pair if
+    // nothing is Waiting.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.4.2
+    pub(crate) fn next(&mut self, role: Role) -> Option<Check> {
+        while let Some(check) = self.triggered.pop_front() {
+            let pair = &mut self.pairs[check.index];
+            match pair.state {
+                PairState::Succeeded if check.nominate => return Some(check),
+                PairState::Frozen | PairState::Waiting => {
+                    pair.state = PairState::InProgress;
+                    return Some(check);
+                }
+                _ => continue,
+            }
+        }
+
+        if !self.pairs.iter().any(|p| p.state == PairState::Waiting) {
+            self.unfreeze_next(role);
+        }
+
+        let index = self
+            .pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.state == PairState::Waiting)
+            .max_by_key(|(_, p)| p.priority(role))
+            .map(|(i, _)| i)?;
+        self.pairs[index].state = PairState::InProgress;
+
+        Some(Check {
+            index,
+            nominate: false,
+        })
+    }
+
+    fn unfreeze_next(&mut self, role: Role) {
+        let active_foundations: Vec<String> = self
+            .pairs
+            .iter()
+            .filter(|p| p.state == PairState::Waiting || p.state == PairState::InProgress)
+            .map(|p| p.foundation.clone())
+            .collect();
+
+        let maybe_index = self
+            .pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.state == PairState::Frozen)
+            .filter(|(_, p)| !active_foundations.contains(&p.foundation))
+            .max_by_key(|(_, p)| p.priority(role))
+            .map(|(i, _)| i);
+
+        if let Some(index) = maybe_index {
+            self.pairs[index].state = PairState::Waiting;
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc8445#section-7.2.5.3.3
+    pub(crate) fn succeeded(&mut self, index: usize) {
+        let foundation = self.pairs[index].foundation.clone();
+
+        let pair = &mut self.pairs[index];
+        pair.state = PairState::Succeeded;
+        pair.valid = true;
+
+        for pair in &mut self.pairs {
+            if pair.foundation == foundation && pair.state == PairState::Frozen {
+                pair.state = PairState::Waiting;
+            }
+        }
+    }
+
+    pub(crate) fn failed(&mut self, index: usize) {
+        let pair = &mut self.pairs[index];
+        pair.state = PairState::Failed;
+        pair.valid = false;
+
+        self.nominating.retain(|i| *i != index);
+    }
+
+    // Nominating a pair selects it for its component, unless a higher
+    // priority pair has already been nominated.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-8.1.1
+    pub(crate) fn nominate(&mut self, index: usize, role: Role) {
+        self.nominating.retain(|i| *i != index);
+
+        let component_id = self.pairs[index].component_id;
+        let maybe_position = self
+            .selected
+            .iter()
+            .position(|i| self.pairs[*i].component_id == component_id);
+        match maybe_position {
+            Some(position) => {
+                let selected = self.selected[position];
+                if self.pairs[index].priority(role) > self.pairs[selected].priority(role) {
+                    self.selected[position] = index;
+                }
+            }
+            None => self.selected.push(index),
+        }
+    }
+
+    // A renominated pair replaces the selected pair of its component,
+    // whatever their priorities.
+    pub(crate) fn renominate(&mut self, index: usize) {
+        self.nominating.retain(|i| *i != index);
+
+        let pairs = &self.pairs;
+        let component_id = pairs[index].component_id;
+        self.selected
+            .retain(|i| pairs[*i].component_id != component_id);
+        self.selected.push(index);
+    }
+
+    pub(crate) fn selected_for(&self, component_id: u16) -> Option<&CandidatePair> {
+        self.selected
+            .iter()
+            .map(|index| &self.pairs[*index])
+            .find(|pair| pair.component_id == component_id)
+    }
+
+    // The components that have pairs to check, in order.
+    fn components(&self) -> Vec<u16> {
+        let mut components: Vec<u16> = self.pairs.iter().map(|p| p.component_id).collect();
+        components.sort_unstable();
+        components.dedup();
+
+        components
+    }
+
+    // The highest priority valid pair for a component, which the
+    // controlling agent nominates.
+    pub(crate) fn best_valid(&self, component_id: u16, role: Role) -> Option<usize> {
+        self.pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.component_id == component_id && p.valid)
+            .max_by_key(|(_, p)| p.priority(role))
+            .map(|(i, _)| i)
+    }
+
+    // For each component, the controlling agent nominates the highest
+    // priority valid pair once there's no pair left to check that could
+    // beat it.
+    pub(crate) fn nominate_best(&mut self, role: Role) {
+        for component_id in self.components() {
+            let nominating = self
+                .nominating
+                .iter()
+                .any(|i| self.pairs[*i].component_id == component_id);
+            if nominating || self.selected_for(component_id).is_some() {
+                continue;
+            }
+
+            let best = match self.best_valid(component_id, role) {
+                Some(index) => index,
+                None => continue,
+            };
+            let best_priority = self.pairs[best].priority(role);
+
+            let pending = self.pairs.iter().any(|p| {
+                let unchecked = p.state == PairState::Frozen
+                    || p.state == PairState::Waiting
+                    || p.state == PairState::InProgress;
+
+                p.component_id == component_id && unchecked && p.priority(role) > best_priority
+            });
+            if pending {
+                continue;
+            }
+
+            self.nominating.push(best);
+            self.triggered.push_back(Check {
+                index: best,
+                nominate: true,
+            });
+        }
+    }
+
+    pub(crate) fn set_nominate_on_success(&mut self, index: usize) {
+        self.pairs[index].nominate_on_success = true;
+    }
+
+    // Checks complete once every component has a selected pair, and fail
+    // as soon as every pair for any one component has failed.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-8.1.2
+    pub(crate) fn state(&self) -> ChecklistState {
+        let components = self.components();
+        let all_selected = components
+            .iter()
+            .all(|component_id| self.selected_for(*component_id).is_some());
+        if !components.is_empty() && all_selected {
+            return ChecklistState::Completed;
+        }
+
+        let any_failed = self.triggered.is_empty()
+            && components.iter().any(|component_id| {
+                self.selected_for(*component_id).is_none()
+                    && self
+                        .pairs
+                        .iter()
+                        .filter(|p| p.component_id == *component_id)
+                        .all(|p| p.state == PairState::Failed)
+            });
+        if any_failed {
+            return ChecklistState::Failed;
+        }
+
+        ChecklistState::Running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(foundation: &str, local_port: u16, local_priority: u32) -> CandidatePair {
+        CandidatePair::new(
+            foundation.to_string(),
+            1,
+            SocketAddr::from(([127, 0, 0, 1], local_port)),
+            local_priority,
+            SocketAddr::from(([192, 0, 2, 1], 9)),
+            100,
+        )
+    }
+
+    #[test]
+    fn pair_priority_formula() {
+        assert_eq!(pair_priority(2, 1), (1 << 32) + 4 + 1);
+        assert_eq!(pair_priority(1, 2), (1 << 32) + 4);
+
+        let pair = pair("a", 1, 200);
+        assert_eq!(pair.priority(Role::Controlling), pair_priority(200, 100));
+        assert_eq!(pair.priority(Role::Controlled), pair_priority(100, 200));
+    }
+
+    #[test]
+    fn duplicate_pairs_are_pruned() {
+        let mut checklist = Checklist::default();
+        let first = checklist.add(pair("a", 1, 100), Role::Controlling);
+        let second = checklist.add(pair("b", 1, 200), Role::Controlling);
+
+        assert_eq!(first, second);
+        assert_eq!(checklist.pairs.len(), 1);
+        assert_eq!(checklist.pair(first).foundation, "b");
+    }
+
+    #[test]
+    fn initial_states_by_foundation() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("a", 2, 200), Role::Controlling);
+        checklist.add(pair("b", 3, 50), Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+
+        let states: Vec<PairState> = checklist.pairs.iter().map(|p| p.state).collect();
+        assert_eq!(
+            states,
+            vec![PairState::Frozen, PairState::Waiting, PairState::Waiting]
+        );
+    }
+
+    #[test]
+    fn triggered_checks_come_first() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("b", 2, 200), Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+        checklist.trigger(Check {
+            index: 0,
+            nominate: false,
+        });
+
+        assert_eq!(checklist.next(Role::Controlling).unwrap().index, 0);
+        assert_eq!(checklist.next(Role::Controlling).unwrap().index, 1);
+        assert_eq!(checklist.next(Role::Controlling), None);
+    }
+
+    #[test]
+    fn success_unfreezes_foundation() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("a", 2, 200), Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+
+        let check = checklist.next(Role::Controlling).unwrap();
+        assert_eq!(check.index, 1);
+        checklist.succeeded(check.index);
+
+        assert_eq!(checklist.pair(0).state, PairState::Waiting);
+        assert_eq!(checklist.best_valid(1, Role::Controlling), Some(1));
+    }
+
+    #[test]
+    fn renomination_replaces_the_selected_pair() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlled);
+        checklist.add(pair("b", 2, 200), Role::Controlled);
+
+        checklist.nominate(1, Role::Controlled);
+        checklist.nominate(0, Role::Controlled);
+        assert_eq!(checklist.selected_for(1).unwrap().local.port(), 2);
+
+        checklist.renominate(0);
+        assert_eq!(checklist.selected_for(1).unwrap().local.port(), 1);
+        assert_eq!(checklist.selected.len(), 1);
+    }
+
+    #[test]
+    fn frozen_pairs_are_unfrozen_when_idle() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("a", 2, 200), Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+
+        let check = checklist.next(Role::Controlling).unwrap();
+        checklist.failed(check.index);
+
+        assert_eq!(checklist.next(Role::Controlling).unwrap().index, 0);
+    }
+
+    #[test]
+    fn nomination_selects_highest_priority() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("b", 2, 200), Role::Controlling);
+        assert_eq!(checklist.state(), ChecklistState::Running);
+
+        checklist.nominate(1, Role::Controlling);
+        checklist.nominate(0, Role::Controlling);
+
+        assert_eq!(checklist.state(), ChecklistState::Completed);
+        assert_eq!(checklist.selected_for(1).unwrap().foundation, "b");
+    }
+
+    #[test]
+    fn nominate_best_waits_for_higher_priority_pairs() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.add(pair("b", 2, 200), Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+
+        checklist.succeeded(0);
+        checklist.nominate_best(Role::Controlling);
+        assert_eq!(checklist.triggered.len(), 0);
+
+        checklist.failed(1);
+        checklist.nominate_best(Role::Controlling);
+        let check = checklist.next(Role::Controlling).unwrap();
+        assert_eq!(
+            check,
+            Check {
+                index: 0,
+                nominate: true
+            }
+        );
+
+        checklist.nominate(check.index, Role::Controlling);
+        assert_eq!(checklist.selected_for(1).unwrap().foundation, "a");
+    }
+
+    #[test]
+    fn each_component_is_selected() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 200), Role::Controlling);
+        let mut rtcp = pair("a", 2, 199);
+        rtcp.component_id = 2;
+        checklist.add(rtcp, Role::Controlling);
+        checklist.unfreeze_initial(Role::Controlling);
+
+        checklist.succeeded(0);
+        checklist.nominate_best(Role::Controlling);
+        assert_eq!(checklist.triggered.len(), 1);
+
+        let check = checklist.next(Role::Controlling).unwrap();
+        assert_eq!(check.index, 0);
+        checklist.nominate(check.index, Role::Controlling);
+        assert_eq!(checklist.state(), ChecklistState::Running);
+        assert_eq!(checklist.selected_for(2), None);
+
+        checklist.failed(1);
+        assert_eq!(checklist.state(), ChecklistState::Failed);
+    }
+
+    #[test]
+    fn all_pairs_failed() {
+        let mut checklist = Checklist::default();
+        checklist.add(pair("a", 1, 100), Role::Controlling);
+        checklist.failed(0);
+
+        assert_eq!(checklist.state(), ChecklistState::Failed);
+    }
+}
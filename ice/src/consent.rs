@@ -0,0 +1,430 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace, warn};
+use rand::Rng;
+use tokio::{task, time};
+
+use crate::{candidate::LocalCandidate, ConnectionState, State, Transport};
+
+// Consent is refreshed every 5 seconds on average, at randomised
+// intervals so that the checks of different sessions don't line up.
+//
+// https://tools.ietf.org/html/rfc7675#section-5.1
+const CONSENT_INTERVAL: Duration = Duration::from_secs(5);
+
+// https://tools.ietf.org/html/rfc7675#section-5.1
+const CONSENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Lite agents don't check consent, so they send a keepalive on each
+// selected pair every Tr instead.
+//
+// https://tools.ietf.org/html/rfc8445#section-11
+const TR: Duration = Duration::from_secs(15);
+
+// Consent checks are given up on before the next one is due.
+const RETRANSMISSION: stun::Retransmission = stun::Retransmission {
+    rto: Duration::from_millis(500),
+    rc: 3,
+    rm: 4,
+};
+
+// What the response to a consent check says.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Consent {
+    Granted,
+    Unconfirmed,
+    Revoked,
+}
+
+#[derive(Debug)]
+struct ConsentCheck {
+    client: stun::Client<dyn stun::Transport>,
+    request: stun::Message,
+    key: String,
+    local: SocketAddr,
+    remote: SocketAddr,
+}
+
+// Keeps the selected pairs alive for as long as the agent's around, with
+// consent checks from full agents and keepalives from lite ones.
+pub(crate) async fn maintain(state: Arc<Mutex<State>>) {
+    let lite = state.lock().unwrap().lite;
+    if lite {
+        keepalives(state).await;
+    } else {
+        consent_checks(state).await;
+    }
+}
+
+fn consent_interval() -> Duration {
+    CONSENT_INTERVAL.mul_f64(rand::thread_rng().gen_range(0.8..1.2))
+}
+
+// Whether there are selected pairs that media could be sent over, which
+// carries on through an ICE restart.
+fn connected(state: &State) -> bool {
+    let usable = !matches!(
+        state.connection_state,
+        ConnectionState::Failed | ConnectionState::Closed
+    );
+
+    usable && !state.selected_pairs().is_empty()
+}
+
+// The client that sends over a pair, if its socket or connection is still
+// around.
+fn client(
+    state: &State,
+    transport: Transport,
+    local: SocketAddr,
+    remote: SocketAddr,
+) -> Option<stun::Client<dyn stun::Transport>> {
+    match transport {
+        Transport::Udp => state.clients.get(&local).cloned(),
+        Transport::Tcp => state.connections.get(&(local, remote)).cloned(),
+    }
+}
+
+// https://tools.ietf.org/html/rfc7675#section-5.1
+async fn consent_checks(state: Arc<Mutex<State>>) {
+    loop {
+        time::sleep(consent_interval()).await;
+
+        let checks = {
+            let mut state = state.lock().unwrap();
+            expire_consent(&mut state, Instant::now());
+            next_consent_checks(&state)
+        };
+
+        for check in checks {
+            task::spawn(refresh_consent(Arc::clone(&state), check));
+        }
+    }
+}
+
+// Without a response to any consent check for 30 seconds, the remote agent
+// is taken to have gone away, and media mustn't be sent to it.
+//
+// https://tools.ietf.org/html/rfc7675#section-5.1
+fn expire_consent(state: &mut State, now: Instant) {
+    if !connected(state) {
+        return;
+    }
+
+    for (pair, _, _) in state.selected_pairs() {
+        let granted = *state
+            .consent
+            .entry((pair.local, pair.remote))
+            .or_insert(now);
+
+        if now.duration_since(granted) > CONSENT_TIMEOUT {
+            warn!("Consent expired for {} -> {}", pair.local, pair.remote);
+            state.set_connection_state(ConnectionState::Failed);
+            return;
+        }
+    }
+}
+
+// Consent checks are Binding requests just like connectivity checks, but
+// never nominate a pair.
+//
+// https://tools.ietf.org/html/rfc7675#section-5.1
+fn next_consent_checks(state: &State) -> Vec<ConsentCheck> {
+    if !connected(state) {
+        return vec![];
+    }
+
+    let mut checks = vec![];
+    for (pair, local_credentials, remote_credentials) in state.selected_pairs() {
+        let client = match client(state, pair.transport, pair.local, pair.remote) {
+            Some(client) => client.with_retransmission(RETRANSMISSION),
+            None => {
+                debug!("No socket to check consent on {}", pair.local);
+                continue;
+            }
+        };
+
+        let username = format!("{}:{}", remote_credentials.ufrag, local_credentials.ufrag);
+        let request = stun::Message::base(stun::Header::new(
+            stun::Method::Binding,
+            stun::Class::Request,
+            stun::TransactionId::new(),
+        ))
+        .with_attributes(vec![
+            stun::Attribute::username(&username),
+            stun::Attribute::priority(LocalCandidate::peer_reflexive_priority(
+                &state.preferences,
+                pair.component_id,
+                pair.local,
+            )),
+            state.role.attribute(state.tie_breaker),
+        ])
+        .with_message_integrity(remote_credentials.pwd.as_bytes())
+        .with_fingerprint();
+
+        checks.push(ConsentCheck {
+            client,
+            request,
+            key: remote_credentials.pwd,
+            local: pair.local,
+            remote: pair.remote,
+        });
+    }
+
+    checks
+}
+
+async fn refresh_consent(state: Arc<Mutex<State>>, check: ConsentCheck) {
+    trace!("Checking consent {} -> {}", check.local, check.remote);
+    let result = check.client.request(&check.request, check.remote).await;
+
+    let consent = match result {
+        Ok(response) => consent(&response, &check.key),
+        Err(err) => {
+            debug!(
+                "Consent check {} -> {} failed: {}",
+                check.local, check.remote, err
+            );
+            Consent::Unconfirmed
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    update_consent(&mut state, check.local, check.remote, consent);
+}
+
+// Only responses protected with the remote password count. A 403 is the
+// remote agent taking its consent back, rather than failing to confirm it.
+//
+// https://tools.ietf.org/html/rfc7675#section-5.1
+fn consent(response: &stun::Message, key: &str) -> Consent {
+    if response.verify_integrity(key.as_bytes()).is_err() {
+        return Consent::Unconfirmed;
+    }
+
+    let forbidden = response.attributes.iter().any(|attribute| match attribute {
+        stun::Attribute::ErrorCode(error_code) => {
+            error_code.numeric_code() == stun::NumericCode::Forbidden
+        }
+        _ => false,
+    });
+
+    match response.header.class {
+        stun::Class::Success => Consent::Granted,
+        stun::Class::Error if forbidden => Consent::Revoked,
+        _ => Consent::Unconfirmed,
+    }
+}
+
+// A consent check that goes unanswered disconnects the agent until the
+// next one is, and revoked consent fails it straight away, without
+// waiting for consent to expire.
+fn update_consent(state: &mut State, local: SocketAddr, remote: SocketAddr, consent: Consent) {
+    if !connected(state) {
+        return;
+    }
+
+    match consent {
+        Consent::Granted => {
+            state.consent.insert((local, remote), Instant::now());
+            if state.connection_state == ConnectionState::Disconnected {
+                state.set_connection_state(ConnectionState::Connected);
+            }
+        }
+        Consent::Revoked => {
+            warn!("Consent revoked for {} -> {}", local, remote);
+            state.set_connection_state(ConnectionState::Failed);
+        }
+        Consent::Unconfirmed if state.connection_state != ConnectionState::Checking => {
+            state.set_connection_state(ConnectionState::Disconnected);
+        }
+        Consent::Unconfirmed => {}
+    }
+}
+
+// https://tools.ietf.org/html/rfc8445#section-11
+async fn keepalives(state: Arc<Mutex<State>>) {
+    let mut tr = time::interval(TR);
+    loop {
+        tr.tick().await;
+
+        let keepalives: Vec<_> = {
+            let state = state.lock().unwrap();
+            if !connected(&state) {
+                continue;
+            }
+
+            state
+                .selected_pairs()
+                .into_iter()
+                .filter_map(|(pair, _, _)| {
+                    client(&state, pair.transport, pair.local, pair.remote)
+                        .map(|client| (client, pair.remote))
+                })
+                .collect()
+        };
+
+        for (client, remote) in keepalives {
+            let indication = stun::Message::base(stun::Header::new(
+                stun::Method::Binding,
+                stun::Class::Indication,
+                stun::TransactionId::new(),
+            ))
+            .with_fingerprint();
+
+            trace!("Sending keepalive to {}", remote);
+            if let Err(err) = client
+                .transport()
+                .send_to(&indication.to_bytes(), remote)
+                .await
+            {
+                warn!("Failed to send keepalive to {}: {}", remote, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checklist::CandidatePair, Credentials, Role};
+    use tokio::net::UdpSocket;
+
+    fn connected_state(local: SocketAddr, remote: SocketAddr) -> State {
+        let mut state = State {
+            remote_credentials: Some(Credentials::random()),
+            ..State::default()
+        };
+
+        let pair = CandidatePair::new("1:1".to_string(), 1, local, 100, remote, 100);
+        let index = state.checklist.add(pair, Role::Controlling);
+        state.checklist.succeeded(index);
+        state.nominate(index);
+        assert_eq!(state.connection_state, ConnectionState::Connected);
+
+        state
+    }
+
+    fn response(class: stun::Class, attributes: Vec<stun::Attribute>) -> stun::Message {
+        stun::Message::base(stun::Header::new(
+            stun::Method::Binding,
+            class,
+            stun::TransactionId::new(),
+        ))
+        .with_attributes(attributes)
+    }
+
+    #[test]
+    fn consent_intervals_are_randomised() {
+        for _ in 0..100 {
+            let interval = consent_interval();
+            assert!(interval >= Duration::from_secs(4));
+            assert!(interval < Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn consent_expires_after_30_seconds() {
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let remote = "127.0.0.1:6000".parse().unwrap();
+        let mut state = connected_state(local, remote);
+
+        let granted = Instant::now();
+        expire_consent(&mut state, granted);
+        expire_consent(&mut state, granted + CONSENT_TIMEOUT);
+        assert_eq!(state.connection_state, ConnectionState::Connected);
+
+        expire_consent(
+            &mut state,
+            granted + CONSENT_TIMEOUT + Duration::from_secs(1),
+        );
+        assert_eq!(state.connection_state, ConnectionState::Failed);
+    }
+
+    #[test]
+    fn missed_consent_disconnects_until_granted_again() {
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let remote = "127.0.0.1:6000".parse().unwrap();
+        let mut state = connected_state(local, remote);
+
+        update_consent(&mut state, local, remote, Consent::Unconfirmed);
+        assert_eq!(state.connection_state, ConnectionState::Disconnected);
+
+        update_consent(&mut state, local, remote, Consent::Granted);
+        assert_eq!(state.connection_state, ConnectionState::Connected);
+    }
+
+    #[test]
+    fn revoked_consent_fails_immediately() {
+        let local = "127.0.0.1:5000".parse().unwrap();
+        let remote = "127.0.0.1:6000".parse().unwrap();
+        let mut state = connected_state(local, remote);
+
+        update_consent(&mut state, local, remote, Consent::Revoked);
+        assert_eq!(state.connection_state, ConnectionState::Failed);
+    }
+
+    #[test]
+    fn consent_responses() {
+        let forbidden = vec![stun::Attribute::error_code(
+            stun::NumericCode::Forbidden,
+            "Forbidden",
+        )];
+
+        let success = response(stun::Class::Success, vec![]);
+        assert_eq!(
+            consent(
+                &success.clone().with_message_integrity(b"password"),
+                "password"
+            ),
+            Consent::Granted
+        );
+        assert_eq!(consent(&success, "password"), Consent::Unconfirmed);
+
+        let error = response(stun::Class::Error, forbidden);
+        assert_eq!(
+            consent(
+                &error.clone().with_message_integrity(b"password"),
+                "password"
+            ),
+            Consent::Revoked
+        );
+        assert_eq!(
+            consent(&error.with_message_integrity(b"other"), "password"),
+            Consent::Unconfirmed
+        );
+    }
+
+    #[tokio::test]
+    async fn keepalives_are_fingerprinted_binding_indications() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = socket.local_addr().unwrap();
+        let remote = peer.local_addr().unwrap();
+
+        let mut state = connected_state(local, remote);
+        state.clients.insert(local, stun::Client::new(socket));
+        let task = task::spawn(keepalives(Arc::new(Mutex::new(state))));
+
+        let mut buf = [0; 1500];
+        let (len, from) = time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        task.abort();
+
+        let (_, message) = stun::message(&buf[..len]).unwrap();
+        assert_eq!(from, local);
+        assert_eq!(message.header.method, stun::Method::Binding);
+        assert_eq!(message.header.class, stun::Class::Indication);
+        assert!(matches!(
+            message.attributes.as_slice(),
+            [stun::Attribute::Fingerprint(_)]
+        ));
+        message.verify_fingerprint().unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+use std::net::SocketAddr;
+
+use log::{debug, trace, warn};
+
+use crate::{
+    check::{error_response, handle_request},
+    checklist::PairState,
+    State, Transport,
+};
+
+// How a STUN message received by a listener is handled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Handler {
+    // an inbound connectivity check
+    Check,
+    // a keepalive, which needs no response
+    //
+    // https://tools.ietf.org/html/rfc8445#section-11
+    Keepalive,
+    // Chrome's lightweight stand-in for a check on a pair that has already
+    // succeeded
+    Ping,
+    // a response to one of our own requests, which is passed to the
+    // client that sent it
+    Response,
+    // data relayed to us by a TURN server
+    Data,
+}
+
+#[rustfmt::skip]
+const HANDLERS: &[(stun::Method, stun::Class, Handler)] = &[
+    (stun::Method::Binding,          stun::Class::Request,    Handler::Check),
+    (stun::Method::Binding,          stun::Class::Indication, Handler::Keepalive),
+    (stun::Method::Binding,          stun::Class::Success,    Handler::Response),
+    (stun::Method::Binding,          stun::Class::Error,      Handler::Response),
+    (stun::Method::GoogPing,         stun::Class::Request,    Handler::Ping),
+    (stun::Method::GoogPing,         stun::Class::Success,    Handler::Response),
+    (stun::Method::GoogPing,         stun::Class::Error,      Handler::Response),
+    (stun::Method::Allocate,         stun::Class::Success,    Handler::Response),
+    (stun::Method::Allocate,         stun::Class::Error,      Handler::Response),
+    (stun::Method::Refresh,          stun::Class::Success,    Handler::Response),
+    (stun::Method::Refresh,          stun::Class::Error,      Handler::Response),
+    (stun::Method::CreatePermission, stun::Class::Success,    Handler::Response),
+    (stun::Method::CreatePermission, stun::Class::Error,      Handler::Response),
+    (stun::Method::ChannelBind,      stun::Class::Success,    Handler::Response),
+    (stun::Method::ChannelBind,      stun::Class::Error,      Handler::Response),
+    (stun::Method::Data,             stun::Class::Indication, Handler::Data),
+];
+
+pub(crate) fn handler(header: &stun::Header) -> Option<Handler> {
+    HANDLERS
+        .iter()
+        .find(|(method, class, _)| *method == header.method && *class == header.class)
+        .map(|(_, _, handler)| *handler)
+}
+
+// Handles a message that the listener needn't pass on elsewhere,
+// returning any reply to send back to its source. Requests without a
+// handler get a 400.
+//
+// https://tools.ietf.org/html/rfc5389#section-7.3.1
+pub(crate) fn handle_message(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    message: &stun::Message,
+) -> Option<stun::Message> {
+    match handler(&message.header) {
+        Some(Handler::Check) => handle_request(state, transport, base, source, message),
+        Some(Handler::Keepalive) => {
+            trace!("Received keepalive from {} on {}", source, base);
+            None
+        }
+        Some(Handler::Ping) => handle_ping(state, transport, base, source, message),
+        Some(Handler::Response) | Some(Handler::Data) => None,
+        None if message.header.class == stun::Class::Request => {
+            debug!(
+                "Rejecting {:?} request from {}",
+                message.header.method, source
+            );
+            let reply = error_response(
+                &message.header,
+                stun::NumericCode::BadRequest,
+                "Bad Request",
+            )
+            .with_fingerprint();
+
+            Some(reply)
+        }
+        None => {
+            debug!(
+                "Dropping {:?} {:?} from {}",
+                message.header.method, message.header.class, source
+            );
+            None
+        }
+    }
+}
+
+// Which of the two integrity attributes libwebrtc protects a GOOG-PING
+// with, which its response must be protected with too.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PingIntegrity {
+    MessageIntegrity,
+    GoogMessageIntegrity32,
+}
+
+// GOOG-PING requests are only sent once a pair has succeeded, so they're
+// answered for those pairs alone. They carry no USERNAME, and so are
+// authenticated with the local password of the pair they arrive on.
+fn handle_ping(
+    state: &mut State,
+    transport: Transport,
+    base: SocketAddr,
+    source: SocketAddr,
+    request: &stun::Message,
+) -> Option<stun::Message> {
+    if let Err(err) = request.verify_fingerprint() {
+        warn!("Dropping ping from {}: {}", source, err);
+        return None;
+    }
+
+    let index = match state.checklist.find(transport, base, source) {
+        Some(index) if state.checklist.pair(index).state == PairState::Succeeded => index,
+        _ => {
+            debug!("Dropping ping from {} on unchecked pair", source);
+            return None;
+        }
+    };
+
+    let integrity = request
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            stun::Attribute::MessageIntegrity(_) => Some(PingIntegrity::MessageIntegrity),
+            stun::Attribute::GoogMessageIntegrity32(_) => {
+                Some(PingIntegrity::GoogMessageIntegrity32)
+            }
+            _ => None,
+        });
+    let integrity = match integrity {
+        Some(integrity) => integrity,
+        None => {
+            debug!("Rejecting unauthenticated ping from {}", source);
+            let reply = error_response(
+                &request.header,
+                stun::NumericCode::BadRequest,
+                "Bad Request",
+            )
+            .with_fingerprint();
+
+            return Some(reply);
+        }
+    };
+
+    let key = state.local_credentials.pwd.clone();
+    let verified = match integrity {
+        PingIntegrity::MessageIntegrity => request.verify_integrity(key.as_bytes()),
+        PingIntegrity::GoogMessageIntegrity32 => {
+            request.verify_goog_message_integrity_32(key.as_bytes())
+        }
+    };
+    if let Err(err) = verified {
+        warn!("Dropping ping from {}: {}", source, err);
+        return None;
+    }
+
+    trace!("Answering ping on pair {}", index);
+    let reply = stun::Message::base(stun::Header::new(
+        stun::Method::GoogPing,
+        stun::Class::Success,
+        request.header.transaction_id,
+    ));
+    let reply = match integrity {
+        PingIntegrity::MessageIntegrity => reply.with_message_integrity(key.as_bytes()),
+        PingIntegrity::GoogMessageIntegrity32 => {
+            reply.with_goog_message_integrity_32(key.as_bytes())
+        }
+    };
+
+    Some(reply.with_fingerprint())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checklist::CandidatePair, Role};
+
+    fn message(method: stun::Method, class: stun::Class) -> stun::Message {
+        stun::Message::base(stun::Header::new(method, class, stun::TransactionId::new()))
+            .with_fingerprint()
+    }
+
+    #[test]
+    fn only_binding_requests_are_checks() {
+        let lookup = |method, class| handler(&message(method, class).header);
+
+        assert_eq!(
+            lookup(stun::Method::Binding, stun::Class::Request),
+            Some(Handler::Check)
+        );
+        assert_eq!(
+            lookup(stun::Method::Binding, stun::Class::Success),
+            Some(Handler::Response)
+        );
+        assert_eq!(
+            lookup(stun::Method::Binding, stun::Class::Indication),
+            Some(Handler::Keepalive)
+        );
+        assert_eq!(lookup(stun::Method::Allocate, stun::Class::Request), None);
+    }
+
+    #[test]
+    fn keepalives_and_unhandled_requests() {
+        let mut state = State::default();
+        let base = "127.0.0.1:5000".parse().unwrap();
+        let source = "127.0.0.1:6000".parse().unwrap();
+
+        let keepalive = message(stun::Method::Binding, stun::Class::Indication);
+        assert!(handle_message(&mut state, Transport::Udp, base, source, &keepalive).is_none());
+
+        let request = message(stun::Method::Allocate, stun::Class::Request);
+        let reply = handle_message(&mut state, Transport::Udp, base, source, &request).unwrap();
+        assert_eq!(reply.header.method, stun::Method::Allocate);
+        assert_eq!(reply.header.class, stun::Class::Error);
+        assert_eq!(reply.header.transaction_id, request.header.transaction_id);
+    }
+
+    // Pings as libwebrtc sends them, without a USERNAME.
+    fn ping(integrity: PingIntegrity, key: &str) -> stun::Message {
+        let request = stun::Message::base(stun::Header::new(
+            stun::Method::GoogPing,
+            stun::Class::Request,
+            stun::TransactionId::new(),
+        ));
+        let request = match integrity {
+            PingIntegrity::MessageIntegrity => request.with_message_integrity(key.as_bytes()),
+            PingIntegrity::GoogMessageIntegrity32 => {
+                request.with_goog_message_integrity_32(key.as_bytes())
+            }
+        };
+
+        let bytes = request.with_fingerprint().to_bytes();
+        stun::message(&bytes).unwrap().1
+    }
+
+    #[test]
+    fn pings_are_answered_on_succeeded_pairs() {
+        let mut state = State::default();
+        let base = "127.0.0.1:5000".parse().unwrap();
+        let source = "127.0.0.1:6000".parse().unwrap();
+
+        let pwd = state.local_credentials.pwd.clone();
+        let ping = ping(PingIntegrity::GoogMessageIntegrity32, &pwd);
+        assert!(handle_message(&mut state, Transport::Udp, base, source, &ping).is_none());
+
+        let pair = CandidatePair::new("1:1".to_string(), 1, base, 100, source, 100);
+        let index = state.checklist.add(pair, Role::Controlling);
+        assert!(handle_message(&mut state, Transport::Udp, base, source, &ping).is_none());
+
+        state.checklist.succeeded(index);
+        let reply = handle_message(&mut state, Transport::Udp, base, source, &ping).unwrap();
+        assert_eq!(reply.header.method, stun::Method::GoogPing);
+        assert_eq!(reply.header.class, stun::Class::Success);
+        assert_eq!(reply.header.transaction_id, ping.header.transaction_id);
+        reply
+            .verify_goog_message_integrity_32(pwd.as_bytes())
+            .unwrap();
+
+        // answered in kind
+        let ping = self::ping(PingIntegrity::MessageIntegrity, &pwd);
+        let reply = handle_message(&mut state, Transport::Udp, base, source, &ping).unwrap();
+        reply.verify_integrity(pwd.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn pings_must_be_authenticated() {
+        let mut state = State::default();
+        let base = "127.0.0.1:5000".parse().unwrap();
+        let source = "127.0.0.1:6000".parse().unwrap();
+
+        let pair = CandidatePair::new("1:1".to_string(), 1, base, 100, source, 100);
+        let index = state.checklist.add(pair, Role::Controlling);
+        state.checklist.succeeded(index);
+
+        let unauthenticated = message(stun::Method::GoogPing, stun::Class::Request);
+        let reply =
+            handle_message(&mut state, Transport::Udp, base, source, &unauthenticated).unwrap();
+        assert_eq!(reply.header.class, stun::Class::Error);
+
+        for &integrity in &[
+            PingIntegrity::MessageIntegrity,
+            PingIntegrity::GoogMessageIntegrity32,
+        ] {
+            let forged = ping(integrity, "not the password");
+            assert!(handle_message(&mut state, Transport::Udp, base, source, &forged).is_none());
+        }
+    }
+}
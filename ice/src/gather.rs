@@ -0,0 +1,384 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use rand::seq::SliceRandom;
+use tokio::task::{self, JoinHandle};
+
+use crate::{
+    bind_address,
+    candidate::LocalCandidate,
+    listener::udp_listener,
+    net::SystemNet,
+    relay::{keep_alive, Relay, TurnServer},
+    tcp::{so_socket, tcp_listener, DISCARD_PORT},
+    xor_mapped_address, Event, GatheringState, MdnsMode, State, TcpType,
+};
+
+// Gathering shouldn't hang for the best part of a minute waiting on an
+// unreachable server, so give up sooner than a regular STUN transaction.
+//
+// https://tools.ietf.org/html/rfc8445#section-14.3
+const RETRANSMISSION: stun::Retransmission = stun::Retransmission {
+    rto: Duration::from_millis(500),
+    rc: 3,
+    rm: 4,
+};
+
+// Which interfaces and addresses host candidates are gathered on, and the
+// ports they're bound to. An empty list of allowed interfaces allows them
+// all, but the denied ones always win, and loopback interfaces are only
+// used when asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GatheringPolicy {
+    pub allowed_interfaces: Vec<String>,
+    pub denied_interfaces: Vec<String>,
+    pub loopback: bool,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    // the inclusive range ports are picked from, so that a firewall only
+    // needs to let that range through
+    pub port_range: Option<(u16, u16)>,
+    // a single UDP socket per address for each component, bound to this
+    // port (and the next one up for RTCP without rtcp-mux)
+    pub fixed_port: Option<u16>,
+}
+
+impl Default for GatheringPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_interfaces: vec![],
+            denied_interfaces: vec![],
+            loopback: false,
+            ipv4: true,
+            ipv6: true,
+            port_range: None,
+            fixed_port: None,
+        }
+    }
+}
+
+impl GatheringPolicy {
+    pub(crate) fn allows_interface(&self, name: &str, loopback: bool) -> bool {
+        let allowed =
+            self.allowed_interfaces.is_empty() || self.allowed_interfaces.iter().any(|i| i == name);
+        let denied = self.denied_interfaces.iter().any(|i| i == name);
+
+        allowed && !denied && (self.loopback || !loopback)
+    }
+
+    pub(crate) fn allows_address(&self, address: &IpAddr) -> bool {
+        match address {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        }
+    }
+
+    // The ports to try binding UDP sockets to, in order, where 0 leaves it
+    // to the OS.
+    pub(crate) fn udp_ports(&self, component_id: u16) -> Vec<u16> {
+        match self.fixed_port {
+            Some(port) => port.checked_add(component_id - 1).into_iter().collect(),
+            None => self.tcp_ports(),
+        }
+    }
+
+    // Passive and simultaneous-open candidates can't share a fixed port,
+    // so TCP sockets only ever keep to the port range.
+    pub(crate) fn tcp_ports(&self) -> Vec<u16> {
+        match self.port_range {
+            Some((min, max)) => {
+                let mut ports: Vec<u16> = (min..=max).collect();
+                ports.shuffle(&mut rand::thread_rng());
+                ports
+            }
+            None => vec![0],
+        }
+    }
+}
+
+// Everything an agent needs to gather its candidates, so that gathering
+// can carry on in the background.
+#[derive(Debug)]
+pub(crate) struct Gatherer {
+    pub(crate) local_addrs: Vec<IpAddr>,
+    pub(crate) policy: GatheringPolicy,
+    pub(crate) stun_servers: Vec<SocketAddr>,
+    pub(crate) turn_servers: Vec<TurnServer>,
+    pub(crate) mdns_mode: MdnsMode,
+    pub(crate) ice_tcp: bool,
+    pub(crate) rtcp_mux: bool,
+    pub(crate) state: Arc<Mutex<State>>,
+}
+
+impl Gatherer {
+    // Gathers every candidate, then signals the end of candidates. Returns
+    // the tasks the candidates depend on.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-5.1.1
+    pub(crate) async fn run(self) -> Vec<JoinHandle<()>> {
+        self.state
+            .lock()
+            .unwrap()
+            .set_gathering_state(GatheringState::Gathering);
+
+        let components = if self.rtcp_mux { 1 } else { 2 };
+
+        let mut handles = vec![];
+        for local_addr in &self.local_addrs {
+            let mut hostname = None;
+            for component_id in 1..=components {
+                let ports = self.policy.udp_ports(component_id);
+                let (address, handle) =
+                    match udp_listener(local_addr, &ports, Arc::clone(&self.state)).await {
+                        Ok(listener) => listener,
+                        Err(err) => {
+                            warn!(
+                                "Unable to gather local candidate for component {} on {}: {}",
+                                component_id, local_addr, err
+                            );
+                            continue;
+                        }
+                    };
+
+                {
+                    let mut state = self.state.lock().unwrap();
+                    let mut candidate =
+                        LocalCandidate::host(&state.preferences, component_id, address);
+                    if self.mdns_mode == MdnsMode::QueryAndGather && hostname.is_none() {
+                        if let Some(mdns) = &state.mdns {
+                            hostname = Some(mdns.register(address.ip()));
+                        }
+                    }
+                    candidate.hostname = hostname.clone();
+                    state.add_local_candidate(candidate);
+                }
+                handles.push(handle);
+
+                if self.ice_tcp {
+                    let ports = self.policy.tcp_ports();
+                    let maybe_handle = tcp_host(
+                        &self.state,
+                        component_id,
+                        local_addr,
+                        &ports,
+                        hostname.clone(),
+                    )
+                    .await;
+                    handles.extend(maybe_handle);
+                }
+
+                server_reflexive(&self.state, component_id, &self.stun_servers, address).await;
+                handles
+                    .extend(relayed(&self.state, component_id, &self.turn_servers, address).await);
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.emit(Event::EndOfCandidates);
+        state.set_gathering_state(GatheringState::Complete);
+
+        handles
+    }
+}
+
+// Sends a Binding request to each STUN server from the given base, and
+// adds a server reflexive candidate for every mapped address we learn.
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.1.2
+pub(crate) async fn server_reflexive(
+    state: &Mutex<State>,
+    component_id: u16,
+    stun_servers: &[SocketAddr],
+    base: SocketAddr,
+) {
+    let client = match state.lock().unwrap().clients.get(&base) {
+        Some(client) => client.clone().with_retransmission(RETRANSMISSION),
+        None => return,
+    };
+
+    for stun_server in stun_servers {
+        if stun_server.is_ipv4() != base.is_ipv4() {
+            continue;
+        }
+
+        let request = stun::Message::base(stun::Header::new(
+            stun::Method::Binding,
+            stun::Class::Request,
+            stun::TransactionId::new(),
+        ));
+
+        let response = match client.request(&request, *stun_server).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Binding request to {} failed: {}", stun_server, err);
+                continue;
+            }
+        };
+
+        let mapped = match xor_mapped_address(&response) {
+            Some(mapped) => mapped,
+            None => {
+                warn!("No mapped address in response from {}", stun_server);
+                continue;
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        let candidate = LocalCandidate::server_reflexive(
+            &state.preferences,
+            component_id,
+            mapped,
+            base,
+            stun_server.ip(),
+        );
+        if state.add_local_candidate(candidate) {
+            debug!(
+                "Gathered server reflexive candidate {} via {}",
+                mapped, stun_server
+            );
+        }
+    }
+}
+
+// Allocates a relay on each TURN server from the given base, and adds a
+// relayed candidate for it. The mapped address in the allocate response
+// doubles as a server reflexive candidate. Returns the tasks keeping the
+// allocations alive.
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.1.2
+pub(crate) async fn relayed(
+    state: &Mutex<State>,
+    component_id: u16,
+    turn_servers: &[TurnServer],
+    base: SocketAddr,
+) -> Vec<JoinHandle<()>> {
+    let client = match state.lock().unwrap().clients.get(&base) {
+        Some(client) => client.clone().with_retransmission(RETRANSMISSION),
+        None => return vec![],
+    };
+
+    let mut handles = vec![];
+    for turn_server in turn_servers {
+        if turn_server.address().is_ipv4() != base.is_ipv4() {
+            continue;
+        }
+
+        let allocation = match turn_server.allocate(client.clone()).await {
+            Ok(allocation) => Arc::new(allocation),
+            Err(err) => {
+                warn!(
+                    "Allocate request to {} failed: {}",
+                    turn_server.address(),
+                    err
+                );
+                continue;
+            }
+        };
+        let relayed = allocation.relayed_address();
+        let mapped = allocation.mapped_address();
+
+        {
+            let mut state = state.lock().unwrap();
+
+            let server = turn_server.address().ip();
+            let candidate = LocalCandidate::server_reflexive(
+                &state.preferences,
+                component_id,
+                mapped,
+                base,
+                server,
+            );
+            if state.add_local_candidate(candidate) {
+                debug!(
+                    "Gathered server reflexive candidate {} via {}",
+                    mapped,
+                    turn_server.address()
+                );
+            }
+
+            let candidate = LocalCandidate::relayed(
+                &state.preferences,
+                component_id,
+                relayed,
+                mapped,
+                base,
+                server,
+            );
+            state.add_local_candidate(candidate);
+            debug!(
+                "Gathered relayed candidate {} via {}",
+                relayed,
+                turn_server.address()
+            );
+
+            let transport: Arc<dyn stun::Transport> = allocation.clone();
+            state.clients.insert(relayed, stun::Client::new(transport));
+            state.relays.push(Relay {
+                base,
+                allocation: Arc::clone(&allocation),
+            });
+        }
+
+        handles.push(task::spawn(keep_alive(allocation)));
+    }
+
+    handles
+}
+
+// Adds an active, a passive and a simultaneous-open TCP candidate on the
+// given address, all sharing any mDNS name of its UDP host candidate.
+// Returns the task accepting connections to the passive candidate.
+//
+// https://tools.ietf.org/html/rfc6544#section-5.1
+pub(crate) async fn tcp_host(
+    state: &Arc<Mutex<State>>,
+    component_id: u16,
+    address: &IpAddr,
+    ports: &[u16],
+    hostname: Option<String>,
+) -> Option<JoinHandle<()>> {
+    let mut active = bind_address(&SystemNet, address);
+    active.set_port(DISCARD_PORT);
+    let mut candidates = vec![(active, TcpType::Active)];
+
+    let maybe_handle = match tcp_listener(address, ports, Arc::clone(state)).await {
+        Ok((passive, handle)) => {
+            candidates.push((passive, TcpType::Passive));
+            Some(handle)
+        }
+        Err(err) => {
+            warn!(
+                "Unable to gather passive TCP candidate on {}: {}",
+                address, err
+            );
+            None
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    match so_socket(address, ports) {
+        Ok((so, socket)) => {
+            candidates.push((so, TcpType::SimultaneousOpen));
+            state.so_sockets.push(socket);
+        }
+        Err(err) => warn!(
+            "Unable to gather simultaneous-open TCP candidate on {}: {}",
+            address, err
+        ),
+    }
+
+    for (address, tcp_type) in candidates {
+        let mut candidate =
+            LocalCandidate::tcp_host(&state.preferences, component_id, address, tcp_type);
+        candidate.hostname = hostname.clone();
+        if state.add_local_candidate(candidate) {
+            debug!("Gathered {} TCP candidate {}", tcp_type, address);
+        }
+    }
+
+    maybe_handle
+}
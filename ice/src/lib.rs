@@ -1,30 +1,55 @@
+mod candidate;
+mod check;
+mod checklist;
+mod consent;
+mod dispatch;
+mod gather;
+mod listener;
+mod mdns;
+mod net;
+mod relay;
+mod role;
+mod tcp;
+
 use std::{
-    convert::{TryFrom, TryInto},
+    collections::HashMap,
+    convert::TryInto,
     default::Default,
-    iter::FromIterator,
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use fehler::{throw, throws};
-use log::{debug, trace, warn};
-use nom::{
-    branch::alt,
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, char, crlf, digit1, none_of, one_of},
-    combinator::{all_consuming, map, map_res, opt, recognize},
-    multi::{count, many0, many1, many_m_n},
-    sequence::{delimited, pair, preceded, terminated, tuple},
-    IResult,
-};
-use nom_locate::LocatedSpan;
-use pnet::datalink;
+use log::{debug, warn};
 use rand::{self, seq::SliceRandom};
 use tokio::{
-    net::UdpSocket,
+    net::TcpSocket,
+    sync::{mpsc, watch},
     task::{self, JoinHandle},
 };
 
+use crate::{
+    candidate::LocalCandidate,
+    check::{nomination_check, perform_check, run_checks},
+    checklist::{CandidatePair, Checklist, ChecklistState},
+    consent::maintain,
+    gather::Gatherer,
+    mdns::{resolve_candidates, Mdns},
+    relay::{Allocation, Relay},
+    role::rand_tie_breaker,
+};
+pub use crate::{
+    candidate::{Candidate, CandidateType, ConnectionAddress, Preferences, TcpType, Transport},
+    gather::GatheringPolicy,
+    listener::ListenerErrors,
+    mdns::MdnsMode,
+    net::{Interface, NatType, Net, SystemNet, UdpConn, VirtualHost, VirtualNat, VirtualNetwork},
+    relay::TurnServer,
+    role::Role,
+};
+
 const MTU: usize = 1500;
 const ICE_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890+/";
 
@@ -34,13 +59,95 @@ pub enum Error {
     BindFailed { source: std::io::Error },
     #[error("invalid candidate attribute: {0}")]
     InvalidCandidate(String),
+    #[error("the agent was closed")]
+    Closed,
+    #[error("failed to connect")]
+    ConnectionFailed,
+    #[error("remote credentials are needed to start checks")]
+    MissingRemoteCredentials,
+    #[error("no pair from {local} to {remote}")]
+    UnknownPair {
+        local: SocketAddr,
+        remote: SocketAddr,
+    },
+    #[error("only a controlling agent can renominate")]
+    NotControlling,
+    #[error("renomination isn't supported by both agents")]
+    RenominationUnsupported,
     #[error("unsupported candidate type: {0}")]
     UnsupportedCandidateType(String),
+    #[error("unsupported TCP type: {0}")]
+    UnsupportedTcpType(String),
     #[error("unsupported transport: {0}")]
     UnsupportedTransport(String),
 }
 
-type Span<'a> = LocatedSpan<&'a str>;
+// Things that happen in the background that the owner of an agent may
+// need to act on.
+#[derive(Debug)]
+pub enum Event {
+    ListenerFailed {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    // A local candidate to trickle to the remote agent.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-8
+    Candidate(Candidate),
+    // Gathering has finished, so no more local candidates will be emitted.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-13
+    EndOfCandidates,
+    GatheringStateChanged(GatheringState),
+    ConnectionStateChanged(ConnectionState),
+    // Media for the component should now be sent over this pair.
+    SelectedPairChanged {
+        component_id: u16,
+        local: SocketAddr,
+        remote: SocketAddr,
+    },
+}
+
+// https://www.w3.org/TR/webrtc/#rtcicegatheringstate-enum
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GatheringState {
+    New,
+    Gathering,
+    Complete,
+}
+
+// How the controlling agent nominates pairs.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum NominationMode {
+    // the best valid pair is nominated with a check of its own once
+    // checking has finished
+    //
+    // https://tools.ietf.org/html/rfc8445#section-8.1.1
+    #[default]
+    Regular,
+    // every check nominates its pair, so the first to succeed is selected
+    // straight away, and is only replaced by a higher priority one
+    //
+    // https://tools.ietf.org/html/rfc5245#section-8.1.1.2
+    Aggressive,
+}
+
+// https://www.w3.org/TR/webrtc/#rtciceconnectionstate-enum
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    New,
+    Checking,
+    // every component has a selected pair, but checks may still be
+    // running
+    Connected,
+    // checks have finished, and every component has a selected pair
+    Completed,
+    // consent checks on the selected pair have stopped being answered
+    Disconnected,
+    // checks found no pair that works, or consent has expired
+    Failed,
+    Closed,
+}
 
 fn rand_ice_string(length: usize) -> String {
     let mut rng = &mut rand::thread_rng();
@@ -52,366 +159,627 @@ fn rand_ice_string(length: usize) -> String {
     String::from_utf8(random_chars).unwrap()
 }
 
-fn get_local_addrs() -> Vec<IpAddr> {
-    datalink::interfaces()
+fn get_local_addrs(net: &dyn Net, policy: &GatheringPolicy) -> Vec<IpAddr> {
+    net.interfaces()
         .into_iter()
-        .flat_map(|i| {
-            if i.is_up() && !i.is_loopback() {
-                i.ips
-            } else {
-                vec![]
-            }
-        })
-        .filter_map(|a| if a.is_ipv4() { Some(a.ip()) } else { None })
+        .filter(|i| i.up && policy.allows_interface(&i.name, i.loopback))
+        .flat_map(|i| i.ips)
+        .filter(|a| is_host_address(a) && policy.allows_address(a))
         .collect()
 }
 
-#[throws]
-async fn udp_listener(address: &IpAddr, key: &str) -> (SocketAddr, JoinHandle<()>) {
-    debug!("Starting UDP listener on {}", address);
+// IPv4-mapped, IPv4-compatible and site-local IPv6 addresses aren't used
+// for host candidates.
+//
+// https://tools.ietf.org/html/rfc8445#section-5.1.1.1
+fn is_host_address(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(addr) => addr.to_ipv4().is_none() && addr.segments()[0] & 0x_FFC0 != 0x_FEC0,
+    }
+}
 
-    let socket = UdpSocket::bind(format!("{}:0", address))
-        .await
-        .map_err(|source| Error::BindFailed { source })?;
-    let local_addr = socket.local_addr().unwrap();
-    debug!("Socket bound to {}", local_addr);
-
-    let key = key.to_string();
-    let handle = task::spawn(async move {
-        let local_addr = socket.local_addr().unwrap();
-        let mut buf = [0; MTU];
-        loop {
-            let (bytes_rcvd, src_addr) = socket.recv_from(&mut buf).await.unwrap();
-            trace!(
-                "Received {} bytes from {} on {}: {:02X?}",
-                bytes_rcvd,
-                src_addr,
-                local_addr,
-                buf[..bytes_rcvd].to_vec()
-            );
+// fe80::/10
+fn is_link_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0x_FFC0 == 0x_FE80
+}
 
-            let (_, message) = stun::message(&buf[..bytes_rcvd]).unwrap();
-            debug!("Received connectivity check: {:?}", message);
+// Link-local IPv6 addresses are only unique within their interface, so
+// sockets bound to them need its index as their scope.
+fn bind_address(net: &dyn Net, address: &IpAddr) -> SocketAddr {
+    match address {
+        IpAddr::V6(addr) if is_link_local(addr) => {
+            let scope_id = net
+                .interfaces()
+                .into_iter()
+                .find(|i| i.ips.contains(address))
+                .map_or(0, |i| i.index);
+
+            SocketAddr::V6(SocketAddrV6::new(*addr, 0, 0, scope_id))
+        }
+        _ => SocketAddr::new(*address, 0),
+    }
+}
 
-            if message.header.method != stun::Method::Binding
-                && message.header.class != stun::Class::Request
-            {
-                continue;
-            }
+// Binds to the first of the ports that's free, returning the error from
+// the last one tried if none are.
+fn bind_any<T>(
+    net: &dyn Net,
+    address: &IpAddr,
+    ports: &[u16],
+    bind: impl Fn(SocketAddr) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "no ports to bind to",
+    ));
+    for port in ports {
+        let mut socket_address = bind_address(net, address);
+        socket_address.set_port(*port);
+
+        result = bind(socket_address);
+        if result.is_ok() {
+            break;
+        }
+    }
 
-            let mut maybe_username = None;
-            for attribute in message.attributes {
-                match attribute {
-                    stun::Attribute::Username(u) => {
-                        maybe_username = Some(u);
-                        break;
-                    }
-                    _ => continue,
-                }
+    result
+}
+
+// A remote link-local address can only be reached through the interface
+// of a link-local base, so it takes on that base's scope.
+fn scoped_remote(remote: SocketAddr, base: SocketAddr) -> Option<SocketAddr> {
+    match (remote, base) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) => Some(remote),
+        (SocketAddr::V6(remote), SocketAddr::V6(base)) => {
+            if is_link_local(remote.ip()) != is_link_local(base.ip()) {
+                return None;
             }
 
-            let username = match maybe_username {
-                Some(u) => u,
-                None => continue,
+            let scope_id = if is_link_local(remote.ip()) {
+                base.scope_id()
+            } else {
+                0
             };
 
-            let reply = stun::Message::base(stun::Header::new(
-                stun::Method::Binding,
-                stun::Class::Success,
-                message.header.transaction_id,
-            ))
-            .with_attributes(vec![
-                stun::Attribute::username(username.as_str()),
-                stun::Attribute::xor_mapped_address(src_addr.ip(), src_addr.port()),
-            ])
-            .with_message_integrity(key.as_ref())
-            .with_fingerprint();
-
-            trace!("Prepared reply: {:?}", reply);
-
-            let reply = reply.to_bytes();
-
-            trace!("Sending reply: {:02X?}", reply.to_vec());
-
-            socket.send_to(&reply, src_addr).await.unwrap();
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                *remote.ip(),
+                remote.port(),
+                0,
+                scope_id,
+            )))
         }
-    });
-
-    (local_addr, handle)
+        _ => None,
+    }
 }
 
-struct Foundation(String);
-
-fn foundation(input: Span) -> IResult<Span, Foundation> {
-    map(
-        terminated(many_m_n(1, 32, one_of(ICE_CHARS)), char(' ')),
-        |chars| Foundation(String::from_iter(&chars)),
-    )(input)
+#[derive(Clone, Debug, PartialEq)]
+struct Credentials {
+    ufrag: String,
+    pwd: String,
 }
 
-struct ComponentId(u16);
-
-fn component_id(input: Span) -> IResult<Span, ComponentId> {
-    let (remainder, id) = map_res(
-        terminated(recognize(many_m_n(1, 5, digit1)), char(' ')),
-        |digits: Span| (*digits.fragment()).parse(),
-    )(input)?;
-
-    Ok((remainder, ComponentId(id)))
+impl Credentials {
+    fn random() -> Self {
+        Self {
+            ufrag: rand_ice_string(4),
+            pwd: rand_ice_string(22),
+        }
+    }
 }
 
-//  token       =  1*(alphanum / "-" / "." / "!" / "%" / "*"
-//                 / "_" / "+" / "`" / "'" / "~" )
+// What's kept from before an ICE restart, so that media can carry on over
+// the pairs that were selected until new ones are.
 //
-// https://tools.ietf.org/html/rfc3261#section-25.1
-fn token(input: Span) -> IResult<Span, Span> {
-    recognize(many1(alt((
-        alphanumeric1,
-        recognize(many1(one_of("-.!%*_+`'~"))),
-    ))))(input)
+// https://tools.ietf.org/html/rfc8445#section-9
+#[derive(Debug)]
+struct Generation {
+    local_credentials: Credentials,
+    remote_credentials: Credentials,
+    selected: Vec<CandidatePair>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-enum Transport {
-    Udp,
-    Tcp,
+fn matches_username(
+    username: &str,
+    local_credentials: &Credentials,
+    remote_credentials: Option<&Credentials>,
+) -> bool {
+    let (local_ufrag, remote_ufrag) = match username.split_once(':') {
+        Some(ufrags) => ufrags,
+        None => return false,
+    };
+
+    if local_ufrag != local_credentials.ufrag {
+        return false;
+    }
+
+    match remote_credentials {
+        Some(remote_credentials) => remote_ufrag == remote_credentials.ufrag,
+        None => true,
+    }
 }
 
-impl FromStr for Transport {
-    type Err = Error;
+// State shared between the agent, its listeners and its checks.
+#[derive(Debug)]
+struct State {
+    local_credentials: Credentials,
+    remote_credentials: Option<Credentials>,
+    lite: bool,
+    role: Role,
+    tie_breaker: u64,
+    preferences: Preferences,
+    local_candidates: Vec<LocalCandidate>,
+    remote_candidates: Vec<Candidate>,
+    checklist: Checklist,
+    clients: HashMap<SocketAddr, stun::Client<dyn stun::Transport>>,
+    relays: Vec<Relay>,
+    // clients for the TCP connections between a base and a remote
+    connections: HashMap<(SocketAddr, SocketAddr), stun::Client<dyn stun::Transport>>,
+    // sockets holding onto the ports of simultaneous-open candidates
+    so_sockets: Vec<TcpSocket>,
+    listener_errors: ListenerErrors,
+    events: mpsc::UnboundedSender<Event>,
+    mdns: Option<Arc<Mdns>>,
+    checks_started: bool,
+    gathering_state: GatheringState,
+    remote_trickle: bool,
+    // the remote agent has no more candidates to trickle, which is always
+    // the case when it doesn't trickle at all
+    remote_end_of_candidates: bool,
+    // bumped by every ICE restart, so checks for the last one stop
+    generation: u32,
+    previous: Option<Generation>,
+    connection_state: ConnectionState,
+    // followed by wait_till_completion, so it needn't consume events
+    connection_states: watch::Sender<ConnectionState>,
+    // when consent was last granted on each selected pair
+    consent: HashMap<(SocketAddr, SocketAddr), Instant>,
+    net: Arc<dyn Net>,
+    nomination_mode: NominationMode,
+    renomination: bool,
+    remote_renomination: bool,
+    // the last nomination value sent by the controlling agent
+    nomination: u32,
+    // the latest nomination of each component, and the pair it was for,
+    // which takes over from any pair selected before it
+    nominations: HashMap<u16, (u32, usize)>,
+}
 
-    #[throws]
-    fn from_str(token: &str) -> Self {
-        match token {
-            "udp" | "UDP" => Self::Udp,
-            "tcp" | "TCP" => Self::Tcp,
-            _ => throw!(Error::UnsupportedTransport(token.to_string())),
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            local_credentials: Credentials::random(),
+            remote_credentials: None,
+            lite: false,
+            role: Role::Controlling,
+            tie_breaker: rand_tie_breaker(),
+            preferences: Preferences::default(),
+            local_candidates: vec![],
+            remote_candidates: vec![],
+            checklist: Checklist::default(),
+            clients: HashMap::new(),
+            relays: vec![],
+            connections: HashMap::new(),
+            so_sockets: vec![],
+            listener_errors: ListenerErrors::default(),
+            // replaced by the agent that owns the state
+            events: mpsc::unbounded_channel().0,
+            mdns: None,
+            checks_started: false,
+            gathering_state: GatheringState::New,
+            remote_trickle: false,
+            remote_end_of_candidates: true,
+            generation: 0,
+            previous: None,
+            connection_state: ConnectionState::New,
+            // replaced by the agent that owns the state
+            connection_states: watch::channel(ConnectionState::New).0,
+            consent: HashMap::new(),
+            net: Arc::new(SystemNet),
+            nomination_mode: NominationMode::default(),
+            renomination: false,
+            remote_renomination: false,
+            nomination: 0,
+            nominations: HashMap::new(),
         }
     }
 }
 
-fn transport(input: Span) -> IResult<Span, Transport> {
-    map_res(terminated(token, char(' ')), |token: Span| {
-        (*token.fragment()).parse()
-    })(input)
-}
+impl State {
+    // Events are dropped once the agent has gone away.
+    fn emit(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
 
-struct Priority(u32);
+    // Redundant candidates, which share a component, transport address and
+    // base with one of at least the same priority, are dropped. Returns
+    // whether the candidate was kept.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-5.1.3
+    fn add_local_candidate(&mut self, candidate: LocalCandidate) -> bool {
+        let maybe_index = self.local_candidates.iter().position(|c| {
+            c.component_id == candidate.component_id
+                && c.address == candidate.address
+                && c.base == candidate.base
+        });
+
+        let index = match maybe_index {
+            Some(index) if self.local_candidates[index].priority >= candidate.priority => {
+                return false;
+            }
+            Some(index) => {
+                self.local_candidates[index] = candidate;
+                index
+            }
+            None => {
+                self.local_candidates.push(candidate);
+                self.local_candidates.len() - 1
+            }
+        };
 
-fn priority(input: Span) -> IResult<Span, Priority> {
-    let (remainder, priority) = map_res(
-        terminated(recognize(many_m_n(1, 10, digit1)), char(' ')),
-        |digits: Span| (*digits.fragment()).parse(),
-    )(input)?;
+        let candidate = self.signalled(&self.local_candidates[index]);
+        self.emit(Event::Candidate(candidate));
+        self.update_checklist();
 
-    Ok((remainder, Priority(priority)))
-}
+        true
+    }
 
-fn ipv4_address(input: Span) -> IResult<Span, IpAddr> {
-    map_res(
-        recognize(pair(count(terminated(digit1, char('.')), 3), digit1)),
-        |addr: Span| (*addr.fragment()).parse(),
-    )(input)
-}
+    // The candidate as it's signalled to the remote agent, with the
+    // addresses of host candidates that have mDNS names kept hidden.
+    fn signalled(&self, candidate: &LocalCandidate) -> Candidate {
+        let hidden: Vec<SocketAddr> = self
+            .local_candidates
+            .iter()
+            .filter(|c| c.hostname.is_some())
+            .map(|c| c.address)
+            .collect();
 
-type Port = u16;
+        candidate.to_candidate(&hidden)
+    }
 
-fn port(input: Span) -> IResult<Span, Port> {
-    map_res(recognize(many_m_n(1, 5, digit1)), |digits: Span| {
-        (*digits.fragment()).parse()
-    })(input)
-}
+    // Candidates that turn up once checks have started, whether they were
+    // trickled, resolved or gathered late, are paired straight away.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-10
+    fn update_checklist(&mut self) {
+        if !self.checks_started {
+            return;
+        }
 
-fn connection_address_and_port(input: Span) -> IResult<Span, SocketAddr> {
-    map(
-        pair(
-            terminated(ipv4_address, char(' ')),
-            terminated(port, char(' ')),
-        ),
-        SocketAddr::from,
-    )(input)
-}
+        self.pair_candidates();
+        let role = self.role;
+        self.checklist.unfreeze_initial(role);
+    }
 
-#[derive(Clone, Debug, PartialEq)]
-enum CandidateType {
-    Host,
-    ServerReflexive,
-    Relayed,
-    PeerReflexive,
-}
+    fn set_gathering_state(&mut self, gathering_state: GatheringState) {
+        if self.gathering_state != gathering_state {
+            self.gathering_state = gathering_state;
+            self.emit(Event::GatheringStateChanged(gathering_state));
+        }
+    }
 
-impl FromStr for CandidateType {
-    type Err = Error;
+    // Nothing but closing the agent leaves it closed.
+    fn set_connection_state(&mut self, connection_state: ConnectionState) {
+        if self.connection_state == ConnectionState::Closed {
+            return;
+        }
 
-    #[throws]
-    fn from_str(token: &str) -> Self {
-        match token {
-            "host" => Self::Host,
-            "srflx" => Self::ServerReflexive,
-            "relay" => Self::Relayed,
-            "prflx" => Self::PeerReflexive,
-            _ => throw!(Error::UnsupportedCandidateType(token.to_string())),
+        if self.connection_state != connection_state {
+            debug!(
+                "Connection state changed from {:?} to {:?}",
+                self.connection_state, connection_state
+            );
+            self.connection_state = connection_state;
+            let _ = self.connection_states.send(connection_state);
+            self.emit(Event::ConnectionStateChanged(connection_state));
         }
     }
-}
 
-fn candidate_type(input: Span) -> IResult<Span, CandidateType> {
-    map_res(preceded(tag("typ "), token), |token: Span| {
-        (*token.fragment()).parse()
-    })(input)
-}
+    // Once every component has a pair selected, any pairs kept from before
+    // an ICE restart are done with.
+    fn nominate(&mut self, index: usize) {
+        let component_id = self.checklist.pair(index).component_id;
+        let before = self.selected_for(component_id);
+
+        // https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+        match self.nominations.get(&component_id) {
+            Some((_, latest)) if *latest == index => self.checklist.renominate(index),
+            Some(_) => {
+                debug!("Ignoring superseded nomination of pair {}", index);
+                return;
+            }
+            None => self.checklist.nominate(index, self.role),
+        }
 
-fn related_address_and_port(input: Span) -> IResult<Span, SocketAddr> {
-    map(
-        pair(
-            preceded(tag(" raddr "), ipv4_address),
-            preceded(tag(" rport "), port),
-        ),
-        SocketAddr::from,
-    )(input)
-}
+        if let Some(pair) = self.checklist.selected_for(component_id) {
+            if before.as_ref() != Some(pair) {
+                let (local, remote) = (pair.local, pair.remote);
+                self.emit(Event::SelectedPairChanged {
+                    component_id,
+                    local,
+                    remote,
+                });
+            }
+        }
 
-struct ExtensionAttribute(String, String);
+        // Lite agents only have the pairs that were checked by the remote
+        // agent, so they're done once every component they gathered for has
+        // been nominated, and have no checks of their own left to finish.
+        //
+        // https://tools.ietf.org/html/rfc8445#section-8.2
+        let completed = if self.lite {
+            self.local_candidates
+                .iter()
+                .all(|c| self.checklist.selected_for(c.component_id).is_some())
+        } else {
+            self.checklist.state() == ChecklistState::Completed
+        };
+
+        if completed {
+            if self.previous.take().is_some() {
+                debug!("ICE restart completed");
+            }
+            if self.lite {
+                self.set_connection_state(ConnectionState::Completed);
+            } else if self.connection_state != ConnectionState::Completed {
+                self.set_connection_state(ConnectionState::Connected);
+            }
+        }
+    }
 
-fn extension_attribute(input: Span) -> IResult<Span, ExtensionAttribute> {
-    map(
-        pair(
-            preceded(char(' '), many1(none_of(" \r\n"))),
-            preceded(char(' '), many1(none_of(" \r\n"))),
-        ),
-        |(name, value)| ExtensionAttribute(String::from_iter(&name), String::from_iter(&value)),
-    )(input)
-}
+    // The pairs media is sent over, along with the local and remote
+    // credentials for checks on them. Until an ICE restart has selected
+    // new pairs, the old ones are kept.
+    fn selected_pairs(&self) -> Vec<(CandidatePair, Credentials, Credentials)> {
+        let mut selected = vec![];
+
+        if let Some(remote_credentials) = &self.remote_credentials {
+            for component_id in 1..=2 {
+                if let Some(pair) = self.checklist.selected_for(component_id) {
+                    selected.push((
+                        pair.clone(),
+                        self.local_credentials.clone(),
+                        remote_credentials.clone(),
+                    ));
+                }
+            }
+        }
 
-trait Candidate {}
+        if let Some(previous) = &self.previous {
+            for pair in &previous.selected {
+                if !selected
+                    .iter()
+                    .any(|(p, _, _)| p.component_id == pair.component_id)
+                {
+                    selected.push((
+                        pair.clone(),
+                        previous.local_credentials.clone(),
+                        previous.remote_credentials.clone(),
+                    ));
+                }
+            }
+        }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct LocalCandidate {
-    address: SocketAddr,
-    ty: CandidateType,
-}
+        selected
+    }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct RemoteCandidate {
-    address: SocketAddr,
-    ty: CandidateType,
-}
+    fn selected_for(&self, component_id: u16) -> Option<CandidatePair> {
+        self.selected_pairs()
+            .into_iter()
+            .map(|(pair, _, _)| pair)
+            .find(|pair| pair.component_id == component_id)
+    }
 
-type RemoteCandidateArgs = (
-    Foundation,
-    ComponentId,
-    Transport,
-    Priority,
-    SocketAddr,
-    CandidateType,
-    Option<SocketAddr>,
-    Vec<ExtensionAttribute>,
-);
-
-impl RemoteCandidate {
-    fn from_tuple(args: RemoteCandidateArgs) -> Self {
-        Self {
-            address: args.4,
-            ty: args.5,
+    // Starts over with new local credentials and no candidates or checks,
+    // keeping the selected pairs until new ones have been selected. The
+    // sockets of the old candidates are kept open for them.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-9
+    fn restart(&mut self) {
+        let selected = self.selected_pairs();
+        if let Some((_, local_credentials, remote_credentials)) = selected.first() {
+            self.previous = Some(Generation {
+                local_credentials: local_credentials.clone(),
+                remote_credentials: remote_credentials.clone(),
+                selected: selected.into_iter().map(|(pair, _, _)| pair).collect(),
+            });
         }
+
+        self.local_credentials = Credentials::random();
+        self.remote_credentials = None;
+        self.local_candidates.clear();
+        self.remote_candidates.clear();
+        self.checklist = Checklist::default();
+        self.nominations.clear();
+        self.checks_started = false;
+        self.remote_end_of_candidates = !self.remote_trickle;
+        self.generation += 1;
+        debug!("Restarted as generation {}", self.generation);
     }
-}
 
-//   candidate-attribute   = "candidate" ":" foundation SP component-id SP
-//                           transport SP
-//                           priority SP
-//                           connection-address SP     ;from RFC 4566
-//                           port         ;port from RFC 4566
-//                           SP cand-type
-//                           [SP rel-addr]
-//                           [SP rel-port]
-//                           *(SP extension-att-name SP
-//                                extension-att-value)
-//
-//   foundation            = 1*32ice-char
-//   component-id          = 1*5DIGIT
-//   transport             = "UDP" / transport-extension
-//   transport-extension   = token              ; from RFC 3261
-//   priority              = 1*10DIGIT
-//   cand-type             = "typ" SP candidate-types
-//   candidate-types       = "host" / "srflx" / "prflx" / "relay" / token
-//   rel-addr              = "raddr" SP connection-address
-//   rel-port              = "rport" SP port
-//   extension-att-name    = byte-string    ;from RFC 4566
-//   extension-att-value   = byte-string
-//   ice-char              = ALPHA / DIGIT / "+" / "/"
-//
-// https://tools.ietf.org/html/rfc5245#section-15.1
-fn candidate(input: Span) -> IResult<Span, RemoteCandidate> {
-    map(
-        tuple((
-            foundation,
-            component_id,
-            transport,
-            priority,
-            connection_address_and_port,
-            candidate_type,
-            opt(related_address_and_port),
-            many0(extension_attribute),
-        )),
-        RemoteCandidate::from_tuple,
-    )(input)
-}
+    // Until both agents have finished trickling, a checklist whose pairs
+    // have all failed may yet get new ones.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-8
+    fn trickling(&self) -> bool {
+        self.gathering_state == GatheringState::Gathering || !self.remote_end_of_candidates
+    }
+
+    // The allocation behind a relayed candidate.
+    fn allocation(&self, relayed: SocketAddr) -> Option<Arc<Allocation>> {
+        self.relays
+            .iter()
+            .find(|relay| relay.allocation.relayed_address() == relayed)
+            .map(|relay| Arc::clone(&relay.allocation))
+    }
+
+    // The TCP type of the local candidate with the given base.
+    fn tcp_type(&self, base: SocketAddr) -> Option<TcpType> {
+        self.local_candidates
+            .iter()
+            .find(|c| c.transport == Transport::Tcp && c.base == base)
+            .and_then(|c| c.tcp_type)
+    }
 
-impl FromStr for RemoteCandidate {
-    type Err = Error;
+    // The USERNAME of an inbound check is "<our ufrag>:<their ufrag>".
+    //
+    // https://tools.ietf.org/html/rfc8445#section-7.2.2
+    fn accepts_username(&self, username: &str) -> bool {
+        matches_username(
+            username,
+            &self.local_credentials,
+            self.remote_credentials.as_ref(),
+        )
+    }
 
-    #[throws(Error)]
-    fn from_str(s: &str) -> Self {
-        let input = Span::new(&s);
-        let (_, candidate) = all_consuming(candidate)(input)
-            .map_err(|err| Error::InvalidCandidate(err.to_string()))?;
+    // Consent checks on the pairs kept from before an ICE restart still use
+    // the old credentials.
+    fn previous_password(&self, username: &str) -> Option<String> {
+        let previous = self.previous.as_ref()?;
+        let matches = matches_username(
+            username,
+            &previous.local_credentials,
+            Some(&previous.remote_credentials),
+        );
 
-        candidate
+        if matches {
+            Some(previous.local_credentials.pwd.clone())
+        } else {
+            None
+        }
     }
-}
 
-fn candidate_attribute(input: Span) -> IResult<Span, RemoteCandidate> {
-    delimited(tag("a=candidate:"), candidate, crlf)(input)
+    // Peer reflexive local candidates are never paired, since checks are
+    // always sent from their base.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.2.2
+    fn pair_candidates(&mut self) {
+        for local in &self.local_candidates {
+            if local.ty == CandidateType::PeerReflexive {
+                continue;
+            }
+
+            for remote in &self.remote_candidates {
+                if remote.component_id != local.component_id || remote.transport != local.transport
+                {
+                    continue;
+                }
+
+                // Passive candidates can only wait to be connected to, and
+                // remote active candidates only ever show up as peer
+                // reflexive ones when they do, so we only pair the
+                // candidates we can open connections from.
+                //
+                // https://tools.ietf.org/html/rfc6544#section-6.2
+                if local.transport == Transport::Tcp
+                    && (local.tcp_type == Some(TcpType::Passive)
+                        || remote.tcp_type != local.tcp_type.map(TcpType::reversed))
+                {
+                    continue;
+                }
+
+                // hostnames are paired once they've been resolved
+                let address = match remote.address() {
+                    Some(address) => address,
+                    None => continue,
+                };
+                let address = match scoped_remote(address, local.base) {
+                    Some(address) => address,
+                    None => continue,
+                };
+
+                let pair = CandidatePair::new(
+                    format!("{}:{}", local.foundation, remote.foundation),
+                    local.component_id,
+                    local.base,
+                    local.priority,
+                    address,
+                    remote.priority,
+                )
+                .with_transport(local.transport);
+                self.checklist.add(pair, self.role);
+            }
+        }
+    }
 }
 
-impl TryFrom<sdp::Attribute> for RemoteCandidate {
-    type Error = Error;
+fn xor_mapped_address(message: &stun::Message) -> Option<SocketAddr> {
+    message
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            stun::Attribute::XorMappedAddress(mapped) => {
+                Some(SocketAddr::new(mapped.address(), mapped.port()))
+            }
+            _ => None,
+        })
+}
 
-    #[throws(Error)]
-    fn try_from(attribute: sdp::Attribute) -> Self {
-        let attr_string = attribute.to_string();
-        let input = Span::new(&attr_string);
-        let (_, candidate) = all_consuming(candidate_attribute)(input)
-            .map_err(|err| Error::InvalidCandidate(err.to_string()))?;
+// How an agent gathers and checks, which is set before it starts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgentConfig {
+    // lite agents only gather host candidates, never send checks, and
+    // select the pairs the remote agent nominates
+    //
+    // https://tools.ietf.org/html/rfc8445#section-2.5
+    pub lite: bool,
+    // overrides the addresses the gathering policy would pick
+    pub local_addrs: Option<Vec<IpAddr>>,
+    pub gathering_policy: GatheringPolicy,
+    pub stun_servers: Vec<SocketAddr>,
+    pub turn_servers: Vec<TurnServer>,
+    pub mdns_mode: MdnsMode,
+    pub mdns_interface: Ipv4Addr,
+    pub ice_tcp: bool,
+    pub rtcp_mux: bool,
+    pub nomination_mode: NominationMode,
+    // whether we accept renominations, and send them when the remote agent
+    // does too
+    //
+    // https://tools.ietf.org/html/draft-thatcher-ice-renomination-01
+    pub renomination: bool,
+}
 
-        candidate
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            lite: false,
+            local_addrs: None,
+            gathering_policy: GatheringPolicy::default(),
+            stun_servers: vec![],
+            turn_servers: vec![],
+            mdns_mode: MdnsMode::default(),
+            mdns_interface: Ipv4Addr::UNSPECIFIED,
+            ice_tcp: false,
+            rtcp_mux: true,
+            nomination_mode: NominationMode::default(),
+            renomination: false,
+        }
     }
 }
 
-impl Candidate for LocalCandidate {}
-impl Candidate for RemoteCandidate {}
+impl AgentConfig {
+    pub fn lite() -> Self {
+        Self {
+            lite: true,
+            ..Self::default()
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Agent {
-    username: String,
-    password: String,
-    local_addrs: Vec<IpAddr>,
-    local_candidates: Vec<LocalCandidate>,
-    remote_candidates: Vec<RemoteCandidate>,
+    config: AgentConfig,
+    maintaining: bool,
+    state: Arc<Mutex<State>>,
+    events: mpsc::UnboundedReceiver<Event>,
+    connection_states: watch::Receiver<ConnectionState>,
     task_handles: Vec<JoinHandle<()>>,
 }
 
 impl Default for Agent {
     fn default() -> Self {
-        Self {
-            username: rand_ice_string(4),
-            password: rand_ice_string(22),
-            local_addrs: get_local_addrs(),
-            local_candidates: vec![],
-            remote_candidates: vec![],
-            task_handles: vec![],
-        }
+        Self::from_config(AgentConfig::default())
     }
 }
 
@@ -420,98 +788,1518 @@ impl Agent {
         Self::default()
     }
 
+    // An agent that gathers and checks as the config says. Full agents
+    // start out controlling, and lite agents controlled.
+    pub fn from_config(config: AgentConfig) -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        let (state_sender, connection_states) = watch::channel(ConnectionState::New);
+        let state = State {
+            events: sender,
+            connection_states: state_sender,
+            lite: config.lite,
+            nomination_mode: config.nomination_mode,
+            renomination: config.renomination,
+            role: if config.lite {
+                Role::Controlled
+            } else {
+                Role::Controlling
+            },
+            ..State::default()
+        };
+
+        Self {
+            config,
+            maintaining: false,
+            state: Arc::new(Mutex::new(state)),
+            events,
+            connection_states,
+            task_handles: vec![],
+        }
+    }
+
+    // A lite agent only answers connectivity checks, and so always takes
+    // the controlled role.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.1
+    pub fn lite() -> Self {
+        Self::from_config(AgentConfig::lite())
+    }
+
+    pub fn config(&self) -> &AgentConfig {
+        &self.config
+    }
+
+    // Lite agents are always controlled, so the role is ignored for them.
+    pub fn with_role(self, role: Role) -> Self {
+        if self.config.lite {
+            warn!("Ignoring {:?} role for lite agent", role);
+        } else {
+            self.state.lock().unwrap().role = role;
+        }
+        self
+    }
+
+    // The network the agent gathers and checks on over UDP, which is the
+    // host's own unless it's a virtual one for testing. ICE-TCP and mDNS
+    // aren't simulated, and always use the host's own sockets.
+    pub fn with_net(self, net: Arc<dyn Net>) -> Self {
+        self.state.lock().unwrap().net = net;
+        self
+    }
+
+    pub fn with_local_addrs(mut self, local_addrs: Vec<IpAddr>) -> Self {
+        self.config.local_addrs = Some(local_addrs);
+        self
+    }
+
+    pub fn with_gathering_policy(mut self, gathering_policy: GatheringPolicy) -> Self {
+        self.config.gathering_policy = gathering_policy;
+        self
+    }
+
+    pub fn with_stun_servers(mut self, stun_servers: Vec<SocketAddr>) -> Self {
+        self.config.stun_servers = stun_servers;
+        self
+    }
+
+    pub fn with_turn_servers(mut self, turn_servers: Vec<TurnServer>) -> Self {
+        self.config.turn_servers = turn_servers;
+        self
+    }
+
+    pub fn with_mdns_mode(mut self, mdns_mode: MdnsMode) -> Self {
+        self.config.mdns_mode = mdns_mode;
+        self
+    }
+
+    // The interface mDNS queries and responses are sent on, which is left
+    // to the OS by default.
+    pub fn with_mdns_interface(mut self, mdns_interface: Ipv4Addr) -> Self {
+        self.config.mdns_interface = mdns_interface;
+        self
+    }
+
+    // Whether to gather TCP host candidates alongside the UDP ones.
+    //
+    // https://tools.ietf.org/html/rfc6544
+    pub fn with_ice_tcp(mut self, ice_tcp: bool) -> Self {
+        self.config.ice_tcp = ice_tcp;
+        self
+    }
+
+    // Without RTP/RTCP multiplexing, every candidate is gathered for a
+    // second component that RTCP is sent on.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-5.1.1
+    pub fn with_rtcp_mux(mut self, rtcp_mux: bool) -> Self {
+        self.config.rtcp_mux = rtcp_mux;
+        self
+    }
+
+    pub fn with_nomination_mode(mut self, nomination_mode: NominationMode) -> Self {
+        self.config.nomination_mode = nomination_mode;
+        self.state.lock().unwrap().nomination_mode = nomination_mode;
+        self
+    }
+
+    pub fn with_renomination(mut self, renomination: bool) -> Self {
+        self.config.renomination = renomination;
+        self.state.lock().unwrap().renomination = renomination;
+        self
+    }
+
+    pub fn with_preferences(self, preferences: Preferences) -> Self {
+        self.state.lock().unwrap().preferences = preferences;
+        self
+    }
+
+    // Whether the remote agent trickles its candidates, in which case
+    // checks won't fail until it's signalled the end of them.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-8
+    pub fn with_remote_trickle(self, remote_trickle: bool) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remote_trickle = remote_trickle;
+            state.remote_end_of_candidates = !remote_trickle;
+        }
+        self
+    }
+
+    // A full agent has to take the controlling role when the remote agent
+    // is lite, since only it sends checks.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.1
+    pub fn with_remote_lite(self, remote_lite: bool) -> Self {
+        if remote_lite && !self.config.lite {
+            self.state.lock().unwrap().role = Role::Controlling;
+        }
+        self
+    }
+
+    // Whether the remote agent advertised the renomination ICE option.
+    pub fn with_remote_renomination(self, remote_renomination: bool) -> Self {
+        self.state.lock().unwrap().remote_renomination = remote_renomination;
+        self
+    }
+
+    pub fn is_lite(&self) -> bool {
+        self.config.lite
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.lock().unwrap().role
+    }
+
     pub fn username(&self) -> String {
-        self.username.clone()
+        self.state.lock().unwrap().local_credentials.ufrag.clone()
     }
 
     pub fn password(&self) -> String {
-        self.password.clone()
+        self.state.lock().unwrap().local_credentials.pwd.clone()
+    }
+
+    // New credentials from the remote agent mean it's restarted, and so we
+    // have to restart too. Returns whether we did, in which case there are
+    // new local credentials to signal, and candidates to gather again.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-9
+    pub fn set_remote_credentials(&mut self, ufrag: &str, pwd: &str) -> bool {
+        let credentials = Credentials {
+            ufrag: ufrag.to_string(),
+            pwd: pwd.to_string(),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let restart = match &state.remote_credentials {
+            Some(remote_credentials) => *remote_credentials != credentials,
+            None => false,
+        };
+        if restart {
+            debug!("Remote credentials changed, restarting");
+            state.restart();
+        }
+        state.remote_credentials = Some(credentials);
+
+        restart
+    }
+
+    // Restarts ICE with new local credentials. Candidates then have to be
+    // gathered, and the remote agent's new credentials and candidates
+    // added, before checks can be started again. The pairs selected so far
+    // stay selected until then.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-9.1
+    pub fn restart(&mut self) {
+        self.state.lock().unwrap().restart();
     }
 
     #[throws]
     pub fn add_remote_candidate(&mut self, candidate_attribute: sdp::Attribute) {
-        let candidate = candidate_attribute.try_into()?;
-        self.remote_candidates.push(candidate);
+        self.add_candidate(candidate_attribute.try_into()?);
     }
 
-    pub async fn gather(&mut self) {
-        for local_addr in &self.local_addrs {
-            if let Ok((address, handle)) = udp_listener(local_addr, &self.password).await {
-                let candidate = LocalCandidate {
-                    ty: CandidateType::Host,
-                    address,
-                };
-                self.local_candidates.push(candidate);
-                self.task_handles.push(handle);
+    // Adds a remote candidate that's already been parsed, and perhaps
+    // filtered, by the signalling layer. Candidates can keep being added
+    // once checks have started.
+    pub fn add_candidate(&mut self, candidate: Candidate) {
+        let maybe_hostname = match &candidate.connection_address {
+            ConnectionAddress::Hostname(hostname) => Some(hostname.clone()),
+            ConnectionAddress::Ip(_) => None,
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remote_candidates.push(candidate);
+            state.update_checklist();
+        }
 
-                break; // we only want one for now
-            } else {
-                warn!("Unable to gather local candidate on {}", local_addr);
+        if let Some(hostname) = maybe_hostname {
+            self.resolve_hostname(hostname);
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc8838#section-11
+    pub fn add_end_of_candidates(&mut self) {
+        self.state.lock().unwrap().remote_end_of_candidates = true;
+    }
+
+    // Remote .local candidates are resolved in the background, and paired
+    // once they have been. Any others are left unresolved.
+    //
+    // https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02#section-3.2
+    fn resolve_hostname(&mut self, hostname: String) {
+        let mdns = match &self.state.lock().unwrap().mdns {
+            Some(mdns) => Arc::clone(mdns),
+            None => return,
+        };
+
+        if !hostname.to_ascii_lowercase().ends_with(".local") {
+            debug!("Not resolving {}", hostname);
+            return;
+        }
+
+        let handle = task::spawn(resolve_candidates(Arc::clone(&self.state), mdns, hostname));
+        self.task_handles.push(handle);
+    }
+
+    fn start_mdns(&mut self) {
+        if self.config.mdns_mode == MdnsMode::Disabled || self.state.lock().unwrap().mdns.is_some()
+        {
+            return;
+        }
+
+        let mdns = match Mdns::bind(self.config.mdns_interface) {
+            Ok(mdns) => Arc::new(mdns),
+            Err(err) => {
+                warn!("Unable to start mDNS: {}", err);
+                return;
+            }
+        };
+        self.task_handles.push(task::spawn(Arc::clone(&mdns).run()));
+
+        let hostnames: Vec<String> = {
+            let mut state = self.state.lock().unwrap();
+            state.mdns = Some(mdns);
+
+            let mut hostnames = vec![];
+            for candidate in &state.remote_candidates {
+                if let ConnectionAddress::Hostname(hostname) = &candidate.connection_address {
+                    if !hostnames.contains(hostname) {
+                        hostnames.push(hostname.clone());
+                    }
+                }
             }
+
+            hostnames
+        };
+        for hostname in hostnames {
+            self.resolve_hostname(hostname);
         }
     }
 
-    pub fn candidate_attributes(&self) -> Vec<sdp::Attribute> {
-        self.local_candidates
+    // Keeps whatever pairs end up selected alive, for as long as the
+    // agent's around.
+    fn start_maintenance(&mut self) {
+        if self.maintaining {
+            return;
+        }
+
+        self.maintaining = true;
+        self.task_handles
+            .push(task::spawn(maintain(Arc::clone(&self.state))));
+    }
+
+    fn net(&self) -> Arc<dyn Net> {
+        Arc::clone(&self.state.lock().unwrap().net)
+    }
+
+    // Lite agents are only reachable on their host candidates, so they
+    // never gather any others.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-5.1.1
+    fn gatherer(&self) -> Gatherer {
+        let (stun_servers, turn_servers) = if self.config.lite {
+            (vec![], vec![])
+        } else {
+            (
+                self.config.stun_servers.clone(),
+                self.config.turn_servers.clone(),
+            )
+        };
+
+        Gatherer {
+            local_addrs: match &self.config.local_addrs {
+                Some(local_addrs) => local_addrs
+                    .iter()
+                    .filter(|a| self.config.gathering_policy.allows_address(a))
+                    .cloned()
+                    .collect(),
+                None => get_local_addrs(&*self.net(), &self.config.gathering_policy),
+            },
+            policy: self.config.gathering_policy.clone(),
+            stun_servers,
+            turn_servers,
+            mdns_mode: self.config.mdns_mode,
+            ice_tcp: self.config.ice_tcp,
+            rtcp_mux: self.config.rtcp_mux,
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub async fn gather(&mut self) {
+        self.start_mdns();
+        self.start_maintenance();
+
+        let handles = self.gatherer().run().await;
+        self.task_handles.extend(handles);
+    }
+
+    // Gathers in the background, so that candidates can be trickled to the
+    // remote agent as they're emitted, rather than waiting on them all.
+    //
+    // https://tools.ietf.org/html/rfc8838#section-4
+    pub fn start_gathering(&mut self) {
+        self.start_mdns();
+        self.start_maintenance();
+
+        let gatherer = self.gatherer();
+        self.task_handles.push(task::spawn(async move {
+            for handle in gatherer.run().await {
+                handle.await.unwrap();
+            }
+        }));
+    }
+
+    // The candidates to signal to the remote agent, which never include
+    // the peer reflexive ones we learn during checks.
+    pub fn local_candidates(&self) -> Vec<Candidate> {
+        let state = self.state.lock().unwrap();
+        state
+            .local_candidates
             .iter()
-            .enumerate()
-            .map(|(f, c)| encode_as_sdp(f, c.address))
+            .filter(|c| c.ty != CandidateType::PeerReflexive)
+            .map(|c| state.signalled(c))
             .collect()
     }
 
-    pub async fn wait_till_completion(self) {
-        for handle in self.task_handles {
-            handle.await.unwrap();
+    pub fn remote_candidates(&self) -> Vec<Candidate> {
+        self.state.lock().unwrap().remote_candidates.clone()
+    }
+
+    // Until gathering has finished, these are only the candidates that
+    // have been gathered so far.
+    pub fn candidate_attributes(&self) -> Vec<sdp::Attribute> {
+        self.local_candidates()
+            .into_iter()
+            .map(sdp::Attribute::from)
+            .collect()
+    }
+
+    // Pairs up the local and remote candidates and starts checking them.
+    // Lite agents don't send checks, so this does nothing for them.
+    //
+    // https://tools.ietf.org/html/rfc8445#section-6.1.2
+    #[throws]
+    pub fn start_checks(&mut self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.lite {
+                return;
+            }
+
+            if state.remote_credentials.is_none() {
+                throw!(Error::MissingRemoteCredentials);
+            }
+
+            state.checks_started = true;
+            state.update_checklist();
+            state.set_connection_state(ConnectionState::Checking);
         }
+
+        let handle = task::spawn(run_checks(Arc::clone(&self.state)));
+        self.task_handles.push(handle);
     }
-}
 
-fn encode_as_sdp(foundation: usize, candidate: SocketAddr) -> sdp::Attribute {
-    let component_id = 1; // RTP == 1
+    // The local base and remote address of the selected pair.
+    pub fn selected_pair(&self) -> Option<(SocketAddr, SocketAddr)> {
+        self.selected_pair_for(1)
+    }
+
+    // The selected pair of the given component, which is only ever 2 when
+    // RTP and RTCP aren't multiplexed.
+    pub fn selected_pair_for(&self, component_id: u16) -> Option<(SocketAddr, SocketAddr)> {
+        let state = self.state.lock().unwrap();
+        state
+            .selected_for(component_id)
+            .map(|pair| (pair.local, pair.remote))
+    }
+
+    // Switches the selected pair of a component over to another of its
+    // pairs, which only a controlling agent can do, and only when both
+    // agents support renomination. The nominating check is a check like any
+    // other, so the pair needn't have been checked before.
+    //
+    // https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+    #[throws]
+    pub fn renominate(&mut self, local: SocketAddr, remote: SocketAddr) {
+        let maybe_check = {
+            let mut state = self.state.lock().unwrap();
+            if !state.renomination || !state.remote_renomination {
+                throw!(Error::RenominationUnsupported);
+            }
+            if state.role != Role::Controlling {
+                throw!(Error::NotControlling);
+            }
+
+            let maybe_index = [Transport::Udp, Transport::Tcp]
+                .iter()
+                .find_map(|transport| state.checklist.find(*transport, local, remote));
+            let index = match maybe_index {
+                Some(index) => index,
+                None => throw!(Error::UnknownPair { local, remote }),
+            };
+
+            nomination_check(&mut state, index)
+        };
+
+        if let Some(check) = maybe_check {
+            let handle = task::spawn(perform_check(Arc::clone(&self.state), check));
+            self.task_handles.push(handle);
+        }
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.state.lock().unwrap().connection_state
+    }
 
-    let transport = "udp";
+    // Counts of the packets dropped and the socket errors seen by the
+    // listeners so far.
+    pub fn listener_errors(&self) -> ListenerErrors {
+        self.state.lock().unwrap().listener_errors
+    }
+
+    // Returns None once every task that could emit an event has finished.
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    pub fn gathering_state(&self) -> GatheringState {
+        self.state.lock().unwrap().gathering_state
+    }
+
+    // Waits until every component has a pair selected, which is as far as
+    // lite agents get, or checks have failed.
+    #[throws]
+    pub async fn wait_till_completion(&self) {
+        let mut connection_states = self.connection_states.clone();
+        loop {
+            let connection_state = *connection_states.borrow();
+            match connection_state {
+                ConnectionState::Connected | ConnectionState::Completed => break,
+                ConnectionState::Failed => throw!(Error::ConnectionFailed),
+                ConnectionState::Closed => throw!(Error::Closed),
+                _ => {}
+            }
 
-    let ip_precedence = 65535; // IPv4 only
-    let priority = ((2_u64.pow(24)) * 126) + ((2_u64.pow(8)) * ip_precedence) + 256 - component_id;
+            if connection_states.changed().await.is_err() {
+                throw!(Error::Closed);
+            }
+        }
+    }
 
-    let v = format!(
-        "{} {} {} {} {} {} typ host",
-        foundation,
-        component_id,
-        transport,
-        priority,
-        candidate.ip(),
-        candidate.port(),
-    );
-    sdp::Attribute::value("candidate", &v)
+    // Stops every task the agent started, so that its sockets are closed.
+    pub fn close(&mut self) {
+        for handle in self.task_handles.drain(..) {
+            handle.abort();
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .set_connection_state(ConnectionState::Closed);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use tokio::{net::UdpSocket, time};
+
     use super::*;
 
     #[test]
     #[throws]
-    fn remote_candidate_from_attribute() {
-        let candidate_attribute = sdp::Attribute::Value("candidate".to_string(), "1853887674 2 udp 1518280447 47.61.61.61 36768 typ srflx raddr 192.168.0.196 rport 36768 generation 0".to_string());
-        let _candidate: RemoteCandidate = candidate_attribute.try_into()?;
+    fn remote_candidate_with_ipv6_addresses() {
+        let candidate: Candidate =
+            "1 1 udp 1677729535 2001:db8::1 50000 typ srflx raddr fe80::1 rport 50000".parse()?;
+        assert_eq!(
+            candidate.address(),
+            Some("[2001:db8::1]:50000".parse().unwrap())
+        );
     }
 
     #[test]
-    #[throws]
-    fn remote_candidate_from_attribute_with_caps_transport() {
-        let candidate_attribute = sdp::Attribute::Value(
-            "candidate".to_string(),
-            "3 2 UDP 1686052862 47.61.61.61 64346 typ srflx raddr 192.168.0.196 rport 64346"
-                .to_string(),
+    fn link_local_remotes_take_the_scope_of_their_base() {
+        let base = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 5000, 0, 3));
+        let remote = "[fe80::2]:6000".parse().unwrap();
+        assert_eq!(
+            scoped_remote(remote, base),
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::2".parse().unwrap(),
+                6000,
+                0,
+                3
+            )))
+        );
+
+        let global = "[2600::1]:5000".parse().unwrap();
+        assert_eq!(scoped_remote(remote, global), None);
+        assert_eq!(
+            scoped_remote("192.0.2.1:6000".parse().unwrap(), global),
+            None
+        );
+    }
+
+    type Pair = (SocketAddr, SocketAddr);
+
+    // Exchanges credentials and candidates between two agents, and waits
+    // for both of them to select a pair.
+    async fn connect(controlling: &mut Agent, controlled: &mut Agent) -> (Pair, Pair) {
+        connect_with(controlling, controlled, |_| true).await
+    }
+
+    // As connect, but only exchanging the candidates that keep returns
+    // true for.
+    async fn connect_with(
+        controlling: &mut Agent,
+        controlled: &mut Agent,
+        keep: fn(&sdp::Attribute) -> bool,
+    ) -> (Pair, Pair) {
+        controlling.set_remote_credentials(&controlled.username(), &controlled.password());
+        controlled.set_remote_credentials(&controlling.username(), &controlling.password());
+        for attribute in controlled.candidate_attributes().into_iter().filter(keep) {
+            controlling.add_remote_candidate(attribute).unwrap();
+        }
+        for attribute in controlling.candidate_attributes().into_iter().filter(keep) {
+            controlled.add_remote_candidate(attribute).unwrap();
+        }
+
+        controlling.start_checks().unwrap();
+        controlled.start_checks().unwrap();
+
+        time::timeout(Duration::from_secs(5), async {
+            loop {
+                let pairs = (controlling.selected_pair(), controlled.selected_pair());
+                if let (Some(controlling_pair), Some(controlled_pair)) = pairs {
+                    return (controlling_pair, controlled_pair);
+                }
+
+                time::sleep(check::TA).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn full_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone());
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect(&mut controlling, &mut controlled).await;
+        assert_eq!(local, remote_remote);
+        assert_eq!(remote, remote_local);
+    }
+
+    #[tokio::test]
+    async fn restarts_keep_the_selected_pair_until_a_new_one_is() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone());
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+
+        controlling.gather().await;
+        controlled.gather().await;
+        let (old, remote_old) = connect(&mut controlling, &mut controlled).await;
+        assert!(matches!(
+            controlling.connection_state(),
+            ConnectionState::Connected | ConnectionState::Completed
+        ));
+
+        let username = controlling.username();
+        controlling.restart();
+        assert_ne!(controlling.username(), username);
+        assert_eq!(controlling.selected_pair(), Some(old));
+        controlling.gather().await;
+
+        // the controlled agent restarts on seeing new credentials
+        let username = controlled.username();
+        assert!(controlled.set_remote_credentials(&controlling.username(), &controlling.password()));
+        assert!(
+            !controlled.set_remote_credentials(&controlling.username(), &controlling.password())
         );
-        let _candidate: RemoteCandidate = candidate_attribute.try_into()?;
+        assert_ne!(controlled.username(), username);
+        assert_eq!(controlled.selected_pair(), Some(remote_old));
+        controlled.gather().await;
+
+        controlling.set_remote_credentials(&controlled.username(), &controlled.password());
+        for attribute in controlled.candidate_attributes() {
+            controlling.add_remote_candidate(attribute).unwrap();
+        }
+        for attribute in controlling.candidate_attributes() {
+            controlled.add_remote_candidate(attribute).unwrap();
+        }
+        controlling.start_checks().unwrap();
+        controlled.start_checks().unwrap();
+
+        let (new, remote_new) = time::timeout(Duration::from_secs(5), async {
+            loop {
+                let pairs = (controlling.selected_pair(), controlled.selected_pair());
+                if let (Some(new), Some(remote_new)) = pairs {
+                    if new != old && remote_new != remote_old {
+                        return (new, remote_new);
+                    }
+                }
+
+                time::sleep(check::TA).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(new.0, remote_new.1);
+        assert_eq!(new.1, remote_new.0);
+    }
+
+    #[tokio::test]
+    async fn trickle_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_remote_trickle(true);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback)
+            .with_remote_trickle(true);
+
+        controlling.set_remote_credentials(&controlled.username(), &controlled.password());
+        controlled.set_remote_credentials(&controlling.username(), &controlling.password());
+        controlling.start_checks().unwrap();
+        controlled.start_checks().unwrap();
+
+        controlling.start_gathering();
+        controlled.start_gathering();
+
+        // candidates are signalled the way they would be in SDP
+        async fn trickle(from: &mut Agent, to: &mut Agent) {
+            loop {
+                match from.next_event().await.unwrap() {
+                    Event::Candidate(candidate) => {
+                        to.add_remote_candidate(sdp::Attribute::from(candidate))
+                            .unwrap();
+                    }
+                    Event::EndOfCandidates => {
+                        to.add_end_of_candidates();
+                        break;
+                    }
+                    Event::ListenerFailed { source, .. } => panic!("{}", source),
+                    _ => {}
+                }
+            }
+        }
+
+        time::timeout(Duration::from_secs(5), async {
+            trickle(&mut controlling, &mut controlled).await;
+            trickle(&mut controlled, &mut controlling).await;
+
+            loop {
+                let pairs = (controlling.selected_pair(), controlled.selected_pair());
+                if let (Some((local, remote)), Some((remote_local, remote_remote))) = pairs {
+                    assert_eq!(local, remote_remote);
+                    assert_eq!(remote, remote_local);
+                    break;
+                }
+
+                time::sleep(check::TA).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rtcp_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_rtcp_mux(false);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback)
+            .with_rtcp_mux(false);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let components: Vec<u16> = controlling
+            .local_candidates()
+            .iter()
+            .map(|c| c.component_id)
+            .collect();
+        assert_eq!(components, vec![1, 2]);
+
+        let (rtp, _) = connect(&mut controlling, &mut controlled).await;
+        let (rtcp, remote_rtcp) = time::timeout(Duration::from_secs(5), async {
+            loop {
+                let pairs = (
+                    controlling.selected_pair_for(2),
+                    controlled.selected_pair_for(2),
+                );
+                if let (Some(rtcp), Some(remote_rtcp)) = pairs {
+                    return (rtcp, remote_rtcp);
+                }
+
+                time::sleep(check::TA).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_ne!(rtp.0, rtcp.0);
+        assert_eq!(rtcp.0, remote_rtcp.1);
+        assert_eq!(rtcp.1, remote_rtcp.0);
     }
 
     #[test]
-    #[throws]
-    fn remote_candidate_from_str() {
-        let candidate_string = "4 2 TCP 2105458942 10.10.10.10 9 typ host tcptype active";
-        let _candidate: RemoteCandidate = candidate_string.parse()?;
+    fn gathering_policy_filters_interfaces_and_addresses() {
+        let policy = GatheringPolicy::default();
+        assert!(policy.allows_interface("eth0", false));
+        assert!(!policy.allows_interface("lo", true));
+
+        let policy = GatheringPolicy {
+            allowed_interfaces: vec!["eth0".to_string(), "lo".to_string()],
+            denied_interfaces: vec!["eth0".to_string()],
+            loopback: true,
+            ipv6: false,
+            ..GatheringPolicy::default()
+        };
+        assert!(!policy.allows_interface("eth0", false));
+        assert!(!policy.allows_interface("wlan0", false));
+        assert!(policy.allows_interface("lo", true));
+        assert!(policy.allows_address(&IpAddr::from([127, 0, 0, 1])));
+        assert!(!policy.allows_address(&"::1".parse().unwrap()));
+
+        let policy = GatheringPolicy {
+            port_range: Some((50000, 50009)),
+            ..GatheringPolicy::default()
+        };
+        let mut ports = policy.udp_ports(1);
+        ports.sort_unstable();
+        assert_eq!(ports, (50000..=50009).collect::<Vec<_>>());
+
+        let policy = GatheringPolicy {
+            fixed_port: Some(50000),
+            ..policy
+        };
+        assert_eq!(policy.udp_ports(1), vec![50000]);
+        assert_eq!(policy.udp_ports(2), vec![50001]);
+        assert_eq!(policy.tcp_ports().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn gather_within_a_port_range() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let policy = GatheringPolicy {
+            port_range: Some((40000, 40099)),
+            ..GatheringPolicy::default()
+        };
+        let mut agent = Agent::new()
+            .with_local_addrs(loopback)
+            .with_rtcp_mux(false)
+            .with_gathering_policy(policy);
+
+        agent.gather().await;
+
+        let ports: Vec<u16> = agent.local_candidates().iter().map(|c| c.port).collect();
+        assert_eq!(ports.len(), 2);
+        assert!(ports.iter().all(|p| (40000..=40099).contains(p)));
+        assert_ne!(ports[0], ports[1]);
+    }
+
+    #[tokio::test]
+    async fn full_and_lite_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let stun_server = stun_server(None).await;
+        let mut full = Agent::new()
+            .with_role(Role::Controlled)
+            .with_remote_lite(true)
+            .with_local_addrs(loopback.clone());
+        let mut lite = Agent::from_config(AgentConfig {
+            local_addrs: Some(loopback),
+            stun_servers: vec![stun_server],
+            ..AgentConfig::lite()
+        });
+        assert_eq!(full.role(), Role::Controlling);
+        assert_eq!(lite.role(), Role::Controlled);
+
+        full.gather().await;
+        lite.gather().await;
+        assert!(lite
+            .local_candidates()
+            .iter()
+            .all(|c| c.ty == CandidateType::Host));
+
+        let ((local, remote), (lite_local, lite_remote)) = connect(&mut full, &mut lite).await;
+        assert_eq!(local, lite_remote);
+        assert_eq!(remote, lite_local);
+
+        time::timeout(Duration::from_secs(5), lite.wait_till_completion())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lite.connection_state(), ConnectionState::Completed);
+        assert!(
+            lite.state
+                .lock()
+                .unwrap()
+                .checklist
+                .selected_for(1)
+                .unwrap()
+                .valid
+        );
+    }
+
+    #[tokio::test]
+    async fn lite_agents_wait_for_every_component() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut full = Agent::new()
+            .with_remote_lite(true)
+            .with_local_addrs(loopback.clone())
+            .with_rtcp_mux(false);
+        let mut lite = Agent::from_config(AgentConfig {
+            local_addrs: Some(loopback),
+            rtcp_mux: false,
+            ..AgentConfig::lite()
+        });
+
+        full.gather().await;
+        lite.gather().await;
+
+        connect(&mut full, &mut lite).await;
+        time::timeout(Duration::from_secs(5), async {
+            full.wait_till_completion().await.unwrap();
+            lite.wait_till_completion().await.unwrap();
+        })
+        .await
+        .unwrap();
+        for component_id in 1..=2 {
+            assert_eq!(
+                lite.selected_pair_for(component_id).map(|(l, r)| (r, l)),
+                full.selected_pair_for(component_id)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn aggressive_nomination_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_nomination_mode(NominationMode::Aggressive);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect(&mut controlling, &mut controlled).await;
+        assert_eq!(local, remote_remote);
+        assert_eq!(remote, remote_local);
+    }
+
+    #[tokio::test]
+    async fn renomination_switches_the_selected_pair() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from([127, 0, 0, 2])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_renomination(true)
+            .with_remote_renomination(true);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback)
+            .with_renomination(true)
+            .with_remote_renomination(true);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        // whichever pair was selected, there's another on the other
+        // addresses to switch to
+        let ((local, remote), _) = connect(&mut controlling, &mut controlled).await;
+        let other = |agent: &Agent, address: SocketAddr| {
+            agent
+                .state
+                .lock()
+                .unwrap()
+                .local_candidates
+                .iter()
+                .map(|c| c.address)
+                .find(|a| a.ip() != address.ip())
+                .unwrap()
+        };
+        let other_local = other(&controlling, local);
+        let other_remote = other(&controlled, remote);
+
+        controlling.renominate(other_local, other_remote).unwrap();
+        time::timeout(Duration::from_secs(5), async {
+            loop {
+                let pairs = (controlling.selected_pair(), controlled.selected_pair());
+                if pairs
+                    == (
+                        Some((other_local, other_remote)),
+                        Some((other_remote, other_local)),
+                    )
+                {
+                    return;
+                }
+
+                time::sleep(check::TA).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let unknown = SocketAddr::from(([192, 0, 2, 1], 9));
+        assert!(matches!(
+            controlling.renominate(other_local, unknown),
+            Err(Error::UnknownPair { .. })
+        ));
+        assert!(matches!(
+            controlled.renominate(other_remote, other_local),
+            Err(Error::NotControlling)
+        ));
+    }
+
+    #[test]
+    fn controlled_agents_take_the_latest_nomination() {
+        let mut state = State {
+            lite: true,
+            role: Role::Controlled,
+            renomination: true,
+            ..State::default()
+        };
+        let base = SocketAddr::from(([127, 0, 0, 1], 5000));
+        state
+            .local_candidates
+            .push(LocalCandidate::host(&Preferences::default(), 1, base));
+        state.remote_credentials = Some(Credentials::random());
+
+        let nominate = |state: &mut State, source: SocketAddr, priority, nomination| {
+            let remote_credentials = state.remote_credentials.clone().unwrap();
+            let username = format!(
+                "{}:{}",
+                state.local_credentials.ufrag, remote_credentials.ufrag
+            );
+            let request = stun::Message::base(stun::Header::new(
+                stun::Method::Binding,
+                stun::Class::Request,
+                stun::TransactionId::new(),
+            ))
+            .with_attributes(vec![
+                stun::Attribute::username(&username),
+                stun::Attribute::priority(priority),
+                stun::Attribute::ice_controlling(0),
+                stun::Attribute::use_candidate(),
+                stun::Attribute::nomination(nomination),
+            ])
+            .with_message_integrity(state.local_credentials.pwd.as_bytes())
+            .with_fingerprint();
+
+            check::handle_request(state, Transport::Udp, base, source, &request).unwrap();
+            state.selected_for(1).map(|pair| pair.remote)
+        };
+
+        let better = SocketAddr::from(([127, 0, 0, 1], 6000));
+        let worse = SocketAddr::from(([127, 0, 0, 1], 6001));
+        assert_eq!(nominate(&mut state, better, 200, 1), Some(better));
+        assert_eq!(nominate(&mut state, worse, 100, 2), Some(worse));
+        assert_eq!(nominate(&mut state, better, 200, 1), Some(worse));
+    }
+
+    #[tokio::test]
+    async fn states_and_events() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone());
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+        assert_eq!(controlling.gathering_state(), GatheringState::New);
+        assert_eq!(controlling.connection_state(), ConnectionState::New);
+
+        controlling.gather().await;
+        controlled.gather().await;
+        assert_eq!(controlling.gathering_state(), GatheringState::Complete);
+
+        let ((local, remote), _) = connect(&mut controlling, &mut controlled).await;
+        time::timeout(Duration::from_secs(5), controlling.wait_till_completion())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut events = vec![];
+        while let Ok(event) = controlling.events.try_recv() {
+            events.push(event);
+        }
+        let gathering_states: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::GatheringStateChanged(state) => Some(*state),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            gathering_states,
+            vec![GatheringState::Gathering, GatheringState::Complete]
+        );
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::SelectedPairChanged { component_id: 1, local: l, remote: r }
+                if *l == local && *r == remote
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ConnectionStateChanged(ConnectionState::Checking)
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ConnectionStateChanged(ConnectionState::Connected)
+        )));
+
+        controlling.close();
+        assert_eq!(controlling.connection_state(), ConnectionState::Closed);
+        assert!(matches!(
+            controlling.wait_till_completion().await,
+            Err(Error::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn tcp_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_ice_tcp(true);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback)
+            .with_ice_tcp(true);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let tcp_types: Vec<Option<TcpType>> = controlling
+            .state
+            .lock()
+            .unwrap()
+            .local_candidates
+            .iter()
+            .map(|c| c.tcp_type)
+            .collect();
+        assert_eq!(
+            tcp_types,
+            vec![
+                None,
+                Some(TcpType::Active),
+                Some(TcpType::Passive),
+                Some(TcpType::SimultaneousOpen)
+            ]
+        );
+
+        let only_tcp = |attribute: &sdp::Attribute| attribute.to_string().contains(" tcp ");
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect_with(&mut controlling, &mut controlled, only_tcp).await;
+
+        // one side of the selected pair is always an active candidate,
+        // which only the other side knows the real port of
+        let state = controlling.state.lock().unwrap();
+        let selected = state.checklist.selected_for(1).unwrap();
+        assert_eq!(selected.transport, Transport::Tcp);
+        assert!(local.port() == tcp::DISCARD_PORT || remote_local.port() == tcp::DISCARD_PORT);
+        assert!(remote == remote_local || local == remote_remote);
+    }
+
+    // Needs multicast on the loopback interface.
+    #[tokio::test]
+    async fn mdns_agents_on_loopback() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_mdns_mode(MdnsMode::QueryAndGather)
+            .with_mdns_interface(Ipv4Addr::LOCALHOST);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback)
+            .with_mdns_mode(MdnsMode::QueryOnly)
+            .with_mdns_interface(Ipv4Addr::LOCALHOST);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        for attribute in controlling.candidate_attributes() {
+            let value = attribute.to_string();
+            assert!(value.contains(".local "), "{}", value);
+            assert!(!value.contains("127.0.0.1"), "{}", value);
+        }
+
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect(&mut controlling, &mut controlled).await;
+        assert_eq!(local, remote_remote);
+        assert_eq!(remote, remote_local);
+    }
+
+    #[tokio::test]
+    async fn non_stun_packets_are_ignored() {
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone());
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for agent in &[&controlling, &controlled] {
+            let base = agent.state.lock().unwrap().local_candidates[0].address;
+
+            // DTLS, RTP, ChannelData, unclassifiable and truncated STUN
+            #[rustfmt::skip]
+            let packets: [&[u8]; 5] = [
+                &[0x_16, 0x_FE, 0x_FD, 0x_00],
+                &[0x_80, 0x_60, 0x_00, 0x_01],
+                &[0x_40, 0x_00, 0x_00, 0x_01, 0x_FF],
+                &[0x_FF],
+                &[0x_00, 0x_01, 0x_00],
+            ];
+            for packet in &packets {
+                socket.send_to(packet, base).await.unwrap();
+            }
+        }
+
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect(&mut controlling, &mut controlled).await;
+        assert_eq!(local, remote_remote);
+        assert_eq!(remote, remote_local);
+
+        for agent in &[&controlling, &controlled] {
+            let listener_errors = agent.listener_errors();
+            assert_eq!(listener_errors.unrecognised, 1);
+            assert_eq!(listener_errors.malformed, 1);
+        }
+    }
+
+    // Answers Binding requests with the given mapped address, or the
+    // source address of the request if there isn't one.
+    async fn stun_server(mapped: Option<SocketAddr>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        task::spawn(async move {
+            let mut buf = [0; MTU];
+            loop {
+                let (bytes_rcvd, src_addr) = socket.recv_from(&mut buf).await.unwrap();
+                let (_, request) = stun::message(&buf[..bytes_rcvd]).unwrap();
+
+                let mapped = mapped.unwrap_or(src_addr);
+                let response = stun::Message::base(stun::Header::new(
+                    stun::Method::Binding,
+                    stun::Class::Success,
+                    request.header.transaction_id,
+                ))
+                .with_attributes(vec![stun::Attribute::xor_mapped_address(
+                    mapped.ip(),
+                    mapped.port(),
+                )]);
+
+                socket
+                    .send_to(&response.to_bytes(), src_addr)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        address
+    }
+
+    // As stun_server, but on a host of a virtual network, and always with
+    // the source address.
+    fn virtual_stun_server(host: &VirtualHost, address: SocketAddr) {
+        let socket = host.bind_udp(address).unwrap();
+
+        task::spawn(async move {
+            let mut buf = [0; MTU];
+            loop {
+                let (bytes_rcvd, src_addr) = socket.recv_from(&mut buf).await.unwrap();
+                let (_, request) = stun::message(&buf[..bytes_rcvd]).unwrap();
+
+                let response = stun::Message::base(stun::Header::new(
+                    stun::Method::Binding,
+                    stun::Class::Success,
+                    request.header.transaction_id,
+                ))
+                .with_attributes(vec![stun::Attribute::xor_mapped_address(
+                    src_addr.ip(),
+                    src_addr.port(),
+                )]);
+
+                socket
+                    .send_to(&response.to_bytes(), src_addr)
+                    .await
+                    .unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn virtual_agents_behind_nats() {
+        let network = VirtualNetwork::new();
+        network.set_latency(Duration::from_millis(20));
+        network.set_loss(0.05);
+        network.set_seed(7);
+
+        let stun_server = SocketAddr::from(([192, 0, 2, 1], 3478));
+        virtual_stun_server(&network.host(stun_server.ip()), stun_server);
+
+        let full_cone = IpAddr::from([198, 51, 100, 1]);
+        let port_restricted = IpAddr::from([203, 0, 113, 1]);
+        let controlling_host = network
+            .nat(NatType::FullCone, full_cone)
+            .host(IpAddr::from([10, 0, 0, 1]));
+        let controlled_host = network
+            .nat(NatType::PortRestricted, port_restricted)
+            .host(IpAddr::from([10, 0, 1, 1]));
+
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_net(Arc::new(controlling_host))
+            .with_stun_servers(vec![stun_server]);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_net(Arc::new(controlled_host))
+            .with_stun_servers(vec![stun_server]);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let types: Vec<CandidateType> = controlled
+            .local_candidates()
+            .into_iter()
+            .map(|c| c.ty)
+            .collect();
+        assert_eq!(
+            types,
+            vec![CandidateType::Host, CandidateType::ServerReflexive]
+        );
+
+        // the hosts are on different private networks, so only the server
+        // reflexive candidates can connect, and checks on the host
+        // candidates would hold up nomination until they timed out
+        let only_srflx = |attribute: &sdp::Attribute| attribute.to_string().contains(" srflx ");
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect_with(&mut controlling, &mut controlled, only_srflx).await;
+        assert_eq!(local.ip(), IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(remote.ip(), port_restricted);
+        assert_eq!(remote_local.ip(), IpAddr::from([10, 0, 1, 1]));
+        assert_eq!(remote_remote.ip(), full_cone);
+    }
+
+    #[tokio::test]
+    async fn gather_server_reflexive() {
+        let mapped = SocketAddr::from(([192, 0, 2, 1], 40000));
+        let stun_server = stun_server(Some(mapped)).await;
+
+        let mut agent = Agent::new()
+            .with_local_addrs(vec![IpAddr::from([127, 0, 0, 1])])
+            .with_stun_servers(vec![stun_server]);
+        agent.gather().await;
+
+        let attributes = agent.candidate_attributes();
+        assert_eq!(attributes.len(), 2);
+
+        let (base, foundation) = {
+            let state = agent.state.lock().unwrap();
+            (
+                state.local_candidates[0].address,
+                state.local_candidates[1].foundation.clone(),
+            )
+        };
+        let expected = format!(
+            "{} 1 udp 1687158783 192.0.2.1 40000 typ srflx raddr 127.0.0.1 rport {}",
+            foundation,
+            base.port()
+        );
+        assert_eq!(attributes[1], sdp::Attribute::value("candidate", &expected));
+    }
+
+    #[tokio::test]
+    async fn redundant_server_reflexive_candidates_are_dropped() {
+        let first = stun_server(None).await;
+        let second = stun_server(Some(SocketAddr::from(([192, 0, 2, 1], 40000)))).await;
+        let third = stun_server(Some(SocketAddr::from(([192, 0, 2, 1], 40000)))).await;
+
+        let mut agent = Agent::new()
+            .with_local_addrs(vec![IpAddr::from([127, 0, 0, 1])])
+            .with_stun_servers(vec![first, second, third]);
+        agent.gather().await;
+
+        let state = agent.state.lock().unwrap();
+        let types: Vec<&CandidateType> = state.local_candidates.iter().map(|c| &c.ty).collect();
+        assert_eq!(
+            types,
+            vec![&CandidateType::Host, &CandidateType::ServerReflexive]
+        );
+    }
+
+    // Challenges Allocate requests without credentials, and otherwise
+    // grants them the given relayed address.
+    async fn turn_server(relayed: SocketAddr) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let key = stun::long_term_key("user", "example.org", "password");
+
+        task::spawn(async move {
+            let mut buf = [0; MTU];
+            loop {
+                let (bytes_rcvd, src_addr) = socket.recv_from(&mut buf).await.unwrap();
+                let (_, request) = stun::message(&buf[..bytes_rcvd]).unwrap();
+                if request.header.method != stun::Method::Allocate {
+                    continue;
+                }
+
+                let header = stun::Header::new(
+                    stun::Method::Allocate,
+                    stun::Class::Success,
+                    request.header.transaction_id,
+                );
+                let response = if request.verify_integrity(&key).is_ok() {
+                    stun::Message::base(header)
+                        .with_attributes(vec![
+                            stun::Attribute::xor_relayed_address(relayed.ip(), relayed.port()),
+                            stun::Attribute::xor_mapped_address(src_addr.ip(), src_addr.port()),
+                            stun::Attribute::lifetime(600),
+                        ])
+                        .with_message_integrity(&key)
+                } else {
+                    stun::Message::base(stun::Header {
+                        class: stun::Class::Error,
+                        ..header
+                    })
+                    .with_attributes(vec![
+                        stun::Attribute::error_code(
+                            stun::NumericCode::Unauthenticated,
+                            "Unauthenticated",
+                        ),
+                        stun::Attribute::realm("example.org"),
+                        stun::Attribute::nonce("f7yaGg4cbZ8qBsnS"),
+                    ])
+                };
+
+                socket
+                    .send_to(&response.to_bytes(), src_addr)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn gather_relayed() {
+        let relayed = SocketAddr::from(([192, 0, 2, 1], 50000));
+        let turn_server = turn_server(relayed).await;
+
+        let mut agent = Agent::new()
+            .with_local_addrs(vec![IpAddr::from([127, 0, 0, 1])])
+            .with_turn_servers(vec![TurnServer::new(turn_server, "user", "password")]);
+        agent.gather().await;
+
+        // the mapped address is the host candidate, so makes a redundant
+        // server reflexive candidate
+        let attributes = agent.candidate_attributes();
+        assert_eq!(attributes.len(), 2);
+
+        let (base, foundation) = {
+            let state = agent.state.lock().unwrap();
+            (
+                state.local_candidates[0].address,
+                state.local_candidates[1].foundation.clone(),
+            )
+        };
+        let expected = format!(
+            "{} 1 udp 9437183 192.0.2.1 50000 typ relay raddr 127.0.0.1 rport {}",
+            foundation,
+            base.port()
+        );
+        assert_eq!(attributes[1], sdp::Attribute::value("candidate", &expected));
+        assert!(agent.state.lock().unwrap().allocation(relayed).is_some());
+    }
+
+    // Only the relayed candidate of the controlling agent is kept, so the
+    // checks have to go through the TURN server.
+    #[tokio::test]
+    async fn relayed_agents_on_loopback() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let turn_server = socket.local_addr().unwrap();
+        let server = stun::TurnServer::new(IpAddr::from([127, 0, 0, 1]))
+            .with_realm("example.org")
+            .and_user("user", "password");
+        task::spawn(server.serve(socket));
+
+        let loopback = vec![IpAddr::from([127, 0, 0, 1])];
+        let mut controlling = Agent::new()
+            .with_role(Role::Controlling)
+            .with_local_addrs(loopback.clone())
+            .with_turn_servers(vec![TurnServer::new(turn_server, "user", "password")]);
+        let mut controlled = Agent::new()
+            .with_role(Role::Controlled)
+            .with_local_addrs(loopback);
+
+        controlling.gather().await;
+        controlled.gather().await;
+
+        let relayed = {
+            let mut state = controlling.state.lock().unwrap();
+            state
+                .local_candidates
+                .retain(|c| c.ty == CandidateType::Relayed);
+            assert_eq!(state.local_candidates.len(), 1);
+
+            state.local_candidates[0].address
+        };
+
+        let ((local, remote), (remote_local, remote_remote)) =
+            connect(&mut controlling, &mut controlled).await;
+        assert_eq!(local, relayed);
+        assert_eq!(local, remote_remote);
+        assert_eq!(remote, remote_local);
+    }
+
+    #[test]
+    fn username_must_match_credentials() {
+        let mut state = State::default();
+        state.local_credentials.ufrag = "abcd".to_string();
+        assert!(state.accepts_username("abcd:wxyz"));
+        assert!(!state.accepts_username("wxyz:abcd"));
+        assert!(!state.accepts_username("abcd"));
+
+        state.remote_credentials = Some(Credentials {
+            ufrag: "wxyz".to_string(),
+            pwd: "password".to_string(),
+        });
+        assert!(state.accepts_username("abcd:wxyz"));
+        assert!(!state.accepts_username("abcd:efgh"));
     }
 }
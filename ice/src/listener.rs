@@ -0,0 +1,337 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use fehler::throws;
+use log::{debug, trace, warn};
+use tokio::task::{self, JoinHandle};
+
+use crate::{
+    bind_any,
+    check::error_response,
+    dispatch::{handle_message, handler, Handler},
+    net::UdpConn,
+    relay::{handle_channel_data, handle_data},
+    Error, Event, State, Transport, MTU,
+};
+
+// Counts of the packets the listeners have had to drop, and of the
+// sends and receives that failed.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ListenerErrors {
+    pub receive: u64,
+    pub send: u64,
+    pub unrecognised: u64,
+    pub malformed: u64,
+    pub unknown_attributes: u64,
+}
+
+// ICMP errors caused by earlier sends can be reported by a later receive,
+// and shouldn't take the listener down with them.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+#[throws]
+pub(crate) async fn udp_listener(
+    address: &IpAddr,
+    ports: &[u16],
+    state: Arc<Mutex<State>>,
+) -> (SocketAddr, JoinHandle<()>) {
+    debug!("Starting UDP listener on {}", address);
+
+    let net = Arc::clone(&state.lock().unwrap().net);
+    let socket = bind_any(&*net, address, ports, |address| net.bind_udp(address))
+        .map_err(|source| Error::BindFailed { source })?;
+    let local_addr = socket
+        .local_addr()
+        .map_err(|source| Error::BindFailed { source })?;
+    debug!("Socket bound to {}", local_addr);
+
+    let client = stun::Client::new(Arc::clone(&socket).into_transport());
+    state
+        .lock()
+        .unwrap()
+        .clients
+        .insert(local_addr, client.clone());
+
+    let handle = task::spawn(async move {
+        if let Err(source) = receive_loop(&*socket, local_addr, &client, &state).await {
+            warn!("UDP listener on {} failed: {}", local_addr, source);
+            state.lock().unwrap().emit(Event::ListenerFailed {
+                address: local_addr,
+                source,
+            });
+        }
+    });
+
+    (local_addr, handle)
+}
+
+// Only returns if receiving fails for good.
+async fn receive_loop(
+    socket: &dyn UdpConn,
+    local_addr: SocketAddr,
+    client: &stun::Client<dyn stun::Transport>,
+    state: &Mutex<State>,
+) -> io::Result<()> {
+    let mut buf = [0; MTU];
+    loop {
+        let (bytes_rcvd, src_addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) if is_transient(&err) => {
+                debug!("Failed to receive on {}: {}", local_addr, err);
+                state.lock().unwrap().listener_errors.receive += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let packet = &buf[..bytes_rcvd];
+        trace!(
+            "Received {} bytes from {} on {}: {:02X?}",
+            bytes_rcvd,
+            src_addr,
+            local_addr,
+            packet
+        );
+
+        // https://tools.ietf.org/html/rfc7983#section-7
+        match stun::demultiplex(packet) {
+            Some(stun::Protocol::Stun) => {}
+            Some(stun::Protocol::ChannelData) => {
+                handle_channel_data(state, local_addr, src_addr, packet).await;
+                continue;
+            }
+            Some(protocol) => {
+                trace!("Ignoring {:?} packet from {}", protocol, src_addr);
+                continue;
+            }
+            None => {
+                debug!("Dropping unrecognised packet from {}", src_addr);
+                state.lock().unwrap().listener_errors.unrecognised += 1;
+                continue;
+            }
+        }
+
+        let message = match stun::message(packet) {
+            Ok((_, message)) => message,
+            Err(err) => {
+                warn!("Failed to decode message from {}: {}", src_addr, err);
+                if let Some(reply) = reject_undecodable(state, packet) {
+                    send_reply(socket, state, &reply, src_addr).await;
+                }
+                continue;
+            }
+        };
+
+        let maybe_reply = match handler(&message.header) {
+            // responses to our own requests are picked up by the client
+            Some(Handler::Response) => {
+                if let Some(response) = client.handle_response(message) {
+                    debug!("Dropping unexpected response: {:?}", response);
+                }
+                continue;
+            }
+            // checks relayed by a TURN server arrive wrapped in Data indications
+            Some(Handler::Data) => {
+                handle_data(state, local_addr, src_addr, &message).await;
+                continue;
+            }
+            _ => {
+                let mut state = state.lock().unwrap();
+                handle_message(&mut state, Transport::Udp, local_addr, src_addr, &message)
+            }
+        };
+
+        if let Some(reply) = maybe_reply {
+            send_reply(socket, state, &reply, src_addr).await;
+        }
+    }
+}
+
+async fn send_reply(
+    socket: &dyn UdpConn,
+    state: &Mutex<State>,
+    reply: &stun::Message,
+    target: SocketAddr,
+) {
+    trace!("Sending reply to {}: {:?}", target, reply);
+
+    if let Err(err) = socket.send_to(&reply.to_bytes(), target).await {
+        warn!("Failed to send reply to {}: {}", target, err);
+        state.lock().unwrap().listener_errors.send += 1;
+    }
+}
+
+// The comprehension-required attributes of a message that we don't
+// understand.
+fn unknown_attributes(packet: &[u8]) -> Vec<u16> {
+    let message = match stun::message_ref(packet) {
+        Ok((_, message)) => message,
+        Err(_) => return vec![],
+    };
+
+    message
+        .attributes()
+        .filter(|attribute| attribute.typ < 0x_8000)
+        .filter(|attribute| match attribute.decode() {
+            Err(stun::ParseError::Stun(err)) => {
+                matches!(err, stun::Error::UnimplementedAttribute(_))
+            }
+            _ => false,
+        })
+        .map(|attribute| attribute.typ)
+        .collect()
+}
+
+// Counts a message that couldn't be decoded, and works out the error
+// response if it was a request. Requests with unknown
+// comprehension-required attributes get a 420, and any other we can't
+// decode get a 400.
+//
+// https://tools.ietf.org/html/rfc5389#section-7.3.1
+pub(crate) fn reject_undecodable(state: &Mutex<State>, packet: &[u8]) -> Option<stun::Message> {
+    let unknown_attributes = unknown_attributes(packet);
+
+    {
+        let mut state = state.lock().unwrap();
+        if unknown_attributes.is_empty() {
+            state.listener_errors.malformed += 1;
+        } else {
+            state.listener_errors.unknown_attributes += 1;
+        }
+    }
+
+    let (_, header) = stun::header(packet).ok()?;
+    if header.class != stun::Class::Request {
+        return None;
+    }
+
+    let reply = if unknown_attributes.is_empty() {
+        error_response(&header, stun::NumericCode::BadRequest, "Bad Request")
+    } else {
+        error_response(
+            &header,
+            stun::NumericCode::UnknownAttribute,
+            "Unknown Attribute",
+        )
+        .and_attribute(stun::Attribute::unknown_attributes(unknown_attributes))
+    };
+
+    Some(reply.with_fingerprint())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{net::UdpSocket, time};
+
+    use super::*;
+
+    fn error_code(response: &stun::Message) -> Option<stun::NumericCode> {
+        response
+            .attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                stun::Attribute::ErrorCode(error_code) => Some(error_code.numeric_code()),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn unknown_attributes_get_420() {
+        let state = Mutex::new(State::default());
+
+        #[rustfmt::skip]
+        let request = [
+            0x_00, 0x_01, 0x_00, 0x_08,
+            0x_21, 0x_12, 0x_A4, 0x_42,
+            0x_00, 0x_01, 0x_02, 0x_03,
+            0x_04, 0x_05, 0x_06, 0x_07,
+            0x_08, 0x_09, 0x_0A, 0x_0B,
+            0x_00, 0x_31, 0x_00, 0x_04,
+            0x_DE, 0x_AD, 0x_BE, 0x_EF,
+        ];
+
+        let reply = reject_undecodable(&state, &request).unwrap();
+        assert_eq!(reply.header.class, stun::Class::Error);
+        assert_eq!(
+            error_code(&reply),
+            Some(stun::NumericCode::UnknownAttribute)
+        );
+        assert!(reply
+            .attributes
+            .contains(&stun::Attribute::unknown_attributes(vec![0x_0031])));
+
+        assert_eq!(state.lock().unwrap().listener_errors.unknown_attributes, 1);
+    }
+
+    #[test]
+    fn malformed_requests_get_400() {
+        let state = Mutex::new(State::default());
+
+        // a PRIORITY attribute two bytes short
+        #[rustfmt::skip]
+        let request = [
+            0x_00, 0x_01, 0x_00, 0x_08,
+            0x_21, 0x_12, 0x_A4, 0x_42,
+            0x_00, 0x_01, 0x_02, 0x_03,
+            0x_04, 0x_05, 0x_06, 0x_07,
+            0x_08, 0x_09, 0x_0A, 0x_0B,
+            0x_00, 0x_24, 0x_00, 0x_02,
+            0x_DE, 0x_AD, 0x_00, 0x_00,
+        ];
+
+        let reply = reject_undecodable(&state, &request).unwrap();
+        assert_eq!(error_code(&reply), Some(stun::NumericCode::BadRequest));
+
+        // responses are dropped without one
+        let mut response = request;
+        response[1] = 0x_01;
+        response[0] = 0x_01;
+        assert!(reject_undecodable(&state, &response).is_none());
+
+        assert_eq!(state.lock().unwrap().listener_errors.malformed, 2);
+    }
+
+    #[tokio::test]
+    async fn listener_replies_to_bad_checks() {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (address, _handle) =
+            udp_listener(&IpAddr::from([127, 0, 0, 1]), &[0], Arc::clone(&state))
+                .await
+                .unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&[0x_FF, 0x_FF], address).await.unwrap();
+
+        // a check without USERNAME or MESSAGE-INTEGRITY
+        let request = stun::Message::base(stun::Header::new(
+            stun::Method::Binding,
+            stun::Class::Request,
+            stun::TransactionId::new(),
+        ))
+        .with_fingerprint();
+        socket.send_to(&request.to_bytes(), address).await.unwrap();
+
+        let mut buf = [0; MTU];
+        let (bytes_rcvd, _) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let (_, reply) = stun::message(&buf[..bytes_rcvd]).unwrap();
+        assert_eq!(reply.header.transaction_id, request.header.transaction_id);
+        assert_eq!(error_code(&reply), Some(stun::NumericCode::BadRequest));
+
+        assert_eq!(state.lock().unwrap().listener_errors.unrecognised, 1);
+    }
+}
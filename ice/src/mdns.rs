@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, trace, warn};
+use nom::{
+    bytes::complete::take,
+    error::{Error, ErrorKind},
+    multi::count,
+    number::complete::{be_u16, be_u32, be_u8},
+    sequence::tuple,
+    IResult,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, sync::oneshot, time};
+
+use crate::{ConnectionAddress, State, MTU};
+
+// https://tools.ietf.org/html/rfc6762#section-3
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+// The top bit of the class is the unicast-response bit in questions, and
+// the cache-flush bit in answers, neither of which we need.
+//
+// https://tools.ietf.org/html/rfc6762#section-18.12
+const CLASS_MASK: u16 = 0x_7FFF;
+const CACHE_FLUSH: u16 = 0x_8000;
+
+// QR and AA
+const RESPONSE_FLAGS: u16 = 0x_8400;
+
+// https://tools.ietf.org/html/rfc6762#section-10
+const TTL: u32 = 120;
+
+// Pointers can only point backwards, so a well-formed name can't follow
+// more of them than there are bytes in a message.
+const MAX_POINTERS: usize = MTU / 2;
+
+const QUERY_ATTEMPTS: usize = 3;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Whether the agent resolves remote .local candidates, and whether it
+// also hides its own host addresses behind mDNS names.
+//
+// https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02#section-3
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum MdnsMode {
+    #[default]
+    Disabled,
+    QueryOnly,
+    QueryAndGather,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Question {
+    name: String,
+    typ: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Answer {
+    name: String,
+    address: IpAddr,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Packet {
+    response: bool,
+    questions: Vec<Question>,
+    answers: Vec<Answer>,
+}
+
+//                                 1  1  1  1  1  1
+//   0  1  2  3  4  5  6  7  8  9  0  1  2  3  4  5
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |                      ID                       |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |QR|   Opcode  |AA|TC|RD|RA|   Z    |   RCODE   |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |                    QDCOUNT                    |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |                    ANCOUNT                    |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |                    NSCOUNT                    |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |                    ARCOUNT                    |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//
+// Only questions and answers are decoded, and answers other than A and
+// AAAA records are skipped.
+//
+// https://tools.ietf.org/html/rfc1035#section-4.1.1
+fn packet(message: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, (_, flags, qdcount, ancount, _, _)) =
+        tuple((be_u16, be_u16, be_u16, be_u16, be_u16, be_u16))(message)?;
+    let (input, questions) = count(question(message), usize::from(qdcount))(input)?;
+    let (input, answers) = count(resource_record(message), usize::from(ancount))(input)?;
+
+    let packet = Packet {
+        response: flags & 0x_8000 != 0,
+        questions,
+        answers: answers.into_iter().flatten().collect(),
+    };
+
+    Ok((input, packet))
+}
+
+// Names are a sequence of labels ending in either an empty label, or a
+// pointer to the rest of the name elsewhere in the message.
+//
+// https://tools.ietf.org/html/rfc1035#section-4.1.4
+fn name<'a>(message: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], String> {
+    move |input| {
+        let mut labels = vec![];
+        let mut remainder = input;
+        let mut after_pointer = None;
+        let mut pointers = 0;
+        loop {
+            let (rest, length) = be_u8(remainder)?;
+            match length {
+                0 => {
+                    remainder = rest;
+                    break;
+                }
+                0x_01..=0x_3F => {
+                    let (rest, label) = take(length)(rest)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    remainder = rest;
+                }
+                0x_C0..=0x_FF => {
+                    let (rest, low) = be_u8(rest)?;
+                    let offset = usize::from(length & 0x_3F) << 8 | usize::from(low);
+
+                    pointers += 1;
+                    if pointers > MAX_POINTERS || offset >= message.len() {
+                        return Err(nom::Err::Error(Error::new(input, ErrorKind::Verify)));
+                    }
+
+                    after_pointer.get_or_insert(rest);
+                    remainder = &message[offset..];
+                }
+                _ => return Err(nom::Err::Error(Error::new(input, ErrorKind::Verify))),
+            }
+        }
+
+        Ok((after_pointer.unwrap_or(remainder), labels.join(".")))
+    }
+}
+
+// https://tools.ietf.org/html/rfc1035#section-4.1.2
+fn question<'a>(message: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Question> {
+    move |input| {
+        let (input, (name, typ, _)) = tuple((name(message), be_u16, be_u16))(input)?;
+
+        Ok((input, Question { name, typ }))
+    }
+}
+
+// https://tools.ietf.org/html/rfc1035#section-4.1.3
+fn resource_record<'a>(
+    message: &'a [u8],
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Option<Answer>> {
+    move |input| {
+        let (input, (name, typ, class, _, rdlength)) =
+            tuple((name(message), be_u16, be_u16, be_u32, be_u16))(input)?;
+        let (input, rdata) = take(rdlength)(input)?;
+
+        let address = match (typ, class & CLASS_MASK, rdata.len()) {
+            (TYPE_A, CLASS_IN, 4) => {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(rdata);
+                IpAddr::from(octets)
+            }
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(rdata);
+                IpAddr::from(octets)
+            }
+            _ => return Ok((input, None)),
+        };
+
+        Ok((input, Some(Answer { name, address })))
+    }
+}
+
+fn encode_name(name: &str, buf: &mut Vec<u8>) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        // SAFE: we only ever encode names of our own making, or that we've
+        // decoded, and labels are at most 63 bytes long either way
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+impl Packet {
+    fn to_bytes(&self) -> Vec<u8> {
+        let flags = if self.response { RESPONSE_FLAGS } else { 0 };
+
+        let mut buf = vec![0x_00, 0x_00];
+        buf.extend_from_slice(&flags.to_be_bytes());
+        buf.extend_from_slice(&(self.questions.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&(self.answers.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&[0x_00; 4]);
+
+        for question in &self.questions {
+            encode_name(&question.name, &mut buf);
+            buf.extend_from_slice(&question.typ.to_be_bytes());
+            buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+
+        for answer in &self.answers {
+            let (typ, rdata) = match answer.address {
+                IpAddr::V4(addr) => (TYPE_A, addr.octets().to_vec()),
+                IpAddr::V6(addr) => (TYPE_AAAA, addr.octets().to_vec()),
+            };
+
+            encode_name(&answer.name, &mut buf);
+            buf.extend_from_slice(&typ.to_be_bytes());
+            buf.extend_from_slice(&(CLASS_IN | CACHE_FLUSH).to_be_bytes());
+            buf.extend_from_slice(&TTL.to_be_bytes());
+            buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&rdata);
+        }
+
+        buf
+    }
+}
+
+// Names are version 4 UUIDs, so that they give nothing away about the
+// host they stand for.
+//
+// https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02#section-3.1.1
+fn random_name() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x_0F) | 0x_40;
+    bytes[8] = (bytes[8] & 0x_3F) | 0x_80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}.local",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// A multicast DNS responder for the names we've registered, which also
+// resolves the names of remote candidates.
+#[derive(Debug)]
+pub(crate) struct Mdns {
+    socket: UdpSocket,
+    group: SocketAddr,
+    names: Mutex<HashMap<String, IpAddr>>,
+    queries: Mutex<HashMap<String, Vec<oneshot::Sender<IpAddr>>>>,
+}
+
+impl Mdns {
+    // Other responders on the host will be listening on the same port,
+    // so the socket has to share it.
+    pub(crate) fn bind(interface: Ipv4Addr) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+        socket.join_multicast_v4(&MDNS_GROUP, &interface)?;
+        socket.set_multicast_if_v4(&interface)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket.into())?,
+            group: SocketAddr::from((MDNS_GROUP, MDNS_PORT)),
+            names: Mutex::new(HashMap::new()),
+            queries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Returns a new name that we'll answer for with the given address.
+    pub(crate) fn register(&self, address: IpAddr) -> String {
+        let name = random_name();
+        self.names.lock().unwrap().insert(name.clone(), address);
+
+        name
+    }
+
+    // https://tools.ietf.org/html/rfc6762#section-5.1
+    pub(crate) async fn resolve(&self, name: &str) -> Option<IpAddr> {
+        let name = name.to_ascii_lowercase();
+        let query = Packet {
+            response: false,
+            questions: vec![
+                Question {
+                    name: name.clone(),
+                    typ: TYPE_A,
+                },
+                Question {
+                    name: name.clone(),
+                    typ: TYPE_AAAA,
+                },
+            ],
+            answers: vec![],
+        }
+        .to_bytes();
+
+        for _ in 0..QUERY_ATTEMPTS {
+            let (sender, receiver) = oneshot::channel();
+            self.queries
+                .lock()
+                .unwrap()
+                .entry(name.clone())
+                .or_default()
+                .push(sender);
+
+            if let Err(err) = self.socket.send_to(&query, self.group).await {
+                warn!("Failed to query for {}: {}", name, err);
+                return None;
+            }
+
+            if let Ok(Ok(address)) = time::timeout(QUERY_TIMEOUT, receiver).await {
+                return Some(address);
+            }
+        }
+
+        self.queries.lock().unwrap().remove(&name);
+
+        None
+    }
+
+    pub(crate) async fn run(self: Arc<Self>) {
+        let mut buf = [0; MTU];
+        loop {
+            let (bytes_rcvd, source) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!("Failed to receive mDNS packet: {}", err);
+                    continue;
+                }
+            };
+
+            let packet = match packet(&buf[..bytes_rcvd]) {
+                Ok((_, packet)) => packet,
+                Err(_) => {
+                    trace!("Dropping malformed mDNS packet from {}", source);
+                    continue;
+                }
+            };
+
+            if packet.response {
+                self.handle_response(packet);
+            } else if let Some(response) = self.handle_query(packet) {
+                if let Err(err) = self.socket.send_to(&response.to_bytes(), self.group).await {
+                    warn!("Failed to send mDNS response: {}", err);
+                }
+            }
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc6762#section-6
+    fn handle_query(&self, query: Packet) -> Option<Packet> {
+        let names = self.names.lock().unwrap();
+        let answers: Vec<Answer> = query
+            .questions
+            .iter()
+            .filter_map(|question| {
+                let name = question.name.to_ascii_lowercase();
+                let address = *names.get(&name)?;
+                let typ = match address {
+                    IpAddr::V4(_) => TYPE_A,
+                    IpAddr::V6(_) => TYPE_AAAA,
+                };
+                if question.typ != typ && question.typ != TYPE_ANY {
+                    return None;
+                }
+
+                Some(Answer { name, address })
+            })
+            .collect();
+
+        if answers.is_empty() {
+            return None;
+        }
+        debug!("Answering mDNS query with {:?}", answers);
+
+        Some(Packet {
+            response: true,
+            questions: vec![],
+            answers,
+        })
+    }
+
+    fn handle_response(&self, response: Packet) {
+        let mut queries = self.queries.lock().unwrap();
+        for answer in response.answers {
+            let name = answer.name.to_ascii_lowercase();
+            for sender in queries.remove(&name).into_iter().flatten() {
+                let _ = sender.send(answer.address);
+            }
+        }
+    }
+}
+
+// Resolves the .local candidates with the given name, and pairs them if
+// checks have already started.
+pub(crate) async fn resolve_candidates(state: Arc<Mutex<State>>, mdns: Arc<Mdns>, name: String) {
+    let address = match mdns.resolve(&name).await {
+        Some(address) => address,
+        None => {
+            warn!("Failed to resolve {}", name);
+            return;
+        }
+    };
+    debug!("Resolved {} to {}", name, address);
+
+    let mut state = state.lock().unwrap();
+    let hostname = ConnectionAddress::Hostname(name);
+    for candidate in &mut state.remote_candidates {
+        if candidate.connection_address == hostname {
+            candidate.connection_address = ConnectionAddress::Ip(address);
+        }
+    }
+
+    state.update_checklist();
+}
+
+// The related address of a candidate whose base is hidden behind an mDNS
+// name is replaced with the unspecified address and a port of 0.
+//
+// https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02#section-3.1.2.2
+pub(crate) fn hidden_address(address: SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_compressed_response() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_00, 0x_84, 0x_00,
+            0x_00, 0x_01, 0x_00, 0x_01,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            // abcd.local, type A, class IN
+            0x_04, 0x_61, 0x_62, 0x_63,
+            0x_64, 0x_05, 0x_6C, 0x_6F,
+            0x_63, 0x_61, 0x_6C, 0x_00,
+            0x_00, 0x_01, 0x_00, 0x_01,
+            // a pointer to the name above, type A, class IN with cache
+            // flush, a TTL of 120 and 192.0.2.1
+            0x_C0, 0x_0C, 0x_00, 0x_01,
+            0x_80, 0x_01, 0x_00, 0x_00,
+            0x_00, 0x_78, 0x_00, 0x_04,
+            0x_C0, 0x_00, 0x_02, 0x_01,
+        ];
+
+        let (remainder, packet) = packet(&input).unwrap();
+        assert!(remainder.is_empty());
+        assert!(packet.response);
+        assert_eq!(
+            packet.questions,
+            vec![Question {
+                name: "abcd.local".to_string(),
+                typ: TYPE_A
+            }]
+        );
+        assert_eq!(
+            packet.answers,
+            vec![Answer {
+                name: "abcd.local".to_string(),
+                address: IpAddr::from([192, 0, 2, 1]),
+            }]
+        );
+    }
+
+    #[test]
+    fn pointer_loops_are_rejected() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_01, 0x_00, 0x_00,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_C0, 0x_0C, 0x_00, 0x_01,
+            0x_00, 0x_01,
+        ];
+
+        assert!(packet(&input).is_err());
+    }
+
+    #[test]
+    fn round_trip_packet() {
+        let expected = Packet {
+            response: true,
+            questions: vec![],
+            answers: vec![Answer {
+                name: random_name(),
+                address: "2001:db8::1".parse().unwrap(),
+            }],
+        };
+
+        let (_, actual) = packet(&expected.to_bytes()).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn names_are_uuids() {
+        let name = random_name();
+        let uuid = name.strip_suffix(".local").unwrap();
+
+        let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&uuid[14..15], "4");
+    }
+
+    // Needs multicast on the loopback interface.
+    #[tokio::test]
+    async fn resolve_on_loopback() {
+        let responder = Arc::new(Mdns::bind(Ipv4Addr::LOCALHOST).unwrap());
+        let resolver = Arc::new(Mdns::bind(Ipv4Addr::LOCALHOST).unwrap());
+        tokio::spawn(Arc::clone(&responder).run());
+        tokio::spawn(Arc::clone(&resolver).run());
+
+        let address = IpAddr::from([192, 0, 2, 1]);
+        let name = responder.register(address);
+
+        assert_eq!(resolver.resolve(&name).await, Some(address));
+        assert_eq!(resolver.resolve(&random_name()).await, None);
+    }
+}
@@ -0,0 +1,610 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use log::trace;
+use pnet::datalink;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, Mutex as AsyncMutex},
+    task, time,
+};
+
+// Ephemeral ports are handed out from here by the virtual network.
+//
+// https://tools.ietf.org/html/rfc6335#section-6
+const EPHEMERAL_PORTS: u16 = 49152;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub up: bool,
+    pub loopback: bool,
+    pub ips: Vec<IpAddr>,
+}
+
+// A UDP socket, real or virtual, that the agent listens and sends on.
+#[async_trait]
+pub trait UdpConn: stun::Transport + fmt::Debug {
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    // Lets the socket be shared with the STUN clients sending over it.
+    fn into_transport(self: Arc<Self>) -> Arc<dyn stun::Transport>;
+}
+
+// The interfaces the agent gathers on, and the UDP sockets it binds to
+// them. Only UDP goes through here: TCP candidates and mDNS always use
+// the host's own sockets, so they're out of reach of a virtual network.
+pub trait Net: Send + Sync + fmt::Debug {
+    fn interfaces(&self) -> Vec<Interface>;
+
+    fn bind_udp(&self, address: SocketAddr) -> io::Result<Arc<dyn UdpConn>>;
+}
+
+#[async_trait]
+impl UdpConn for UdpSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf).await
+    }
+
+    fn into_transport(self: Arc<Self>) -> Arc<dyn stun::Transport> {
+        self
+    }
+}
+
+// The host's own interfaces and sockets.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemNet;
+
+impl Net for SystemNet {
+    fn interfaces(&self) -> Vec<Interface> {
+        datalink::interfaces()
+            .into_iter()
+            .map(|i| Interface {
+                up: i.is_up(),
+                loopback: i.is_loopback(),
+                ips: i.ips.iter().map(|ip| ip.ip()).collect(),
+                name: i.name,
+                index: i.index,
+            })
+            .collect()
+    }
+
+    fn bind_udp(&self, address: SocketAddr) -> io::Result<Arc<dyn UdpConn>> {
+        let socket = std::net::UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Arc::new(UdpSocket::from_std(socket)?))
+    }
+}
+
+// https://tools.ietf.org/html/rfc3489#section-5
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NatType {
+    // any external host can send to a mapping once it's been made
+    FullCone,
+    // only hosts that have been sent to can send back to a mapping
+    Restricted,
+    // only the addresses and ports that have been sent to can send back
+    PortRestricted,
+    // every destination gets its own mapping, which only it can send to
+    Symmetric,
+}
+
+#[derive(Debug)]
+struct Nat {
+    ty: NatType,
+    public: IpAddr,
+    // the public port mapped for an internal address, and the destination
+    // too for symmetric NATs
+    mappings: HashMap<(SocketAddr, Option<SocketAddr>), u16>,
+    // the internal address behind each public port
+    internal: HashMap<u16, SocketAddr>,
+    // the remote addresses each internal address has sent to
+    permissions: HashSet<(SocketAddr, SocketAddr)>,
+    next_port: u16,
+}
+
+impl Nat {
+    fn new(ty: NatType, public: IpAddr) -> Self {
+        Self {
+            ty,
+            public,
+            mappings: HashMap::new(),
+            internal: HashMap::new(),
+            permissions: HashSet::new(),
+            next_port: EPHEMERAL_PORTS,
+        }
+    }
+
+    fn outbound(&mut self, source: SocketAddr, destination: SocketAddr) -> SocketAddr {
+        let key = match self.ty {
+            NatType::Symmetric => (source, Some(destination)),
+            _ => (source, None),
+        };
+
+        let public = match self.mappings.get(&key) {
+            Some(port) => *port,
+            None => {
+                let port = self.next_port;
+                self.next_port = self.next_port.wrapping_add(1).max(EPHEMERAL_PORTS);
+                self.mappings.insert(key, port);
+                self.internal.insert(port, source);
+                port
+            }
+        };
+        self.permissions.insert((source, destination));
+
+        SocketAddr::new(self.public, public)
+    }
+
+    fn inbound(&self, source: SocketAddr, destination: SocketAddr) -> Option<SocketAddr> {
+        let internal = *self.internal.get(&destination.port())?;
+
+        let permitted = match self.ty {
+            NatType::FullCone => true,
+            NatType::Restricted => self
+                .permissions
+                .iter()
+                .any(|(from, to)| *from == internal && to.ip() == source.ip()),
+            NatType::PortRestricted => self.permissions.contains(&(internal, source)),
+            NatType::Symmetric => {
+                self.mappings.get(&(internal, Some(source))) == Some(&destination.port())
+            }
+        };
+
+        if permitted {
+            Some(internal)
+        } else {
+            None
+        }
+    }
+}
+
+// The public realm is None, and the private realm behind each NAT is its
+// index.
+type Realm = Option<usize>;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Debug)]
+struct Network {
+    sockets: HashMap<(Realm, SocketAddr), mpsc::UnboundedSender<Datagram>>,
+    nats: Vec<Nat>,
+    next_ports: HashMap<(Realm, IpAddr), u16>,
+    latency: Duration,
+    loss: f64,
+    // seeded, so that the same datagrams are lost on every run
+    rng: StdRng,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            sockets: HashMap::new(),
+            nats: vec![],
+            next_ports: HashMap::new(),
+            latency: Duration::from_secs(0),
+            loss: 0.0,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl Network {
+    fn bind(
+        &mut self,
+        network: &VirtualNetwork,
+        realm: Realm,
+        mut address: SocketAddr,
+    ) -> io::Result<VirtualSocket> {
+        if address.port() == 0 {
+            let next_port = self
+                .next_ports
+                .entry((realm, address.ip()))
+                .or_insert(EPHEMERAL_PORTS);
+            while self
+                .sockets
+                .contains_key(&(realm, SocketAddr::new(address.ip(), *next_port)))
+            {
+                *next_port = next_port.wrapping_add(1).max(EPHEMERAL_PORTS);
+            }
+            address.set_port(*next_port);
+        } else if self.sockets.contains_key(&(realm, address)) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already bound", address),
+            ));
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.sockets.insert((realm, address), sender);
+
+        Ok(VirtualSocket {
+            network: network.clone(),
+            realm,
+            address,
+            receiver: AsyncMutex::new(receiver),
+        })
+    }
+
+    // Works out where a datagram ends up, translating it through the NATs
+    // between its source and destination.
+    fn route(
+        &mut self,
+        realm: Realm,
+        source: SocketAddr,
+        destination: SocketAddr,
+    ) -> Option<(Realm, SocketAddr, SocketAddr)> {
+        if self.sockets.contains_key(&(realm, destination)) {
+            return Some((realm, source, destination));
+        }
+
+        let source = match realm {
+            Some(index) => self.nats[index].outbound(source, destination),
+            None => source,
+        };
+
+        match self
+            .nats
+            .iter()
+            .position(|nat| nat.public == destination.ip())
+        {
+            Some(index) => {
+                let internal = self.nats[index].inbound(source, destination)?;
+                Some((Some(index), source, internal))
+            }
+            None => Some((None, source, destination)),
+        }
+    }
+}
+
+// An in-memory network of hosts, some of which can be put behind NATs,
+// with packet loss and latency applied to everything sent over it. Only
+// UDP is simulated.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+impl VirtualNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.lock().unwrap().latency = latency;
+    }
+
+    // The probability of any one datagram being dropped.
+    pub fn set_loss(&self, loss: f64) {
+        self.inner.lock().unwrap().loss = loss.clamp(0.0, 1.0);
+    }
+
+    // Reseeds the choice of which datagrams are lost.
+    pub fn set_seed(&self, seed: u64) {
+        self.inner.lock().unwrap().rng = StdRng::seed_from_u64(seed);
+    }
+
+    // A host with a public address.
+    pub fn host(&self, address: IpAddr) -> VirtualHost {
+        VirtualHost {
+            network: self.clone(),
+            realm: None,
+            address,
+        }
+    }
+
+    pub fn nat(&self, ty: NatType, public: IpAddr) -> VirtualNat {
+        let mut inner = self.inner.lock().unwrap();
+        inner.nats.push(Nat::new(ty, public));
+
+        VirtualNat {
+            network: self.clone(),
+            index: inner.nats.len() - 1,
+        }
+    }
+
+    fn send(&self, realm: Realm, source: SocketAddr, buf: &[u8], destination: SocketAddr) {
+        let mut inner = self.inner.lock().unwrap();
+        let loss = inner.loss;
+        if loss > 0.0 && inner.rng.gen_bool(loss) {
+            trace!("Losing datagram from {} to {}", source, destination);
+            return;
+        }
+
+        let (realm, source, destination) = match inner.route(realm, source, destination) {
+            Some(route) => route,
+            None => {
+                trace!("Filtering datagram from {} to {}", source, destination);
+                return;
+            }
+        };
+        let sender = match inner.sockets.get(&(realm, destination)) {
+            Some(sender) => sender.clone(),
+            None => {
+                trace!("Nothing bound to {} for {}", destination, source);
+                return;
+            }
+        };
+
+        let datagram = (buf.to_vec(), source);
+        let latency = inner.latency;
+        if latency == Duration::from_secs(0) {
+            let _ = sender.send(datagram);
+        } else {
+            task::spawn(async move {
+                time::sleep(latency).await;
+                let _ = sender.send(datagram);
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VirtualNat {
+    network: VirtualNetwork,
+    index: usize,
+}
+
+impl VirtualNat {
+    // A host with a private address behind the NAT.
+    pub fn host(&self, address: IpAddr) -> VirtualHost {
+        VirtualHost {
+            network: self.network.clone(),
+            realm: Some(self.index),
+            address,
+        }
+    }
+}
+
+// A host on a virtual network, with a single interface.
+#[derive(Clone, Debug)]
+pub struct VirtualHost {
+    network: VirtualNetwork,
+    realm: Realm,
+    address: IpAddr,
+}
+
+impl Net for VirtualHost {
+    fn interfaces(&self) -> Vec<Interface> {
+        vec![Interface {
+            name: "veth0".to_string(),
+            index: 1,
+            up: true,
+            loopback: false,
+            ips: vec![self.address],
+        }]
+    }
+
+    fn bind_udp(&self, address: SocketAddr) -> io::Result<Arc<dyn UdpConn>> {
+        if address.ip() != self.address {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("{} isn't an address of this host", address.ip()),
+            ));
+        }
+
+        let socket = self
+            .network
+            .inner
+            .lock()
+            .unwrap()
+            .bind(&self.network, self.realm, address)?;
+
+        Ok(Arc::new(socket))
+    }
+}
+
+pub struct VirtualSocket {
+    network: VirtualNetwork,
+    realm: Realm,
+    address: SocketAddr,
+    receiver: AsyncMutex<mpsc::UnboundedReceiver<Datagram>>,
+}
+
+impl fmt::Debug for VirtualSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualSocket")
+            .field("realm", &self.realm)
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl Drop for VirtualSocket {
+    fn drop(&mut self) {
+        self.network
+            .inner
+            .lock()
+            .unwrap()
+            .sockets
+            .remove(&(self.realm, self.address));
+    }
+}
+
+#[async_trait]
+impl stun::Transport for VirtualSocket {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.network.send(self.realm, self.address, buf, target);
+        Ok(buf.len())
+    }
+}
+
+#[async_trait]
+impl UdpConn for VirtualSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.address)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (datagram, source) = self
+            .receiver
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        let bytes_rcvd = datagram.len().min(buf.len());
+        buf[..bytes_rcvd].copy_from_slice(&datagram[..bytes_rcvd]);
+        Ok((bytes_rcvd, source))
+    }
+
+    fn into_transport(self: Arc<Self>) -> Arc<dyn stun::Transport> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    async fn receive(socket: &Arc<dyn UdpConn>) -> Option<SocketAddr> {
+        let mut buf = [0; 16];
+        time::timeout(Duration::from_millis(100), socket.recv_from(&mut buf))
+            .await
+            .ok()
+            .map(|received| received.unwrap().1)
+    }
+
+    #[tokio::test]
+    async fn public_hosts_reach_each_other() {
+        let network = VirtualNetwork::new();
+        let a = network.host(IpAddr::from([192, 0, 2, 1]));
+        let b = network.host(IpAddr::from([192, 0, 2, 2]));
+
+        let a = a.bind_udp(address("192.0.2.1:0")).unwrap();
+        let b = b.bind_udp(address("192.0.2.2:5000")).unwrap();
+        assert_eq!(a.local_addr().unwrap(), address("192.0.2.1:49152"));
+
+        a.send_to(b"hello", b.local_addr().unwrap()).await.unwrap();
+        assert_eq!(receive(&b).await, Some(a.local_addr().unwrap()));
+
+        // no one's listening on this one
+        a.send_to(b"hello", address("192.0.2.2:5001"))
+            .await
+            .unwrap();
+        assert_eq!(receive(&b).await, None);
+    }
+
+    #[tokio::test]
+    async fn ports_are_only_bound_once() {
+        let network = VirtualNetwork::new();
+        let host = network.host(IpAddr::from([192, 0, 2, 1]));
+
+        let socket = host.bind_udp(address("192.0.2.1:5000")).unwrap();
+        let err = host.bind_udp(address("192.0.2.1:5000")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let err = host.bind_udp(address("192.0.2.2:5000")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+
+        drop(socket);
+        host.bind_udp(address("192.0.2.1:5000")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn nats_map_and_filter() {
+        let network = VirtualNetwork::new();
+        let public = network.host(IpAddr::from([192, 0, 2, 1]));
+        let first = public.bind_udp(address("192.0.2.1:5000")).unwrap();
+        let second = public.bind_udp(address("192.0.2.1:5001")).unwrap();
+        let third = public.bind_udp(address("192.0.2.1:5002")).unwrap();
+
+        let types = [
+            NatType::FullCone,
+            NatType::Restricted,
+            NatType::PortRestricted,
+            NatType::Symmetric,
+        ];
+        for (i, ty) in types.iter().enumerate() {
+            let public_ip = IpAddr::from([198, 51, 100, i as u8 + 1]);
+            let nat = network.nat(*ty, public_ip);
+            let private = nat.host(IpAddr::from([10, 0, 0, 1]));
+            let socket = private.bind_udp(address("10.0.0.1:0")).unwrap();
+
+            socket
+                .send_to(b"hello", first.local_addr().unwrap())
+                .await
+                .unwrap();
+            let mapped = receive(&first).await.unwrap();
+            assert_eq!(mapped.ip(), public_ip);
+
+            socket
+                .send_to(b"hello", second.local_addr().unwrap())
+                .await
+                .unwrap();
+            let other_mapped = receive(&second).await.unwrap();
+            assert_eq!(mapped == other_mapped, *ty != NatType::Symmetric);
+
+            // the host that was sent to can always send back
+            first.send_to(b"hello", mapped).await.unwrap();
+            assert!(receive(&socket).await.is_some());
+
+            // and so can its other ports, unless the NAT filters on them
+            third.send_to(b"hello", mapped).await.unwrap();
+            let received = receive(&socket).await.is_some();
+            assert_eq!(
+                received,
+                matches!(ty, NatType::FullCone | NatType::Restricted)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn loss_drops_datagrams() {
+        let network = VirtualNetwork::new();
+        let host = network.host(IpAddr::from([192, 0, 2, 1]));
+        let a = host.bind_udp(address("192.0.2.1:5000")).unwrap();
+        let b = host.bind_udp(address("192.0.2.1:5001")).unwrap();
+
+        network.set_loss(1.0);
+        a.send_to(b"hello", b.local_addr().unwrap()).await.unwrap();
+        assert_eq!(receive(&b).await, None);
+
+        network.set_loss(0.0);
+        network.set_latency(Duration::from_millis(50));
+        a.send_to(b"hello", b.local_addr().unwrap()).await.unwrap();
+        assert_eq!(receive(&b).await, Some(a.local_addr().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn seeded_loss_is_repeatable() {
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let network = VirtualNetwork::new();
+            network.set_loss(0.5);
+            network.set_seed(7);
+            let host = network.host(IpAddr::from([192, 0, 2, 1]));
+            let a = host.bind_udp(address("192.0.2.1:5000")).unwrap();
+            let b = host.bind_udp(address("192.0.2.1:5001")).unwrap();
+
+            let mut received = vec![];
+            for _ in 0..16 {
+                a.send_to(b"hello", b.local_addr().unwrap()).await.unwrap();
+                received.push(receive(&b).await.is_some());
+            }
+            runs.push(received);
+        }
+
+        assert_eq!(runs[0], runs[1]);
+        assert!(runs[0].contains(&true) && runs[0].contains(&false));
+    }
+}
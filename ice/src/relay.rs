@@ -0,0 +1,199 @@
+use std::{
+    cmp,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, trace, warn};
+use tokio::time;
+
+use crate::{
+    dispatch::{handle_message, handler, Handler},
+    listener::reject_undecodable,
+    State, Transport,
+};
+
+pub(crate) type Allocation = stun::Allocation<dyn stun::Transport>;
+
+// https://tools.ietf.org/html/rfc8656#section-7.1
+const ALLOCATION_LIFETIME: Duration = Duration::from_secs(600);
+
+// Permissions expire after five minutes, so refresh them (along with the
+// allocation) a minute before that.
+//
+// https://tools.ietf.org/html/rfc8656#section-9
+const PERMISSION_REFRESH: Duration = Duration::from_secs(240);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnServer {
+    address: SocketAddr,
+    username: String,
+    password: String,
+}
+
+impl TurnServer {
+    pub fn new(address: SocketAddr, username: &str, password: &str) -> Self {
+        Self {
+            address,
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub(crate) fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub(crate) async fn allocate(
+        &self,
+        client: stun::Client<dyn stun::Transport>,
+    ) -> Result<Allocation, stun::Error> {
+        Allocation::allocate(client, self.address, &self.username, &self.password).await
+    }
+}
+
+// An allocation on a TURN server, made from the socket bound to base.
+#[derive(Debug)]
+pub(crate) struct Relay {
+    pub(crate) base: SocketAddr,
+    pub(crate) allocation: Arc<Allocation>,
+}
+
+// Keeps the allocation, and any permissions installed on it, from
+// expiring.
+//
+// https://tools.ietf.org/html/rfc8656#section-8
+pub(crate) async fn keep_alive(allocation: Arc<Allocation>) {
+    loop {
+        time::sleep(cmp::min(allocation.lifetime() / 2, PERMISSION_REFRESH)).await;
+
+        match allocation.refresh(ALLOCATION_LIFETIME).await {
+            Ok(lifetime) => trace!(
+                "Refreshed allocation {} for {:?}",
+                allocation.relayed_address(),
+                lifetime
+            ),
+            Err(err) => {
+                warn!(
+                    "Failed to refresh allocation {}: {}",
+                    allocation.relayed_address(),
+                    err
+                );
+                break;
+            }
+        }
+
+        if let Err(err) = allocation.refresh_permissions().await {
+            warn!(
+                "Failed to refresh permissions on {}: {}",
+                allocation.relayed_address(),
+                err
+            );
+        }
+    }
+}
+
+// The relay, and the client for checks sent through it, whose TURN server
+// is at source.
+fn relay_from(
+    state: &Mutex<State>,
+    base: SocketAddr,
+    source: SocketAddr,
+) -> Option<(Arc<Allocation>, stun::Client<dyn stun::Transport>)> {
+    let state = state.lock().unwrap();
+    let relay = state
+        .relays
+        .iter()
+        .find(|relay| relay.base == base && relay.allocation.server() == source)?;
+    let client = state.clients.get(&relay.allocation.relayed_address())?;
+
+    Some((Arc::clone(&relay.allocation), client.clone()))
+}
+
+// Handles a Data indication received on base, if it came from the TURN
+// server of one of our relays.
+//
+// https://tools.ietf.org/html/rfc8656#section-11.4
+pub(crate) async fn handle_data(
+    state: &Mutex<State>,
+    base: SocketAddr,
+    source: SocketAddr,
+    indication: &stun::Message,
+) {
+    let (allocation, client) = match relay_from(state, base, source) {
+        Some(relay) => relay,
+        None => return,
+    };
+
+    if let Some((peer, data)) = allocation.peer_data(indication) {
+        handle_relayed(state, &allocation, &client, peer, data).await;
+    }
+}
+
+// Handles ChannelData received on base, if it came from the TURN server
+// of one of our relays.
+//
+// https://tools.ietf.org/html/rfc8656#section-12.6
+pub(crate) async fn handle_channel_data(
+    state: &Mutex<State>,
+    base: SocketAddr,
+    source: SocketAddr,
+    packet: &[u8],
+) {
+    let (allocation, client) = match relay_from(state, base, source) {
+        Some(relay) => relay,
+        None => return,
+    };
+
+    let channel_data = match stun::channel_data(packet) {
+        Ok((_, channel_data)) => channel_data,
+        Err(err) => {
+            warn!("Dropping malformed channel data from {}: {}", source, err);
+            return;
+        }
+    };
+
+    if let Some(peer) = allocation.channel(channel_data.channel_number) {
+        handle_relayed(state, &allocation, &client, peer, &channel_data.data).await;
+    }
+}
+
+// Data relayed from a peer is treated as though it had been received on
+// the relayed address.
+async fn handle_relayed(
+    state: &Mutex<State>,
+    allocation: &Allocation,
+    client: &stun::Client<dyn stun::Transport>,
+    peer: SocketAddr,
+    data: &[u8],
+) {
+    let maybe_reply = match stun::message(data) {
+        Ok((_, message)) => match handler(&message.header) {
+            // responses to checks sent through the relay are picked up by its client
+            Some(Handler::Response) => {
+                if let Some(response) = client.handle_response(message) {
+                    debug!("Dropping unexpected relayed response: {:?}", response);
+                }
+                return;
+            }
+            Some(Handler::Data) => return,
+            _ => {
+                let relayed_address = allocation.relayed_address();
+                let mut state = state.lock().unwrap();
+                handle_message(&mut state, Transport::Udp, relayed_address, peer, &message)
+            }
+        },
+        Err(err) => {
+            warn!("Dropping relayed data from {}: {}", peer, err);
+            reject_undecodable(state, data)
+        }
+    };
+
+    if let Some(reply) = maybe_reply {
+        debug!("Replying to relayed check from {}", peer);
+        if let Err(err) = stun::Transport::send_to(allocation, &reply.to_bytes(), peer).await {
+            warn!("Failed to relay reply to {}: {}", peer, err);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use rand::Rng;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Controlling,
+    Controlled,
+}
+
+impl Role {
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Controlling => Self::Controlled,
+            Self::Controlled => Self::Controlling,
+        }
+    }
+
+    pub(crate) fn attribute(self, tie_breaker: u64) -> stun::Attribute {
+        match self {
+            Self::Controlling => stun::Attribute::ice_controlling(tie_breaker),
+            Self::Controlled => stun::Attribute::ice_controlled(tie_breaker),
+        }
+    }
+}
+
+pub(crate) fn rand_tie_breaker() -> u64 {
+    rand::thread_rng().gen()
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Resolution {
+    NoConflict,
+    SwitchRole,
+    RoleConflictError,
+}
+
+// Decides what to do about an incoming Binding request whose
+// ICE-CONTROLLING/ICE-CONTROLLED attribute claims the same role as ours.
+//
+// https://tools.ietf.org/html/rfc8445#section-7.3.1.1
+pub(crate) fn resolve_conflict(
+    role: Role,
+    tie_breaker: u64,
+    attributes: &[stun::Attribute],
+) -> Resolution {
+    for attribute in attributes {
+        match (role, attribute) {
+            (Role::Controlling, stun::Attribute::IceControlling(remote)) => {
+                return if tie_breaker >= remote.tie_breaker() {
+                    Resolution::RoleConflictError
+                } else {
+                    Resolution::SwitchRole
+                };
+            }
+            (Role::Controlled, stun::Attribute::IceControlled(remote)) => {
+                return if tie_breaker >= remote.tie_breaker() {
+                    Resolution::SwitchRole
+                } else {
+                    Resolution::RoleConflictError
+                };
+            }
+            _ => continue,
+        }
+    }
+
+    Resolution::NoConflict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_conflict() {
+        let attributes = vec![stun::Attribute::ice_controlled(1)];
+        let actual = resolve_conflict(Role::Controlling, 2, &attributes);
+        assert_eq!(actual, Resolution::NoConflict);
+
+        let attributes = vec![stun::Attribute::ice_controlling(1)];
+        let actual = resolve_conflict(Role::Controlled, 2, &attributes);
+        assert_eq!(actual, Resolution::NoConflict);
+    }
+
+    #[test]
+    fn both_controlling() {
+        let attributes = vec![stun::Attribute::ice_controlling(1)];
+        let actual = resolve_conflict(Role::Controlling, 2, &attributes);
+        assert_eq!(actual, Resolution::RoleConflictError);
+
+        let actual = resolve_conflict(Role::Controlling, 0, &attributes);
+        assert_eq!(actual, Resolution::SwitchRole);
+    }
+
+    #[test]
+    fn both_controlled() {
+        let attributes = vec![stun::Attribute::ice_controlled(1)];
+        let actual = resolve_conflict(Role::Controlled, 2, &attributes);
+        assert_eq!(actual, Resolution::SwitchRole);
+
+        let actual = resolve_conflict(Role::Controlled, 0, &attributes);
+        assert_eq!(actual, Resolution::RoleConflictError);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use fehler::throws;
+use log::{debug, trace, warn};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpSocket, TcpStream,
+    },
+    sync::Mutex as AsyncMutex,
+    task::{self, JoinHandle},
+};
+
+use crate::{
+    bind_any,
+    dispatch::{handle_message, handler, Handler},
+    listener::reject_undecodable,
+    net::SystemNet,
+    Error, Event, State, TcpType, Transport,
+};
+
+// Active candidates never accept connections, so they're signalled with
+// the discard port.
+//
+// https://tools.ietf.org/html/rfc6544#section-4.5
+pub(crate) const DISCARD_PORT: u16 = 9;
+
+// Requests over TCP aren't retransmitted, but are still given up on
+// after Ti.
+//
+// https://tools.ietf.org/html/rfc5389#section-7.2.2
+const RETRANSMISSION: stun::Retransmission = stun::Retransmission {
+    rto: Duration::from_millis(500),
+    rc: 1,
+    rm: 79,
+};
+
+// Packets are framed with their length as a 16-bit unsigned integer.
+//
+//   0                   1                   2                   3
+//   0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//  -----------------------------------------------------------------
+//  |             LENGTH            |  STUN or other packet ...     |
+//  -----------------------------------------------------------------
+//
+// https://tools.ietf.org/html/rfc4571#section-2
+fn frame(packet: &[u8]) -> io::Result<Vec<u8>> {
+    if packet.len() > usize::from(u16::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "packet too long to frame",
+        ));
+    }
+
+    let mut buf = Vec::with_capacity(2 + packet.len());
+    buf.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    buf.extend_from_slice(packet);
+
+    Ok(buf)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let length = reader.read_u16().await?;
+    let mut packet = vec![0; usize::from(length)];
+    reader.read_exact(&mut packet).await?;
+
+    Ok(packet)
+}
+
+// The sending half of a connection, which only ever sends to its peer.
+#[derive(Debug)]
+struct Connection {
+    peer: SocketAddr,
+    writer: AsyncMutex<OwnedWriteHalf>,
+}
+
+#[async_trait]
+impl stun::Transport for Connection {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        if target != self.peer {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("connection is to {}, not {}", self.peer, target),
+            ));
+        }
+
+        let framed = frame(buf)?;
+        self.writer.lock().await.write_all(&framed).await?;
+
+        Ok(buf.len())
+    }
+}
+
+// Accepts connections to a passive candidate.
+#[throws]
+pub(crate) async fn tcp_listener(
+    address: &IpAddr,
+    ports: &[u16],
+    state: Arc<Mutex<State>>,
+) -> (SocketAddr, JoinHandle<()>) {
+    debug!("Starting TCP listener on {}", address);
+
+    let listener = bind_any(&SystemNet, address, ports, |address| {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener)
+    })
+    .map_err(|source| Error::BindFailed { source })?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|source| Error::BindFailed { source })?;
+    debug!("Listener bound to {}", local_addr);
+
+    let handle = task::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    debug!("Accepted connection from {} on {}", peer, local_addr);
+                    register(&state, local_addr, stream, peer);
+                }
+                Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => {
+                    debug!("Failed to accept on {}: {}", local_addr, err);
+                }
+                Err(source) => {
+                    warn!("TCP listener on {} failed: {}", local_addr, source);
+                    state.lock().unwrap().emit(Event::ListenerFailed {
+                        address: local_addr,
+                        source,
+                    });
+                    break;
+                }
+            }
+        }
+    });
+
+    (local_addr, handle)
+}
+
+fn tcp_socket(address: SocketAddr) -> io::Result<TcpSocket> {
+    match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }
+}
+
+// Simultaneous-open candidates connect from the port they were gathered
+// on, so every socket bound to it has to be able to share it.
+fn shared_socket(address: SocketAddr) -> io::Result<TcpSocket> {
+    let socket = tcp_socket(address)?;
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(address)?;
+
+    Ok(socket)
+}
+
+// Binds the port of a simultaneous-open candidate, returning the socket
+// that holds onto it.
+//
+// https://tools.ietf.org/html/rfc6544#section-5.1
+pub(crate) fn so_socket(address: &IpAddr, ports: &[u16]) -> io::Result<(SocketAddr, TcpSocket)> {
+    let socket = bind_any(&SystemNet, address, ports, shared_socket)?;
+    let local_addr = socket.local_addr()?;
+    debug!("Reserved {} for simultaneous-open", local_addr);
+
+    Ok((local_addr, socket))
+}
+
+// Opens a connection from an active or simultaneous-open candidate,
+// returning the client that checks are sent with.
+//
+// https://tools.ietf.org/html/rfc6544#section-7.1
+pub(crate) async fn connect(
+    state: &Arc<Mutex<State>>,
+    local: SocketAddr,
+    tcp_type: TcpType,
+    remote: SocketAddr,
+) -> io::Result<stun::Client<dyn stun::Transport>> {
+    let socket = match tcp_type {
+        TcpType::SimultaneousOpen => shared_socket(local)?,
+        _ => {
+            let socket = tcp_socket(local)?;
+            socket.bind(SocketAddr::new(local.ip(), 0))?;
+            socket
+        }
+    };
+
+    let stream = socket.connect(remote).await?;
+    debug!(
+        "Connected {} -> {} from {}",
+        local,
+        remote,
+        stream.local_addr()?
+    );
+
+    Ok(register(state, local, stream, remote))
+}
+
+// Keeps a connection to use for checks between base and peer, and
+// receives from it until it closes.
+fn register(
+    state: &Arc<Mutex<State>>,
+    base: SocketAddr,
+    stream: TcpStream,
+    peer: SocketAddr,
+) -> stun::Client<dyn stun::Transport> {
+    let (reader, writer) = stream.into_split();
+    let connection: Arc<dyn stun::Transport> = Arc::new(Connection {
+        peer,
+        writer: AsyncMutex::new(writer),
+    });
+    let client = stun::Client::new(connection).with_retransmission(RETRANSMISSION);
+
+    state
+        .lock()
+        .unwrap()
+        .connections
+        .insert((base, peer), client.clone());
+    task::spawn(receive_loop(
+        reader,
+        base,
+        peer,
+        client.clone(),
+        Arc::clone(state),
+    ));
+
+    client
+}
+
+async fn receive_loop(
+    mut reader: OwnedReadHalf,
+    base: SocketAddr,
+    peer: SocketAddr,
+    client: stun::Client<dyn stun::Transport>,
+    state: Arc<Mutex<State>>,
+) {
+    loop {
+        let packet = match read_frame(&mut reader).await {
+            Ok(packet) => packet,
+            Err(err) => {
+                debug!("Connection {} -> {} closed: {}", base, peer, err);
+                break;
+            }
+        };
+        trace!(
+            "Received {} bytes from {} on {}: {:02X?}",
+            packet.len(),
+            peer,
+            base,
+            packet
+        );
+
+        match stun::demultiplex(&packet) {
+            Some(stun::Protocol::Stun) => {}
+            Some(protocol) => {
+                trace!("Ignoring {:?} packet from {}", protocol, peer);
+                continue;
+            }
+            None => {
+                debug!("Dropping unrecognised packet from {}", peer);
+                state.lock().unwrap().listener_errors.unrecognised += 1;
+                continue;
+            }
+        }
+
+        let maybe_reply = match stun::message(&packet) {
+            Ok((_, message)) => match handler(&message.header) {
+                Some(Handler::Response) => {
+                    if let Some(response) = client.handle_response(message) {
+                        debug!("Dropping unexpected response: {:?}", response);
+                    }
+                    continue;
+                }
+                _ => {
+                    let mut state = state.lock().unwrap();
+                    handle_message(&mut state, Transport::Tcp, base, peer, &message)
+                }
+            },
+            Err(err) => {
+                warn!("Failed to decode message from {}: {}", peer, err);
+                reject_undecodable(&state, &packet)
+            }
+        };
+
+        if let Some(reply) = maybe_reply {
+            trace!("Sending reply to {}: {:?}", peer, reply);
+            if let Err(err) = client.transport().send_to(&reply.to_bytes(), peer).await {
+                warn!("Failed to send reply to {}: {}", peer, err);
+                state.lock().unwrap().listener_errors.send += 1;
+            }
+        }
+    }
+
+    state.lock().unwrap().connections.remove(&(base, peer));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let packets: &[&[u8]] = &[&[0x_00, 0x_01, 0x_00, 0x_00], &[], &[0x_FF; 300]];
+
+        let mut stream = vec![];
+        for packet in packets {
+            stream.extend(frame(packet).unwrap());
+        }
+        assert_eq!(&stream[..6], &[0x_00, 0x_04, 0x_00, 0x_01, 0x_00, 0x_00]);
+
+        let mut reader = &stream[..];
+        for packet in packets {
+            assert_eq!(read_frame(&mut reader).await.unwrap(), *packet);
+        }
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+
+    #[test]
+    fn oversized_packets_are_not_framed() {
+        assert!(frame(&vec![0; 0x_0001_0000]).is_err());
+    }
+}
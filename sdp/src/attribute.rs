@@ -26,12 +26,40 @@ impl Attribute {
         Self::Value(k.to_string(), v.to_owned())
     }
 
+    // https://tools.ietf.org/html/rfc8840#section-8.2
+    pub fn end_of_candidates() -> Self {
+        Self::property("end-of-candidates")
+    }
+
+    // https://tools.ietf.org/html/rfc8839#section-5.6
+    pub fn ice_options(options: &[&str]) -> Self {
+        Self::value("ice-options", &options.join(" "))
+    }
+
     pub fn is_ice_candidate(&self) -> bool {
         match self {
             Self::Value(k, _v) => k == "candidate",
             Self::Property(_) => false,
         }
     }
+
+    pub fn is_end_of_candidates(&self) -> bool {
+        match self {
+            Self::Property(p) => p == "end-of-candidates",
+            Self::Value(_, _) => false,
+        }
+    }
+
+    // The option tags of an ice-options attribute, which are separated by
+    // spaces.
+    pub fn ice_option_tags(&self) -> Vec<String> {
+        match self {
+            Self::Value(k, v) if k == "ice-options" => {
+                v.split_whitespace().map(str::to_string).collect()
+            }
+            _ => vec![],
+        }
+    }
 }
 
 impl fmt::Display for Attribute {
@@ -113,6 +141,21 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn ice_attributes() {
+        let attribute = attribute(Span::new("a=end-of-candidates\r\n")).unwrap().1;
+        assert!(attribute.is_end_of_candidates());
+        assert_eq!(attribute, Attribute::end_of_candidates());
+
+        let attribute = Attribute::ice_options(&["trickle", "renomination"]);
+        assert_eq!(
+            attribute.to_string(),
+            "a=ice-options:trickle renomination\r\n"
+        );
+        assert_eq!(attribute.ice_option_tags(), vec!["trickle", "renomination"]);
+        assert!(!attribute.is_end_of_candidates());
+    }
+
     #[test]
     fn parse_attribute() {
         let input = Span::new("a=msid-semantic: WMS stream\r\n");
@@ -110,6 +110,70 @@ impl SessionDescription {
 
         candidates
     }
+
+    // The ICE options given at either the session or the media level.
+    //
+    // https://tools.ietf.org/html/rfc8839#section-5.6
+    pub fn ice_options(&self) -> Vec<String> {
+        let media_attributes = self.media_descriptions.iter().flat_map(|m| &m.attributes);
+
+        let mut ice_options = vec![];
+        for attribute in self.attributes.iter().chain(media_attributes) {
+            for tag in attribute.ice_option_tags() {
+                if !ice_options.contains(&tag) {
+                    ice_options.push(tag);
+                }
+            }
+        }
+
+        ice_options
+    }
+
+    // The ice-ufrag and ice-pwd, from the media level if they're given
+    // there, or else the session level. New ones in a later description
+    // mean an ICE restart.
+    //
+    // https://tools.ietf.org/html/rfc8839#section-5.4
+    pub fn ice_credentials(&self) -> Option<(String, String)> {
+        let value = |attributes: &[Attribute], name: &str| {
+            attributes.iter().find_map(|attribute| match attribute {
+                Attribute::Value(k, v) if k == name => Some(v.clone()),
+                _ => None,
+            })
+        };
+
+        let media_credentials = self
+            .media_descriptions
+            .iter()
+            .find_map(|m| value(&m.attributes, "ice-ufrag").zip(value(&m.attributes, "ice-pwd")));
+
+        media_credentials.or_else(|| {
+            value(&self.attributes, "ice-ufrag").zip(value(&self.attributes, "ice-pwd"))
+        })
+    }
+
+    // https://tools.ietf.org/html/rfc8840#section-4.1.1
+    pub fn supports_trickle(&self) -> bool {
+        self.ice_options().iter().any(|tag| tag == "trickle")
+    }
+
+    // https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+    pub fn supports_renomination(&self) -> bool {
+        self.ice_options().iter().any(|tag| tag == "renomination")
+    }
+
+    // Whether the remote agent has signalled that it won't be trickling
+    // any more candidates.
+    //
+    // https://tools.ietf.org/html/rfc8840#section-8.2
+    pub fn end_of_candidates(&self) -> bool {
+        let media_attributes = self.media_descriptions.iter().flat_map(|m| &m.attributes);
+
+        self.attributes
+            .iter()
+            .chain(media_attributes)
+            .any(Attribute::is_end_of_candidates)
+    }
 }
 
 type SessionDescriptionArgs = (
@@ -414,4 +478,34 @@ a=rtpmap:99 h263-1998/90000
         let actual = SessionDescription::from_str(sdp)?;
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    #[throws]
+    fn trickle_attributes() {
+        let sdp = "v=0
+o=- 1433832402044130222 3 IN IP4 127.0.0.1
+s=-
+t=0 0
+a=ice-options:trickle
+m=audio 9 UDP/TLS/RTP/SAVPF 111
+a=ice-options:trickle renomination
+a=ice-ufrag:abcd
+a=ice-pwd:abcdefghijklmnopqrstuv
+a=candidate:1 1 udp 2122260223 192.0.2.1 54321 typ host
+a=end-of-candidates
+";
+        let session_description = SessionDescription::from_str(sdp)?;
+        assert_eq!(
+            session_description.ice_options(),
+            vec!["trickle", "renomination"]
+        );
+        assert!(session_description.supports_trickle());
+        assert!(session_description.supports_renomination());
+        assert!(session_description.end_of_candidates());
+        assert_eq!(session_description.candidates().len(), 1);
+        assert_eq!(
+            session_description.ice_credentials(),
+            Some(("abcd".to_string(), "abcdefghijklmnopqrstuv".to_string()))
+        );
+    }
 }
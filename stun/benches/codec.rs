@@ -0,0 +1,71 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use stun::{Attribute, Class, Header, Message, Method, TransactionId};
+
+const KEY: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+
+fn binding_request() -> Message {
+    Message::base(Header::new(
+        Method::Binding,
+        Class::Request,
+        TransactionId::new(),
+    ))
+    .with_attributes(vec![
+        Attribute::username("evtj:h6vY"),
+        Attribute::xor_mapped_address([192, 0, 2, 1].into(), 32853),
+    ])
+    .with_message_integrity(KEY)
+    .with_fingerprint()
+}
+
+fn decode(c: &mut Criterion) {
+    let bytes = binding_request().to_bytes();
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("message", |b| {
+        b.iter(|| stun::message(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("message_ref", |b| {
+        b.iter(|| {
+            let (_, message) = stun::message_ref(black_box(&bytes)).unwrap();
+            message.attributes().count()
+        })
+    });
+    group.finish();
+}
+
+fn verify(c: &mut Criterion) {
+    let bytes = binding_request().to_bytes();
+
+    let mut group = c.benchmark_group("verify");
+    group.bench_function("message", |b| {
+        b.iter(|| {
+            let (_, message) = stun::message(black_box(&bytes)).unwrap();
+            message.verify_integrity(KEY).unwrap();
+            message.verify_fingerprint().unwrap();
+        })
+    });
+    group.bench_function("message_ref", |b| {
+        b.iter(|| {
+            let (_, message) = stun::message_ref(black_box(&bytes)).unwrap();
+            message.verify_integrity(KEY).unwrap();
+            message.verify_fingerprint().unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let message = binding_request();
+
+    let mut group = c.benchmark_group("encode");
+    group.bench_function("to_bytes", |b| b.iter(|| black_box(&message).to_bytes()));
+    group.bench_function("encode", |b| {
+        let mut buf = [0_u8; 1500];
+        b.iter(|| black_box(&message).encode(&mut buf).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, verify, encode);
+criterion_main!(benches);
@@ -0,0 +1,140 @@
+use std::{
+    convert::TryInto,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use nom::{
+    bytes::complete::take,
+    number::complete::{be_u16, be_u8},
+    sequence::tuple,
+    IResult,
+};
+
+use crate::{Error, ParseError, TransactionId, MAGIC_COOKIE};
+
+const FAMILY_IPV4: u8 = 0x_01;
+const FAMILY_IPV6: u8 = 0x_02;
+
+pub(crate) fn address_length(address: &IpAddr) -> u16 {
+    match address {
+        IpAddr::V4(_) => 8,
+        IpAddr::V6(_) => 20,
+    }
+}
+
+pub(crate) fn encode_address(address: &IpAddr, port: u16, buf: &mut [u8]) {
+    buf[0] = 0x_00;
+    buf[2..4].copy_from_slice(&port.to_be_bytes());
+    match address {
+        IpAddr::V4(addr) => {
+            buf[1] = FAMILY_IPV4;
+            buf[4..8].copy_from_slice(&addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            buf[1] = FAMILY_IPV6;
+            buf[4..20].copy_from_slice(&addr.octets());
+        }
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |0 0 0 0 0 0 0 0|    Family     |           Port                |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                                                               |
+// |                 Address (32 bits or 128 bits)                 |
+// |                                                               |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+//         Figure 5: Format of MAPPED-ADDRESS Attribute
+//
+// https://tools.ietf.org/html/rfc8489#section-14.1
+pub(crate) fn address_and_port(input: &[u8]) -> IResult<&[u8], (IpAddr, u16), ParseError<&[u8]>> {
+    let (input, (_, family, port)) = tuple((be_u8, be_u8, be_u16))(input)?;
+
+    let (input, address) = match family {
+        FAMILY_IPV4 => {
+            let (input, address_field) = take(4_usize)(input)?;
+            // SAFE: we've just taken exactly 4 bytes
+            let octets: [u8; 4] = address_field.try_into().unwrap();
+
+            (input, IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        FAMILY_IPV6 => {
+            let (input, address_field) = take(16_usize)(input)?;
+            // SAFE: we've just taken exactly 16 bytes
+            let octets: [u8; 16] = address_field.try_into().unwrap();
+
+            (input, IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => return Err(nom::Err::Error(Error::InvalidAddressFamily(family).into())),
+    };
+
+    Ok((input, (address, port)))
+}
+
+// The X-Port is the port XOR'd with the most significant 16 bits of the
+// magic cookie, and an IPv4 X-Address is the address XOR'd with the magic
+// cookie. An IPv6 X-Address is XOR'd with the magic cookie followed by the
+// transaction ID, but attributes are coded without knowing the transaction
+// ID, so only the magic cookie is applied here, and the message applies
+// the rest with xor_transaction_id.
+//
+// https://tools.ietf.org/html/rfc8489#section-14.2
+fn xor_port(port: u16) -> u16 {
+    // SAFE: shifting leaves only 16 significant bits
+    let magic_cookie_upper_16: u16 = (MAGIC_COOKIE >> 16).try_into().unwrap();
+
+    port ^ magic_cookie_upper_16
+}
+
+pub(crate) fn encode_xor_address(address: &IpAddr, port: u16, buf: &mut [u8]) {
+    buf[0] = 0x_00;
+    buf[2..4].copy_from_slice(&xor_port(port).to_be_bytes());
+    match address {
+        IpAddr::V4(addr) => {
+            buf[1] = FAMILY_IPV4;
+            let x_address = u32::from(*addr) ^ MAGIC_COOKIE;
+            buf[4..8].copy_from_slice(&x_address.to_be_bytes());
+        }
+        IpAddr::V6(addr) => {
+            buf[1] = FAMILY_IPV6;
+            let x_address = u128::from(*addr) ^ (u128::from(MAGIC_COOKIE) << 96);
+            buf[4..20].copy_from_slice(&x_address.to_be_bytes());
+        }
+    }
+}
+
+pub(crate) fn xor_address_and_port(
+    input: &[u8],
+) -> IResult<&[u8], (IpAddr, u16), ParseError<&[u8]>> {
+    let (input, (x_address, x_port)) = address_and_port(input)?;
+
+    let address = match x_address {
+        IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) ^ MAGIC_COOKIE)),
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(
+            u128::from(addr) ^ (u128::from(MAGIC_COOKIE) << 96),
+        )),
+    };
+
+    Ok((input, (address, xor_port(x_port))))
+}
+
+// XORs the last 96 bits of an IPv6 address with the transaction ID, which
+// undoes itself when applied twice. IPv4 addresses are left alone.
+//
+// https://tools.ietf.org/html/rfc8489#section-14.2
+pub(crate) fn xor_transaction_id(address: IpAddr, transaction_id: &TransactionId) -> IpAddr {
+    match address {
+        IpAddr::V4(_) => address,
+        IpAddr::V6(addr) => {
+            let mut octets = addr.octets();
+            for (octet, mask) in octets[4..].iter_mut().zip(&transaction_id.0) {
+                *octet ^= mask;
+            }
+
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, string_value, Attribute, Tlv};
+
+const TYPE: u16 = 0x_8003;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlternateDomain(String);
+
+impl AlternateDomain {
+    pub fn new(domain: &str) -> Self {
+        // TODO: Ensure the domain is ASCII and < 256 bytes.
+        Self(domain.to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Tlv for AlternateDomain {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.16
+pub(crate) fn alternate_domain(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let value = string_value(value_field)?;
+
+    let inner = AlternateDomain(value);
+    let attribute = Attribute::AlternateDomain(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_03, 0x_00, 0x_10,
+            0x_73, 0x_74, 0x_75, 0x_6E,
+            0x_2E, 0x_65, 0x_78, 0x_61,
+            0x_6D, 0x_70, 0x_6C, 0x_65,
+            0x_2E, 0x_6F, 0x_72, 0x_67,
+        ];
+
+        let (_, attribute) = alternate_domain(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
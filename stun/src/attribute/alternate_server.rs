@@ -0,0 +1,98 @@
+use std::net::IpAddr;
+
+use nom::{
+    bytes::complete::tag, combinator::all_consuming, multi::length_data, number::complete::be_u16,
+    sequence::preceded, IResult,
+};
+
+use crate::attribute::{
+    address::{address_and_port, address_length, encode_address},
+    Attribute, Tlv,
+};
+
+const TYPE: u16 = 0x_8023;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlternateServer {
+    address: IpAddr,
+    port: u16,
+}
+
+impl AlternateServer {
+    pub fn new(address: IpAddr, port: u16) -> Self {
+        Self { address, port }
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Tlv for AlternateServer {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        address_length(&self.address)
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        encode_address(&self.address, self.port, buf);
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.15
+pub(crate) fn alternate_server(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, (address, port)) = all_consuming(address_and_port)(value_field)?;
+
+    let inner = AlternateServer { address, port };
+    let attribute = Attribute::AlternateServer(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_23, 0x_00, 0x_08,
+            0x_00, 0x_01, 0x_BE, 0x_EF,
+            0x_C0, 0x_00, 0x_02, 0x_01,
+        ];
+
+        let (_, attribute) = alternate_server(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn round_trip_bytes_ipv6() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_23, 0x_00, 0x_14,
+            0x_00, 0x_02, 0x_BE, 0x_EF,
+            0x_20, 0x_01, 0x_0D, 0x_B8,
+            0x_12, 0x_34, 0x_56, 0x_78,
+            0x_00, 0x_11, 0x_22, 0x_33,
+            0x_44, 0x_55, 0x_66, 0x_77,
+        ];
+
+        let (_, attribute) = alternate_server(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
@@ -0,0 +1,76 @@
+use nom::{
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::{pair, preceded},
+    IResult,
+};
+
+use crate::attribute::{Attribute, Tlv};
+
+const TYPE: u16 = 0x_000C;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelNumber(u16);
+
+impl ChannelNumber {
+    pub fn new(channel_number: u16) -> Self {
+        Self(channel_number)
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Tlv for ChannelNumber {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        4
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..2].copy_from_slice(&self.0.to_be_bytes());
+        buf[2..4].copy_from_slice(&[0x_00, 0x_00]);
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |        Channel Number         |         RFFU = 0              |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+// https://tools.ietf.org/html/rfc8656#section-18.1
+pub(crate) fn channel_number(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, (value, _rffu)) = all_consuming(pair(be_u16, be_u16))(value_field)?;
+
+    let inner = ChannelNumber(value);
+    let attribute = Attribute::ChannelNumber(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_0C, 0x_00, 0x_04,
+            0x_40, 0x_00, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = channel_number(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
@@ -2,9 +2,9 @@ use std::convert::TryInto;
 
 use nom::{multi::length_data, number::complete::be_u16, sequence::tuple, IResult};
 
-use crate::attribute::{Attribute, Tlv};
+use crate::attribute::{pad_len, Attribute, Tlv};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ComprehensionOptional {
     typ: u16,
     value: Vec<u8>,
@@ -19,8 +19,8 @@ impl Tlv for ComprehensionOptional {
         self.value.len().try_into().unwrap()
     }
 
-    fn value(&self) -> Vec<u8> {
-        self.value.to_vec()
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.value.len()].copy_from_slice(&self.value);
     }
 }
 
@@ -29,6 +29,9 @@ pub(crate) fn comprehension_optional(
 ) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, (typ, value_field)) = tuple((be_u16, length_data(be_u16)))(input)?;
 
+    let pad_len = pad_len(value_field.len());
+    let remainder = &remainder[pad_len..];
+
     // TODO: assert that typ is within the comprehension optional range
 
     let value = value_field.to_vec();
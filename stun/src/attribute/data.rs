@@ -0,0 +1,71 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, Attribute, Tlv};
+
+const TYPE: u16 = 0x_0013;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data(Vec<u8>);
+
+impl Data {
+    pub fn new(data: &[u8]) -> Self {
+        Self(data.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Tlv for Data {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(&self.0);
+    }
+}
+
+// https://tools.ietf.org/html/rfc8656#section-18.4
+pub(crate) fn data(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let inner = Data(value_field.to_vec());
+    let attribute = Attribute::Data(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_13, 0x_00, 0x_05,
+            0x_DE, 0x_AD, 0x_BE, 0x_EF,
+            0x_42, 0x_00, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = data(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
@@ -2,7 +2,7 @@ use std::convert::TryInto;
 
 use nom::{
     bits::{bits, complete::take},
-    bytes::complete::tag,
+    bytes::complete::{tag, take as take_bytes},
     multi::length_data,
     number::complete::be_u16,
     sequence::{preceded, tuple},
@@ -11,11 +11,11 @@ use nom::{
 use num_enum::TryFromPrimitive;
 
 use crate::{
-    attribute::{Attribute, Tlv},
+    attribute::{pad_len, string_value, Attribute, Tlv},
     Error,
 };
 
-const TYPE: u16 = 0x_0009;
+pub(crate) const TYPE: u16 = 0x_0009;
 
 #[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
 #[repr(u16)]
@@ -52,7 +52,7 @@ pub enum NumericCode {
     // 509-699: (Unassigned)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ErrorCode {
     numeric_code: NumericCode,
     reason_phrase: String,
@@ -68,6 +68,14 @@ impl ErrorCode {
             reason_phrase,
         }
     }
+
+    pub fn numeric_code(&self) -> NumericCode {
+        self.numeric_code
+    }
+
+    pub fn reason_phrase(&self) -> &str {
+        &self.reason_phrase
+    }
 }
 
 impl Tlv for ErrorCode {
@@ -79,26 +87,24 @@ impl Tlv for ErrorCode {
         (4 + self.reason_phrase.len()).try_into().unwrap()
     }
 
-    fn value(&self) -> Vec<u8> {
+    fn encode_value(&self, buf: &mut [u8]) {
         let class_and_number = self.numeric_code as u32;
         let class = class_and_number / 100;
         let number = class_and_number % 100;
         let class_and_number_encoded = class << 8 | number;
 
-        let mut value_field = class_and_number_encoded.to_be_bytes().to_vec();
-        value_field.extend_from_slice(self.reason_phrase.as_bytes());
+        let reason_phrase = self.reason_phrase.as_bytes();
 
-        let pad_len = (4 - (value_field.len() % 4)) % 4;
-        let new_len = value_field.len() + pad_len;
-        value_field.resize(new_len, 0x_00);
-
-        value_field
+        buf[..4].copy_from_slice(&class_and_number_encoded.to_be_bytes());
+        buf[4..4 + reason_phrase.len()].copy_from_slice(reason_phrase);
     }
 }
 
 pub(crate) fn error_code(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
 
+    let (remainder, _padding) = take_bytes(pad_len(value_field.len()))(remainder)?;
+
     let (value_remainder, (class, number)): (&[u8], (u16, u16)) = bits::<_, _, (_, _), _, _>(
         preceded::<_, u32, _, _, _, _>(take(21_usize), tuple((take(3_usize), take(8_usize)))),
     )(value_field)?;
@@ -109,7 +115,7 @@ pub(crate) fn error_code(input: &[u8]) -> IResult<&[u8], Attribute, crate::Parse
         .map_err(|_| nom::Err::Error(Error::InvalidErrorCode(class_and_number).into()))?;
 
     // TODO: Ensure the phrase is < 128 chars (and < 763 bytes).
-    let reason_phrase = String::from_utf8(value_remainder.to_vec()).unwrap();
+    let reason_phrase = string_value(value_remainder)?;
 
     let inner = ErrorCode {
         numeric_code,
@@ -139,4 +145,27 @@ mod tests {
 
         assert_eq!(attribute_bytes, input);
     }
+
+    #[test]
+    fn skips_padding_before_the_next_attribute() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_09, 0x_00, 0x_13,
+            0x_00, 0x_00, 0x_04, 0x_01,
+            0x_55, 0x_6E, 0x_61, 0x_75,
+            0x_74, 0x_68, 0x_65, 0x_6E,
+            0x_74, 0x_69, 0x_63, 0x_61,
+            0x_74, 0x_65, 0x_64, 0x_00,
+            0x_00, 0x_14, 0x_00, 0x_00,
+        ];
+
+        let (remainder, attribute) = error_code(&input).unwrap();
+
+        assert_eq!(
+            attribute,
+            Attribute::error_code(NumericCode::Unauthenticated, "Unauthenticated")
+        );
+        assert_eq!(attribute.to_bytes(), input[..24]);
+        assert_eq!(remainder, &input[24..]);
+    }
 }
@@ -1,15 +1,20 @@
 use std::convert::TryInto;
 
 use nom::{
-    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u32},
+    sequence::preceded,
+    IResult,
 };
 
 use crate::attribute::{Attribute, Tlv};
 
-const TYPE: u16 = 0x_8028;
+pub(crate) const TYPE: u16 = 0x_8028;
 const MAGIC_NUMBER: u32 = 0x_5354_554E;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Fingerprint(u32);
 
 impl Fingerprint {
@@ -27,19 +32,16 @@ impl Tlv for Fingerprint {
         std::mem::size_of::<u32>().try_into().unwrap()
     }
 
-    fn value(&self) -> Vec<u8> {
+    fn encode_value(&self, buf: &mut [u8]) {
         let xored = self.0 ^ MAGIC_NUMBER;
 
-        xored.to_be_bytes().to_vec()
+        buf[..4].copy_from_slice(&xored.to_be_bytes());
     }
 }
 
 pub(crate) fn fingerprint(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
-
-    // TODO: return Err here
-    let value_field: [u8; 4] = value_field.try_into().unwrap();
-    let xored = u32::from_be_bytes(value_field);
+    let (_, xored) = all_consuming(be_u32)(value_field)?;
     let value = xored ^ MAGIC_NUMBER;
 
     let inner = Fingerprint(value);
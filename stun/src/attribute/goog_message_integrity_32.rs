@@ -0,0 +1,93 @@
+use std::convert::{TryFrom, TryInto};
+
+use fehler::{throw, throws};
+use nom::{
+    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+};
+
+use super::{Attribute, Tlv};
+use crate::{Error, ParseError};
+
+pub(crate) const TYPE: u16 = 0x_C060;
+pub(crate) const GOOG_MESSAGE_INTEGRITY_32_LEN: usize = 4;
+
+type GoogMessageIntegrity32Buf = [u8; GOOG_MESSAGE_INTEGRITY_32_LEN];
+
+// libwebrtc's MESSAGE-INTEGRITY, with the HMAC-SHA1 truncated to its first
+// 4 bytes, which it protects GOOG-PING requests and responses with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoogMessageIntegrity32(GoogMessageIntegrity32Buf);
+
+impl TryFrom<&[u8]> for GoogMessageIntegrity32 {
+    type Error = Error;
+
+    #[throws]
+    fn try_from(bytes: &[u8]) -> Self {
+        if bytes.len() != GOOG_MESSAGE_INTEGRITY_32_LEN {
+            throw!(Error::InvalidGoogMessageIntegrity32(bytes.to_vec()));
+        }
+
+        let mut buf = [0u8; GOOG_MESSAGE_INTEGRITY_32_LEN];
+        buf.copy_from_slice(bytes);
+
+        Self(buf)
+    }
+}
+
+impl Tlv for GoogMessageIntegrity32 {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        GOOG_MESSAGE_INTEGRITY_32_LEN as u16
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..GOOG_MESSAGE_INTEGRITY_32_LEN].copy_from_slice(&self.0);
+    }
+}
+
+pub(crate) fn goog_message_integrity_32(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+
+    let inner = value_field
+        .try_into()
+        .map_err(|err| nom::Err::Error(ParseError::from(err)))?;
+    let attribute = Attribute::GoogMessageIntegrity32(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_C0, 0x_60, 0x_00, 0x_04,
+            0x_DE, 0x_AD, 0x_BE, 0x_EF,
+        ];
+
+        let (_, attribute) = goog_message_integrity_32(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn wrong_length() {
+        #[rustfmt::skip]
+        let input = [
+            0x_C0, 0x_60, 0x_00, 0x_08,
+            0x_DE, 0x_AD, 0x_BE, 0x_EF,
+            0x_CA, 0x_FE, 0x_BA, 0x_BE,
+        ];
+
+        assert!(goog_message_integrity_32(&input).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use nom::{
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u64},
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{Attribute, Tlv};
+
+const TYPE: u16 = 0x_8029;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IceControlled(u64);
+
+impl IceControlled {
+    pub fn new(tie_breaker: u64) -> Self {
+        Self(tie_breaker)
+    }
+
+    pub fn tie_breaker(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Tlv for IceControlled {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        8
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..8].copy_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8445#section-16.1
+pub(crate) fn ice_controlled(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, value) = all_consuming(be_u64)(value_field)?;
+
+    let inner = IceControlled(value);
+    let attribute = Attribute::IceControlled(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_29, 0x_00, 0x_08,
+            0x_93, 0x_2F, 0x_F9, 0x_B1,
+            0x_51, 0x_26, 0x_3B, 0x_36,
+        ];
+
+        let (_, attribute) = ice_controlled(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
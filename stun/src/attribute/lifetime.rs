@@ -0,0 +1,69 @@
+use nom::{
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u32},
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{Attribute, Tlv};
+
+const TYPE: u16 = 0x_000D;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lifetime(u32);
+
+impl Lifetime {
+    pub fn new(seconds: u32) -> Self {
+        Self(seconds)
+    }
+
+    pub fn seconds(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Tlv for Lifetime {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        4
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8656#section-18.2
+pub(crate) fn lifetime(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, seconds) = all_consuming(be_u32)(value_field)?;
+
+    let inner = Lifetime(seconds);
+    let attribute = Attribute::Lifetime(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_0D, 0x_00, 0x_04,
+            0x_00, 0x_00, 0x_02, 0x_58,
+        ];
+
+        let (_, attribute) = lifetime(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
@@ -8,12 +8,12 @@ use nom::{
 use super::{Attribute, Tlv};
 use crate::{Error, ParseError};
 
-const TYPE: u16 = 0x_0008;
+pub(crate) const TYPE: u16 = 0x_0008;
 const MESSAGE_INTEGRITY_LEN: usize = 20;
 
 type MessageIntegrityBuf = [u8; MESSAGE_INTEGRITY_LEN];
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct MessageIntegrity(MessageIntegrityBuf);
 
 impl TryFrom<&[u8]> for MessageIntegrity {
@@ -41,8 +41,8 @@ impl Tlv for MessageIntegrity {
         MESSAGE_INTEGRITY_LEN as u16
     }
 
-    fn value(&self) -> Vec<u8> {
-        self.0.to_vec()
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..MESSAGE_INTEGRITY_LEN].copy_from_slice(&self.0);
     }
 }
 
@@ -0,0 +1,114 @@
+use std::convert::{TryFrom, TryInto};
+
+use fehler::{throw, throws};
+use nom::{
+    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+};
+
+use super::{Attribute, Tlv};
+use crate::{Error, ParseError};
+
+const TYPE: u16 = 0x_001C;
+const MIN_LEN: usize = 16;
+const MAX_LEN: usize = 32;
+
+// The HMAC may be truncated to as few as 16 bytes, but must remain a
+// multiple of 4 bytes long.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageIntegritySha256(Vec<u8>);
+
+impl TryFrom<&[u8]> for MessageIntegritySha256 {
+    type Error = Error;
+
+    #[throws]
+    fn try_from(bytes: &[u8]) -> Self {
+        if bytes.len() < MIN_LEN || bytes.len() > MAX_LEN || !bytes.len().is_multiple_of(4) {
+            throw!(Error::InvalidMessageIntegritySha256(bytes.to_vec()));
+        }
+
+        Self(bytes.to_vec())
+    }
+}
+
+impl Tlv for MessageIntegritySha256 {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(&self.0);
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.6
+pub(crate) fn message_integrity_sha256(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+
+    let inner = value_field
+        .try_into()
+        .map_err(|err| nom::Err::Error(ParseError::from(err)))?;
+    let attribute = Attribute::MessageIntegritySha256(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1C, 0x_00, 0x_20,
+            0x_E4, 0x_68, 0x_6C, 0x_8F,
+            0x_0E, 0x_DE, 0x_B5, 0x_90,
+            0x_13, 0x_E0, 0x_70, 0x_90,
+            0x_01, 0x_0A, 0x_93, 0x_EF,
+            0x_CC, 0x_BC, 0x_CC, 0x_54,
+            0x_4C, 0x_0A, 0x_45, 0x_D9,
+            0x_F8, 0x_30, 0x_AA, 0x_6D,
+            0x_6F, 0x_73, 0x_5A, 0x_01,
+        ];
+
+        let (_, attribute) = message_integrity_sha256(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn truncated_to_16_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1C, 0x_00, 0x_10,
+            0x_E4, 0x_68, 0x_6C, 0x_8F,
+            0x_0E, 0x_DE, 0x_B5, 0x_90,
+            0x_13, 0x_E0, 0x_70, 0x_90,
+            0x_01, 0x_0A, 0x_93, 0x_EF,
+        ];
+
+        let (_, attribute) = message_integrity_sha256(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn invalid_length() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1C, 0x_00, 0x_08,
+            0x_E4, 0x_68, 0x_6C, 0x_8F,
+            0x_0E, 0x_DE, 0x_B5, 0x_90,
+        ];
+
+        assert!(message_integrity_sha256(&input).is_err());
+    }
+}
@@ -1,11 +1,35 @@
+mod address;
+mod alternate_domain;
+mod alternate_server;
+mod channel_number;
 mod comprehension_optional;
-mod error_code;
+mod data;
+pub(crate) mod error_code;
 pub(crate) mod fingerprint;
+pub(crate) mod goog_message_integrity_32;
+mod ice_controlled;
+mod ice_controlling;
+mod lifetime;
+mod mapped_address;
 pub(crate) mod message_integrity;
+mod message_integrity_sha256;
+mod nomination;
+mod nonce;
+mod other_address;
+pub(crate) mod password_algorithm;
+mod password_algorithms;
 mod priority;
+mod realm;
+pub(crate) mod requested_transport;
+mod response_origin;
+mod software;
+mod unknown_attributes;
 mod use_candidate;
+mod userhash;
 mod username;
-mod xor_mapped_address;
+pub(crate) mod xor_mapped_address;
+mod xor_peer_address;
+pub(crate) mod xor_relayed_address;
 
 use std::net::IpAddr;
 
@@ -14,33 +38,91 @@ use simplified_enum::simplified;
 
 use crate::{
     attribute::{
+        address::xor_transaction_id,
+        alternate_domain::{alternate_domain, AlternateDomain},
+        alternate_server::{alternate_server, AlternateServer},
+        channel_number::{channel_number, ChannelNumber},
         comprehension_optional::{comprehension_optional, ComprehensionOptional},
-        error_code::{error_code, ErrorCode},
+        data::{data, Data},
+        error_code::{error_code, ErrorCode, NumericCode},
         fingerprint::{fingerprint, Fingerprint},
+        goog_message_integrity_32::{goog_message_integrity_32, GoogMessageIntegrity32},
+        ice_controlled::{ice_controlled, IceControlled},
+        ice_controlling::{ice_controlling, IceControlling},
+        lifetime::{lifetime, Lifetime},
+        mapped_address::{mapped_address, MappedAddress},
         message_integrity::{message_integrity, MessageIntegrity},
+        message_integrity_sha256::{message_integrity_sha256, MessageIntegritySha256},
+        nomination::{nomination, Nomination},
+        nonce::{nonce, Nonce},
+        other_address::{other_address, OtherAddress},
+        password_algorithm::{password_algorithm, PasswordAlgorithm},
+        password_algorithms::{password_algorithms, PasswordAlgorithms},
         priority::{priority, Priority},
+        realm::{realm, Realm},
+        requested_transport::{requested_transport, RequestedTransport},
+        response_origin::{response_origin, ResponseOrigin},
+        software::{software, Software},
+        unknown_attributes::{unknown_attributes, UnknownAttributes},
         use_candidate::{use_candidate, UseCandidate},
+        userhash::{userhash, Userhash},
         username::{username, Username},
         xor_mapped_address::{xor_mapped_address, XorMappedAddress},
+        xor_peer_address::{xor_peer_address, XorPeerAddress},
+        xor_relayed_address::{xor_relayed_address, XorRelayedAddress},
     },
-    Error,
+    Error, ParseError, TransactionId,
 };
 
+pub(crate) fn pad_len(length: usize) -> usize {
+    (4 - (length % 4)) % 4
+}
+
+pub(crate) fn string_value(value_field: &[u8]) -> Result<String, nom::Err<ParseError<&[u8]>>> {
+    String::from_utf8(value_field.to_vec())
+        .map_err(|err| nom::Err::Error(Error::InvalidString(err.into_bytes()).into()))
+}
+
 pub trait Tlv {
     fn typ(&self) -> u16;
 
     fn length(&self) -> u16;
 
-    fn value(&self) -> Vec<u8>;
+    // Writes the (unpadded) value field into the start of buf.
+    fn encode_value(&self, buf: &mut [u8]);
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let value_field = self.value();
-        let length_field = self.length().to_be_bytes();
-        let type_field = self.typ().to_be_bytes();
+    fn value(&self) -> Vec<u8> {
+        let length = usize::from(self.length());
+
+        let mut value_field = vec![0x_00; length + pad_len(length)];
+        self.encode_value(&mut value_field);
+
+        value_field
+    }
+
+    fn encoded_len(&self) -> usize {
+        let length = usize::from(self.length());
+
+        4 + length + pad_len(length)
+    }
 
-        let mut bytes = type_field.to_vec();
-        bytes.extend_from_slice(&length_field);
-        bytes.extend_from_slice(&value_field);
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let length = usize::from(self.length());
+        let encoded_len = self.encoded_len();
+
+        buf[..2].copy_from_slice(&self.typ().to_be_bytes());
+        buf[2..4].copy_from_slice(&self.length().to_be_bytes());
+        self.encode_value(&mut buf[4..4 + length]);
+        for byte in &mut buf[4 + length..encoded_len] {
+            *byte = 0x_00;
+        }
+
+        encoded_len
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0x_00; self.encoded_len()];
+        self.encode(&mut bytes);
 
         bytes
     }
@@ -51,21 +133,174 @@ pub trait Tlv {
     fn typ(&self) -> u16 {}
     fn length(&self) -> u16 {}
     fn value(&self) -> Vec<u8> {}
+    pub fn encoded_len(&self) -> usize {}
+    pub fn encode(&self, buf: &mut [u8]) -> usize {}
     pub fn to_bytes(&self) -> Vec<u8> {}
 }]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Attribute {
+    AlternateDomain,
+    AlternateServer,
+    ChannelNumber,
     ComprehensionOptional,
+    Data,
     ErrorCode,
     Fingerprint,
+    GoogMessageIntegrity32,
+    IceControlled,
+    IceControlling,
+    Lifetime,
+    MappedAddress,
     MessageIntegrity,
+    MessageIntegritySha256,
+    Nomination,
+    Nonce,
+    OtherAddress,
+    PasswordAlgorithm,
+    PasswordAlgorithms,
     Priority,
+    Realm,
+    RequestedTransport,
+    ResponseOrigin,
+    Software,
+    UnknownAttributes,
     Username,
     UseCandidate,
+    Userhash,
     XorMappedAddress,
+    XorPeerAddress,
+    XorRelayedAddress,
 }
 
 impl Attribute {
+    pub fn alternate_domain(domain: &str) -> Self {
+        let inner = AlternateDomain::new(domain);
+
+        Self::AlternateDomain(inner)
+    }
+
+    pub fn alternate_server(address: IpAddr, port: u16) -> Self {
+        let inner = AlternateServer::new(address, port);
+
+        Self::AlternateServer(inner)
+    }
+
+    pub fn channel_number(channel_number: u16) -> Self {
+        let inner = ChannelNumber::new(channel_number);
+
+        Self::ChannelNumber(inner)
+    }
+
+    pub fn data(data: &[u8]) -> Self {
+        let inner = Data::new(data);
+
+        Self::Data(inner)
+    }
+
+    pub fn error_code(numeric_code: NumericCode, reason_phrase: &str) -> Self {
+        let inner = ErrorCode::new(numeric_code, reason_phrase);
+
+        Self::ErrorCode(inner)
+    }
+
+    pub fn ice_controlled(tie_breaker: u64) -> Self {
+        let inner = IceControlled::new(tie_breaker);
+
+        Self::IceControlled(inner)
+    }
+
+    pub fn ice_controlling(tie_breaker: u64) -> Self {
+        let inner = IceControlling::new(tie_breaker);
+
+        Self::IceControlling(inner)
+    }
+
+    pub fn lifetime(seconds: u32) -> Self {
+        let inner = Lifetime::new(seconds);
+
+        Self::Lifetime(inner)
+    }
+
+    pub fn mapped_address(address: IpAddr, port: u16) -> Self {
+        let inner = MappedAddress::new(address, port);
+
+        Self::MappedAddress(inner)
+    }
+
+    pub fn nomination(value: u32) -> Self {
+        let inner = Nomination::new(value);
+
+        Self::Nomination(inner)
+    }
+
+    pub fn nonce(value: &str) -> Self {
+        let inner = Nonce::new(value);
+
+        Self::Nonce(inner)
+    }
+
+    pub fn other_address(address: IpAddr, port: u16) -> Self {
+        let inner = OtherAddress::new(address, port);
+
+        Self::OtherAddress(inner)
+    }
+
+    pub fn password_algorithm(algorithm: password_algorithm::Algorithm) -> Self {
+        let inner = PasswordAlgorithm::new(algorithm, &[]);
+
+        Self::PasswordAlgorithm(inner)
+    }
+
+    pub fn password_algorithms(algorithms: &[password_algorithm::Algorithm]) -> Self {
+        let algorithms = algorithms
+            .iter()
+            .map(|algorithm| PasswordAlgorithm::new(*algorithm, &[]))
+            .collect();
+        let inner = PasswordAlgorithms::new(algorithms);
+
+        Self::PasswordAlgorithms(inner)
+    }
+
+    pub fn priority(value: u32) -> Self {
+        let inner = Priority::new(value);
+
+        Self::Priority(inner)
+    }
+
+    pub fn realm(value: &str) -> Self {
+        let inner = Realm::new(value);
+
+        Self::Realm(inner)
+    }
+
+    pub fn requested_transport(protocol: u8) -> Self {
+        let inner = RequestedTransport::new(protocol);
+
+        Self::RequestedTransport(inner)
+    }
+
+    pub fn response_origin(address: IpAddr, port: u16) -> Self {
+        let inner = ResponseOrigin::new(address, port);
+
+        Self::ResponseOrigin(inner)
+    }
+
+    pub fn software(value: &str) -> Self {
+        let inner = Software::new(value);
+
+        Self::Software(inner)
+    }
+
+    pub fn unknown_attributes(types: Vec<u16>) -> Self {
+        let inner = UnknownAttributes::new(types);
+
+        Self::UnknownAttributes(inner)
+    }
+
+    pub fn use_candidate() -> Self {
+        Self::UseCandidate(UseCandidate)
+    }
+
     pub fn username(value: &str) -> Self {
         let inner = Username::new(value);
 
@@ -77,6 +312,44 @@ impl Attribute {
 
         Self::XorMappedAddress(inner)
     }
+
+    pub fn xor_peer_address(address: IpAddr, port: u16) -> Self {
+        let inner = XorPeerAddress::new(address, port);
+
+        Self::XorPeerAddress(inner)
+    }
+
+    pub fn xor_relayed_address(address: IpAddr, port: u16) -> Self {
+        let inner = XorRelayedAddress::new(address, port);
+
+        Self::XorRelayedAddress(inner)
+    }
+
+    // The XOR'd address attributes holding IPv6 addresses need the
+    // transaction ID of their message applying on top of the magic cookie,
+    // both when encoding and decoding. Returns None for any attribute that
+    // doesn't.
+    pub(crate) fn xor_transaction_id(&self, transaction_id: &TransactionId) -> Option<Self> {
+        let attribute = match self {
+            Self::XorMappedAddress(inner) if inner.address().is_ipv6() => Self::xor_mapped_address(
+                xor_transaction_id(inner.address(), transaction_id),
+                inner.port(),
+            ),
+            Self::XorPeerAddress(inner) if inner.address().is_ipv6() => Self::xor_peer_address(
+                xor_transaction_id(inner.address(), transaction_id),
+                inner.port(),
+            ),
+            Self::XorRelayedAddress(inner) if inner.address().is_ipv6() => {
+                Self::xor_relayed_address(
+                    xor_transaction_id(inner.address(), transaction_id),
+                    inner.port(),
+                )
+            }
+            _ => return None,
+        };
+
+        Some(attribute)
+    }
 }
 
 //  0                   1                   2                   3
@@ -91,7 +364,7 @@ impl Attribute {
 //
 // https://tools.ietf.org/html/rfc5389#section-15
 // https://www.iana.org/assignments/stun-parameters/stun-parameters.xhtml
-pub(crate) fn attribute(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+pub(crate) fn attribute(input: &[u8]) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
     let (input, attribute_type) = peek(be_u16)(input)?;
     let parser = match attribute_type {
         // Attribute Registry
@@ -99,7 +372,7 @@ pub(crate) fn attribute(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseE
         //
         // Comprehension-required range (0x0000-0x7FFF)
         // 0x0000: (Reserved)
-        // 0x0001: MAPPED-ADDRESS
+        0x_0001 => mapped_address,
         // 0x0002: (Reserved; was RESPONSE-ADDRESS)
         // 0x0003: (Reserved; was CHANGE-ADDRESS)
         // 0x0004: (Reserved; was SOURCE-ADDRESS)
@@ -108,26 +381,26 @@ pub(crate) fn attribute(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseE
         // 0x0007: (Reserved; was PASSWORD)
         0x_0008 => message_integrity,
         0x_0009 => error_code,
-        // 0x000A: UNKNOWN-ATTRIBUTES
+        0x_000A => unknown_attributes,
         // 0x000B: (Reserved; was REFLECTED-FROM)
-        // 0x000C: CHANNEL-NUMBER
-        // 0x000D: LIFETIME
+        0x_000C => channel_number,
+        0x_000D => lifetime,
         // 0x000E-0x000F: (Reserved)
         // 0x0010: (Reserved; was BANDWIDTH)
         // 0x0011: (Reserved)
-        // 0x0012: XOR-PEER-ADDRESS
-        // 0x0013: DATA
-        // 0x0014: REALM
-        // 0x0015: NONCE
-        // 0x0016: XOR-RELAYED-ADDRESS
+        0x_0012 => xor_peer_address,
+        0x_0013 => data,
+        0x_0014 => realm,
+        0x_0015 => nonce,
+        0x_0016 => xor_relayed_address,
         // 0x0017: REQUESTED-ADDRESS-FAMILY
         // 0x0018: EVEN-PORT
-        // 0x0019: REQUESTED-TRANSPORT
+        0x_0019 => requested_transport,
         // 0x001A: DONT-FRAGMENT
         // 0x001B: ACCESS-TOKEN
-        // 0x001C: MESSAGE-INTEGRITY-SHA256
-        // 0x001D: PASSWORD-ALGORITHM
-        // 0x001E: USERHASH
+        0x_001C => message_integrity_sha256,
+        0x_001D => password_algorithm,
+        0x_001E => userhash,
         // 0x001F: (Unassigned)
         0x_0020 => xor_mapped_address,
         // 0x0021: (Reserved; was TIMER-VAL)
@@ -146,33 +419,35 @@ pub(crate) fn attribute(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseE
         // Comprehension-optional range (0x8000-0xFFFF)
         // 0x8000: ADDITIONAL-ADDRESS-FAMILY
         // 0x8001: ADDRESS-ERROR-CODE
-        // 0x8002: PASSWORD-ALGORITHMS
-        // 0x8003: ALTERNATE-DOMAIN
+        0x_8002 => password_algorithms,
+        0x_8003 => alternate_domain,
         // 0x8004: ICMP
         // 0x8005-0x8021: (Unassigned)
-        // 0x8022: SOFTWARE
-        // 0x8023: ALTERNATE-SERVER
+        0x_8022 => software,
+        0x_8023 => alternate_server,
         // 0x8024: (Reserved)
         // 0x8025: TRANSACTION_TRANSMIT_COUNTER
         // 0x8026: (Reserved)
         // 0x8027: CACHE-TIMEOUT
         0x_8028 => fingerprint,
-        // 0x8029: ICE-CONTROLLED
-        // 0x802A: ICE-CONTROLLING
-        // 0x802B: RESPONSE-ORIGIN
-        // 0x802C: OTHER-ADDRESS
+        0x_8029 => ice_controlled,
+        0x_802A => ice_controlling,
+        0x_802B => response_origin,
+        0x_802C => other_address,
         // 0x802D: ECN-CHECK STUN
         // 0x802E: THIRD-PARTY-AUTHORIZATION
         // 0x802F: (Unassigned)
         // 0x8030: MOBILITY-TICKET
         // 0x8031-0xBFFF: (Unassigned)
         // 0xC000: CISCO-STUN-FLOWDATA
-        // 0xC001: ENF-FLOW-DESCRIPTION
+        // 0xC001: ENF-FLOW-DESCRIPTION (and libwebrtc's NOMINATION)
+        0x_C001 => nomination,
         // 0xC002: ENF-NETWORK-STATUS
         // 0xC003-0xC058: (Unassigned)
         // 0xC059: GOOG-MISC-INFO
-        // 0xC05A: GOOG-MESSAGE-INTEGRITY-32
-        // 0xC05B-0xFFFF: (Unassigned)
+        // 0xC05A-0xC05F: (Unassigned)
+        0x_C060 => goog_message_integrity_32,
+        // 0xC061-0xFFFF: (Unassigned)
         typ if typ >= 0x_8000 => comprehension_optional,
 
         _ => {
@@ -0,0 +1,81 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u32},
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{Attribute, Tlv};
+
+// Not registered with IANA, which has since assigned the type to
+// ENF-FLOW-DESCRIPTION, but it's what libwebrtc sends for renomination.
+//
+// https://tools.ietf.org/html/draft-thatcher-ice-renomination-01#section-3
+const TYPE: u16 = 0x_C001;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nomination(u32);
+
+impl Nomination {
+    pub fn new(value: u32) -> Self {
+        Self(value)
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Tlv for Nomination {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        std::mem::size_of::<u32>().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+pub(crate) fn nomination(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, value) = all_consuming(be_u32)(value_field)?;
+
+    let inner = Nomination(value);
+    let attribute = Attribute::Nomination(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_C0, 0x_01, 0x_00, 0x_04,
+            0x_00, 0x_00, 0x_00, 0x_02,
+        ];
+
+        let (_, attribute) = nomination(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn rejects_short_values() {
+        let input = [0x_C0, 0x_01, 0x_00, 0x_02, 0x_00, 0x_01, 0x_00, 0x_00];
+
+        assert!(nomination(&input).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, string_value, Attribute, Tlv};
+
+const TYPE: u16 = 0x_0015;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nonce(String);
+
+impl Nonce {
+    pub fn new(nonce: &str) -> Self {
+        // TODO: Ensure the nonce is < 128 chars (and < 763 bytes).
+        Self(nonce.to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Tlv for Nonce {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.10
+pub(crate) fn nonce(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let value = string_value(value_field)?;
+
+    let inner = Nonce(value);
+    let attribute = Attribute::Nonce(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_15, 0x_00, 0x_1C,
+            0x_66, 0x_2F, 0x_2F, 0x_34,
+            0x_39, 0x_39, 0x_6B, 0x_39,
+            0x_35, 0x_34, 0x_64, 0x_36,
+            0x_4F, 0x_4C, 0x_33, 0x_34,
+            0x_6F, 0x_4C, 0x_39, 0x_46,
+            0x_53, 0x_54, 0x_76, 0x_79,
+            0x_36, 0x_34, 0x_73, 0x_41,
+        ];
+
+        let (_, attribute) = nonce(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::IpAddr;
+
+use nom::{
+    bytes::complete::tag, combinator::all_consuming, multi::length_data, number::complete::be_u16,
+    sequence::preceded, IResult,
+};
+
+use crate::attribute::{
+    address::{address_and_port, address_length, encode_address},
+    Attribute, Tlv,
+};
+
+const TYPE: u16 = 0x_802C;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtherAddress {
+    address: IpAddr,
+    port: u16,
+}
+
+impl OtherAddress {
+    pub fn new(address: IpAddr, port: u16) -> Self {
+        Self { address, port }
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Tlv for OtherAddress {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        address_length(&self.address)
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        encode_address(&self.address, self.port, buf);
+    }
+}
+
+// https://tools.ietf.org/html/rfc5780#section-7.4
+pub(crate) fn other_address(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, (address, port)) = all_consuming(address_and_port)(value_field)?;
+
+    let inner = OtherAddress { address, port };
+    let attribute = Attribute::OtherAddress(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_2C, 0x_00, 0x_08,
+            0x_00, 0x_01, 0x_BE, 0x_EF,
+            0x_C0, 0x_00, 0x_02, 0x_01,
+        ];
+
+        let (_, attribute) = other_address(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
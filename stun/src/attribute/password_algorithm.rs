@@ -0,0 +1,163 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::{preceded, tuple},
+    IResult,
+};
+use num_enum::TryFromPrimitive;
+
+use crate::{
+    attribute::{pad_len, Attribute, Tlv},
+    Error, ParseError,
+};
+
+const TYPE: u16 = 0x_001D;
+
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u16)]
+pub enum Algorithm {
+    // 0x0000: (Reserved)
+    Md5 = 0x_0001,
+    Sha256 = 0x_0002,
+    // 0x0003-0xFFFF: (Unassigned)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordAlgorithm {
+    algorithm: Algorithm,
+    parameters: Vec<u8>,
+}
+
+impl PasswordAlgorithm {
+    pub fn new(algorithm: Algorithm, parameters: &[u8]) -> Self {
+        let parameters = parameters.to_vec();
+
+        Self {
+            algorithm,
+            parameters,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn parameters(&self) -> &[u8] {
+        &self.parameters
+    }
+
+    // The algorithm and its parameters are encoded the same way whether
+    // they make up a PASSWORD-ALGORITHM attribute on their own or are
+    // one entry in a PASSWORD-ALGORITHMS list.
+    pub(crate) fn encoded_algorithm_len(&self) -> usize {
+        let length = self.parameters.len();
+
+        4 + length + pad_len(length)
+    }
+
+    pub(crate) fn encode_algorithm(&self, buf: &mut [u8]) -> usize {
+        let length = self.parameters.len();
+        let length_field: u16 = length.try_into().unwrap();
+        let encoded_len = self.encoded_algorithm_len();
+
+        buf[..2].copy_from_slice(&(self.algorithm as u16).to_be_bytes());
+        buf[2..4].copy_from_slice(&length_field.to_be_bytes());
+        buf[4..4 + length].copy_from_slice(&self.parameters);
+        for byte in &mut buf[4 + length..encoded_len] {
+            *byte = 0x_00;
+        }
+
+        encoded_len
+    }
+}
+
+impl Tlv for PasswordAlgorithm {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.encoded_algorithm_len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        self.encode_algorithm(buf);
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |          Algorithm           |  Algorithm Parameters Length   |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                    Algorithm Parameters (variable)
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+//        Figure 10: Format of PASSWORD-ALGORITHM Attribute
+//
+// https://tools.ietf.org/html/rfc8489#section-14.12
+pub(crate) fn algorithm_and_parameters(
+    input: &[u8],
+) -> IResult<&[u8], PasswordAlgorithm, ParseError<&[u8]>> {
+    let (input, (algorithm, parameters)) = tuple((be_u16, length_data(be_u16)))(input)?;
+    let (input, _padding) = take(pad_len(parameters.len()))(input)?;
+
+    let algorithm = algorithm
+        .try_into()
+        .map_err(|_| nom::Err::Error(Error::InvalidPasswordAlgorithm(algorithm).into()))?;
+    let parameters = parameters.to_vec();
+
+    let password_algorithm = PasswordAlgorithm {
+        algorithm,
+        parameters,
+    };
+
+    Ok((input, password_algorithm))
+}
+
+pub(crate) fn password_algorithm(input: &[u8]) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, inner) = all_consuming(algorithm_and_parameters)(value_field)?;
+
+    let attribute = Attribute::PasswordAlgorithm(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1D, 0x_00, 0x_04,
+            0x_00, 0x_02, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = password_algorithm(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+
+    #[test]
+    fn round_trip_bytes_with_parameters() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1D, 0x_00, 0x_08,
+            0x_00, 0x_01, 0x_00, 0x_03,
+            0x_DE, 0x_AD, 0x_BE, 0x_00,
+        ];
+
+        let (_, attribute) = password_algorithm(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
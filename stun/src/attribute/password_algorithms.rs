@@ -0,0 +1,103 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::{length_data, many0},
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::{
+    attribute::{
+        password_algorithm::{algorithm_and_parameters, PasswordAlgorithm},
+        Attribute, Tlv,
+    },
+    ParseError,
+};
+
+const TYPE: u16 = 0x_8002;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordAlgorithms(Vec<PasswordAlgorithm>);
+
+impl PasswordAlgorithms {
+    pub fn new(algorithms: Vec<PasswordAlgorithm>) -> Self {
+        Self(algorithms)
+    }
+
+    pub fn algorithms(&self) -> &[PasswordAlgorithm] {
+        &self.0
+    }
+}
+
+impl Tlv for PasswordAlgorithms {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        let length: usize = self
+            .0
+            .iter()
+            .map(PasswordAlgorithm::encoded_algorithm_len)
+            .sum();
+
+        length.try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        let mut offset = 0;
+        for algorithm in &self.0 {
+            offset += algorithm.encode_algorithm(&mut buf[offset..]);
+        }
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |         Algorithm 1           | Algorithm 1 Parameters Length |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                    Algorithm 1 Parameters (variable)
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |         Algorithm 2           | Algorithm 2 Parameters Length |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                    Algorithm 2 Parameters (variable)
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                             ...
+//
+//        Figure 9: Format of PASSWORD-ALGORITHMS Attribute
+//
+// https://tools.ietf.org/html/rfc8489#section-14.11
+pub(crate) fn password_algorithms(input: &[u8]) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, algorithms) = all_consuming(many0(algorithm_and_parameters))(value_field)?;
+
+    let inner = PasswordAlgorithms(algorithms);
+    let attribute = Attribute::PasswordAlgorithms(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_02, 0x_00, 0x_0C,
+            0x_00, 0x_02, 0x_00, 0x_00,
+            0x_00, 0x_01, 0x_00, 0x_02,
+            0x_CA, 0x_FE, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = password_algorithms(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
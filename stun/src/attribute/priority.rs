@@ -1,16 +1,31 @@
 use std::convert::TryInto;
 
 use nom::{
-    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+    bytes::complete::tag,
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u32},
+    sequence::preceded,
+    IResult,
 };
 
 use crate::attribute::{Attribute, Tlv};
 
 const TYPE: u16 = 0x_0024;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Priority(u32);
 
+impl Priority {
+    pub fn new(value: u32) -> Self {
+        Self(value)
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
 impl Tlv for Priority {
     fn typ(&self) -> u16 {
         TYPE
@@ -20,17 +35,14 @@ impl Tlv for Priority {
         std::mem::size_of::<u32>().try_into().unwrap()
     }
 
-    fn value(&self) -> Vec<u8> {
-        self.0.to_be_bytes().to_vec()
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.0.to_be_bytes());
     }
 }
 
 pub(crate) fn priority(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
-
-    // TODO: return Err here
-    let value_field: [u8; 4] = value_field.try_into().unwrap();
-    let value = u32::from_be_bytes(value_field);
+    let (_, value) = all_consuming(be_u32)(value_field)?;
 
     let inner = Priority(value);
     let attribute = Attribute::Priority(inner);
@@ -55,4 +67,11 @@ mod tests {
 
         assert_eq!(attribute_bytes, input);
     }
+
+    #[test]
+    fn rejects_short_values() {
+        let input = [0x_00, 0x_24, 0x_00, 0x_02, 0x_00, 0x_01, 0x_00, 0x_00];
+
+        assert!(priority(&input).is_err());
+    }
 }
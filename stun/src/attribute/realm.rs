@@ -0,0 +1,75 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, string_value, Attribute, Tlv};
+
+const TYPE: u16 = 0x_0014;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Realm(String);
+
+impl Realm {
+    pub fn new(realm: &str) -> Self {
+        // TODO: Ensure the realm is < 128 chars (and < 763 bytes).
+        Self(realm.to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Tlv for Realm {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.9
+pub(crate) fn realm(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let value = string_value(value_field)?;
+
+    let inner = Realm(value);
+    let attribute = Attribute::Realm(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_14, 0x_00, 0x_0B,
+            0x_65, 0x_78, 0x_61, 0x_6D,
+            0x_70, 0x_6C, 0x_65, 0x_2E,
+            0x_6F, 0x_72, 0x_67, 0x_00,
+        ];
+
+        let (_, attribute) = realm(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
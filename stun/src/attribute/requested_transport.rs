@@ -0,0 +1,81 @@
+use nom::{
+    bytes::complete::{tag, take},
+    combinator::all_consuming,
+    multi::length_data,
+    number::complete::{be_u16, be_u8},
+    sequence::{pair, preceded},
+    IResult,
+};
+
+use crate::attribute::{Attribute, Tlv};
+
+const TYPE: u16 = 0x_0019;
+
+// https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
+pub(crate) const PROTOCOL_UDP: u8 = 17;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestedTransport(u8);
+
+impl RequestedTransport {
+    pub fn new(protocol: u8) -> Self {
+        Self(protocol)
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Tlv for RequestedTransport {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        4
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[0] = self.0;
+        buf[1..4].copy_from_slice(&[0x_00, 0x_00, 0x_00]);
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |    Protocol   |                    RFFU                       |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+// https://tools.ietf.org/html/rfc8656#section-18.8
+pub(crate) fn requested_transport(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (_, (protocol, _rffu)) = all_consuming(pair(be_u8, take(3_usize)))(value_field)?;
+
+    let inner = RequestedTransport(protocol);
+    let attribute = Attribute::RequestedTransport(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_19, 0x_00, 0x_04,
+            0x_11, 0x_00, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = requested_transport(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
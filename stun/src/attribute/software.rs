@@ -0,0 +1,76 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, string_value, Attribute, Tlv};
+
+const TYPE: u16 = 0x_8022;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Software(String);
+
+impl Software {
+    pub fn new(software: &str) -> Self {
+        // TODO: Ensure the description is < 128 chars (and < 763 bytes).
+        Self(software.to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Tlv for Software {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        self.0.len().try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.14
+pub(crate) fn software(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let value = string_value(value_field)?;
+
+    let inner = Software(value);
+    let attribute = Attribute::Software(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_80, 0x_22, 0x_00, 0x_10,
+            0x_53, 0x_54, 0x_55, 0x_4E,
+            0x_20, 0x_74, 0x_65, 0x_73,
+            0x_74, 0x_20, 0x_63, 0x_6C,
+            0x_69, 0x_65, 0x_6E, 0x_74,
+        ];
+
+        let (_, attribute) = software(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
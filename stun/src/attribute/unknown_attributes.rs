@@ -0,0 +1,88 @@
+use std::convert::TryInto;
+
+use nom::{
+    bytes::complete::{tag, take},
+    combinator::all_consuming,
+    multi::{length_data, many0},
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
+};
+
+use crate::attribute::{pad_len, Attribute, Tlv};
+
+const TYPE: u16 = 0x_000A;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownAttributes(Vec<u16>);
+
+impl UnknownAttributes {
+    pub fn new(types: Vec<u16>) -> Self {
+        Self(types)
+    }
+
+    pub fn types(&self) -> &[u16] {
+        &self.0
+    }
+}
+
+impl Tlv for UnknownAttributes {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        (2 * self.0.len()).try_into().unwrap()
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        for (typ, chunk) in self.0.iter().zip(buf.chunks_exact_mut(2)) {
+            chunk.copy_from_slice(&typ.to_be_bytes());
+        }
+    }
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |      Attribute 1 Type         |       Attribute 2 Type        |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |      Attribute 3 Type         |       Attribute 4 Type    ...
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+//       Figure 12: Format of UNKNOWN-ATTRIBUTES Attribute
+//
+// https://tools.ietf.org/html/rfc8489#section-14.13
+pub(crate) fn unknown_attributes(
+    input: &[u8],
+) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
+
+    let (_, types) = all_consuming(many0(be_u16))(value_field)?;
+
+    let inner = UnknownAttributes(types);
+    let attribute = Attribute::UnknownAttributes(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_0A, 0x_00, 0x_06,
+            0x_00, 0x_1C, 0x_00, 0x_1D,
+            0x_00, 0x_1E, 0x_00, 0x_00,
+        ];
+
+        let (_, attribute) = unknown_attributes(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
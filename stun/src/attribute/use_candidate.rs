@@ -6,7 +6,7 @@ use crate::attribute::{Attribute, Tlv};
 
 const TYPE: u16 = 0x_0025;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct UseCandidate;
 
 impl Tlv for UseCandidate {
@@ -18,9 +18,7 @@ impl Tlv for UseCandidate {
         0
     }
 
-    fn value(&self) -> Vec<u8> {
-        vec![]
-    }
+    fn encode_value(&self, _buf: &mut [u8]) {}
 }
 
 pub(crate) fn use_candidate(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
@@ -0,0 +1,91 @@
+use std::convert::{TryFrom, TryInto};
+
+use fehler::{throw, throws};
+use nom::{
+    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+};
+
+use super::{Attribute, Tlv};
+use crate::{Error, ParseError};
+
+const TYPE: u16 = 0x_001E;
+const USERHASH_LEN: usize = 32;
+
+type UserhashBuf = [u8; USERHASH_LEN];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Userhash(UserhashBuf);
+
+impl From<UserhashBuf> for Userhash {
+    fn from(buf: UserhashBuf) -> Self {
+        Self(buf)
+    }
+}
+
+impl TryFrom<&[u8]> for Userhash {
+    type Error = Error;
+
+    #[throws]
+    fn try_from(bytes: &[u8]) -> Self {
+        if bytes.len() != USERHASH_LEN {
+            throw!(Error::InvalidUserhash(bytes.to_vec()));
+        }
+
+        let mut buf = [0u8; USERHASH_LEN];
+        buf.copy_from_slice(bytes);
+
+        Self(buf)
+    }
+}
+
+impl Tlv for Userhash {
+    fn typ(&self) -> u16 {
+        TYPE
+    }
+
+    fn length(&self) -> u16 {
+        USERHASH_LEN as u16
+    }
+
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..USERHASH_LEN].copy_from_slice(&self.0);
+    }
+}
+
+// https://tools.ietf.org/html/rfc8489#section-14.4
+pub(crate) fn userhash(input: &[u8]) -> IResult<&[u8], Attribute, ParseError<&[u8]>> {
+    let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
+
+    let inner = value_field
+        .try_into()
+        .map_err(|err| nom::Err::Error(ParseError::from(err)))?;
+    let attribute = Attribute::Userhash(inner);
+
+    Ok((remainder, attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_1E, 0x_00, 0x_20,
+            0x_4A, 0x_3C, 0x_F3, 0x_8F,
+            0x_EF, 0x_69, 0x_92, 0x_BD,
+            0x_A9, 0x_52, 0x_C6, 0x_78,
+            0x_04, 0x_17, 0x_DA, 0x_0F,
+            0x_24, 0x_81, 0x_94, 0x_15,
+            0x_56, 0x_9E, 0x_60, 0x_B2,
+            0x_05, 0x_C4, 0x_6E, 0x_41,
+            0x_40, 0x_7F, 0x_17, 0x_04,
+        ];
+
+        let (_, attribute) = userhash(&input).unwrap();
+        let attribute_bytes = attribute.to_bytes();
+
+        assert_eq!(attribute_bytes, input);
+    }
+}
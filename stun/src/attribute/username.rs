@@ -1,14 +1,18 @@
 use std::convert::TryInto;
 
 use nom::{
-    bytes::complete::tag, multi::length_data, number::complete::be_u16, sequence::preceded, IResult,
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::be_u16,
+    sequence::preceded,
+    IResult,
 };
 
-use crate::attribute::{Attribute, Tlv};
+use crate::attribute::{pad_len, string_value, Attribute, Tlv};
 
 const TYPE: u16 = 0x_0006;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Username(String);
 
 impl Username {
@@ -30,26 +34,18 @@ impl Tlv for Username {
         self.0.len().try_into().unwrap()
     }
 
-    fn value(&self) -> Vec<u8> {
-        let mut value_field = self.0.as_bytes().to_vec();
-
-        let pad_len = (4 - (value_field.len() % 4)) % 4;
-        let new_len = value_field.len() + pad_len;
-        value_field.resize(new_len, 0x_00);
-
-        value_field
+    fn encode_value(&self, buf: &mut [u8]) {
+        buf[..self.0.len()].copy_from_slice(self.0.as_bytes());
     }
 }
 
 pub(crate) fn username(input: &[u8]) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
 
-    let pad_len = (4 - (value_field.len() % 4)) % 4;
-    let remainder = &remainder[pad_len..];
+    let (remainder, _padding) = take(pad_len(value_field.len()))(remainder)?;
 
     // TODO: check the input length is < 513
-    // TODO: return Err here
-    let value = String::from_utf8(value_field.to_vec()).unwrap();
+    let value = string_value(value_field)?;
 
     let inner = Username(value);
     let attribute = Attribute::Username(inner);
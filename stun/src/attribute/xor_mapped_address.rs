@@ -1,22 +1,18 @@
-use std::convert::TryInto;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 
 use nom::{
-    bytes::complete::tag,
-    multi::length_data,
-    number::complete::be_u16,
-    sequence::{preceded, tuple},
-    IResult,
+    bytes::complete::tag, combinator::all_consuming, multi::length_data, number::complete::be_u16,
+    sequence::preceded, IResult,
 };
 
-use crate::{
-    attribute::{Attribute, Tlv},
-    MAGIC_COOKIE,
+use crate::attribute::{
+    address::{address_length, encode_xor_address, xor_address_and_port},
+    Attribute, Tlv,
 };
 
-const TYPE: u16 = 0x_0020;
+pub(crate) const TYPE: u16 = 0x_0020;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct XorMappedAddress {
     address: IpAddr,
     port: u16,
@@ -26,6 +22,14 @@ impl XorMappedAddress {
     pub fn new(address: IpAddr, port: u16) -> Self {
         Self { address, port }
     }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
 }
 
 impl Tlv for XorMappedAddress {
@@ -34,31 +38,11 @@ impl Tlv for XorMappedAddress {
     }
 
     fn length(&self) -> u16 {
-        self.value().len().try_into().unwrap()
+        address_length(&self.address)
     }
 
-    fn value(&self) -> Vec<u8> {
-        let (family_field, x_address_field) = match self.address {
-            IpAddr::V4(addr) => {
-                let family_code: u16 = 0x_01;
-                let family_field = family_code.to_be_bytes();
-
-                let addr = u32::from_be_bytes(addr.octets());
-                let x_address_field = (addr ^ MAGIC_COOKIE).to_be_bytes();
-
-                (family_field, x_address_field)
-            }
-            _ => unimplemented!(),
-        };
-
-        let magic_cookie_upper_16: u16 = (MAGIC_COOKIE >> 16).try_into().unwrap();
-        let x_port_field = (self.port ^ magic_cookie_upper_16).to_be_bytes();
-
-        let mut value_field = family_field.to_vec();
-        value_field.extend_from_slice(&x_port_field);
-        value_field.extend_from_slice(&x_address_field);
-
-        value_field
+    fn encode_value(&self, buf: &mut [u8]) {
+        encode_xor_address(&self.address, self.port, buf);
     }
 }
 
@@ -77,27 +61,7 @@ pub(crate) fn xor_mapped_address(
     input: &[u8],
 ) -> IResult<&[u8], Attribute, crate::ParseError<&[u8]>> {
     let (remainder, value_field) = preceded(tag(TYPE.to_be_bytes()), length_data(be_u16))(input)?;
-    let (x_address_field, (mut family_field, x_port_field)) = tuple((be_u16, be_u16))(value_field)?;
-
-    let magic_cookie_upper_16: u16 = (MAGIC_COOKIE >> 16).try_into().unwrap();
-    let port = x_port_field ^ magic_cookie_upper_16;
-
-    family_field &= 0b_0000_0000_1111_1111;
-    let address = match family_field {
-        0x_01 => {
-            // TODO: assert that remainder is &[]
-            let (x_address_field, _remainder) = x_address_field.split_at(4);
-            // TODO: convert std::array::TryFromSliceError to nom::internal::Err
-            let x_address_field: [u8; 4] = x_address_field.try_into().unwrap();
-            let address_bytes = u32::from_be_bytes(x_address_field) ^ MAGIC_COOKIE;
-
-            IpAddr::V4(Ipv4Addr::from(address_bytes))
-        }
-        // TODO: implement v6 addresses
-        0x_02 => unimplemented!(),
-        // TODO: return Err here
-        _ => unimplemented!(),
-    };
+    let (_, (address, port)) = all_consuming(xor_address_and_port)(value_field)?;
 
     let inner = XorMappedAddress { address, port };
     let attribute = Attribute::XorMappedAddress(inner);
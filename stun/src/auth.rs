@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crypto::{hmac::Hmac, mac::Mac, sha1::Sha1, util::fixed_time_eq};
+
+use crate::{long_term_key, Attribute, Message, NumericCode};
+
+// the issue time, in hex, followed by its signature
+const ISSUED_LEN: usize = 16;
+const SIGNATURE_LEN: usize = 16;
+const NONCE_LIFETIME: Duration = Duration::from_secs(600);
+
+pub(crate) type Rejection = (NumericCode, &'static str);
+
+// The server side of the long-term credential mechanism, which is only
+// turned on once a realm is set.
+//
+// Nonces are the time they were issued, signed with a secret only the
+// server knows, so they can be checked without keeping anything for the
+// clients that are sent them.
+//
+// https://tools.ietf.org/html/rfc8489#section-9.2
+#[derive(Debug)]
+pub(crate) struct LongTermCredentials {
+    realm: Option<String>,
+    users: HashMap<String, String>,
+    secret: [u8; 16],
+    started: Instant,
+}
+
+impl Default for LongTermCredentials {
+    fn default() -> Self {
+        Self {
+            realm: None,
+            users: HashMap::new(),
+            secret: rand::random(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl LongTermCredentials {
+    pub(crate) fn set_realm(&mut self, realm: &str) {
+        self.realm = Some(realm.to_string());
+    }
+
+    pub(crate) fn add_user(&mut self, username: &str, password: &str) {
+        self.users
+            .insert(username.to_string(), password.to_string());
+    }
+
+    // Returns the key to protect the response with, if the long-term
+    // credential mechanism is in use.
+    //
+    // https://tools.ietf.org/html/rfc8489#section-9.2.4
+    pub(crate) fn authenticate(&self, request: &Message) -> Result<Option<Vec<u8>>, Rejection> {
+        let realm = match &self.realm {
+            Some(realm) => realm.clone(),
+            None => return Ok(None),
+        };
+
+        let mut has_message_integrity = false;
+        let mut maybe_username = None;
+        let mut maybe_realm = None;
+        let mut maybe_nonce = None;
+        for attribute in &request.attributes {
+            match attribute {
+                Attribute::MessageIntegrity(_) => has_message_integrity = true,
+                Attribute::Username(username) => maybe_username = Some(username.as_str()),
+                Attribute::Realm(realm) => maybe_realm = Some(realm.as_str()),
+                Attribute::Nonce(nonce) => maybe_nonce = Some(nonce.as_str()),
+                _ => continue,
+            }
+        }
+
+        if !has_message_integrity {
+            return Err((NumericCode::Unauthenticated, "Unauthenticated"));
+        }
+
+        let (username, request_realm, nonce) = match (maybe_username, maybe_realm, maybe_nonce) {
+            (Some(username), Some(realm), Some(nonce)) => (username, realm, nonce),
+            _ => return Err((NumericCode::BadRequest, "Bad Request")),
+        };
+
+        if !self.is_fresh(nonce) {
+            return Err((NumericCode::StaleNonce, "Stale Nonce"));
+        }
+
+        let password = match self.users.get(username) {
+            Some(password) if request_realm == realm => password,
+            _ => return Err((NumericCode::Unauthenticated, "Unauthenticated")),
+        };
+
+        let key = long_term_key(username, &realm, password);
+        if request.verify_integrity(&key).is_err() {
+            return Err((NumericCode::Unauthenticated, "Unauthenticated"));
+        }
+
+        Ok(Some(key))
+    }
+
+    // The REALM and a fresh NONCE to go in a 401 or 438 response.
+    pub(crate) fn challenge(&self, numeric_code: NumericCode) -> Vec<Attribute> {
+        let challenge =
+            numeric_code == NumericCode::Unauthenticated || numeric_code == NumericCode::StaleNonce;
+
+        match (challenge, self.realm.clone()) {
+            (true, Some(realm)) => vec![Attribute::realm(&realm), Attribute::nonce(&self.nonce())],
+            _ => vec![],
+        }
+    }
+
+    fn nonce(&self) -> String {
+        let issued = self.started.elapsed().as_secs();
+
+        format!("{:016x}{}", issued, self.signature(issued))
+    }
+
+    fn signature(&self, issued: u64) -> String {
+        let mut mac = Hmac::new(Sha1::new(), &self.secret);
+        mac.input(&issued.to_be_bytes());
+
+        mac.result().code()[..SIGNATURE_LEN / 2]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn is_fresh(&self, nonce: &str) -> bool {
+        if nonce.len() != ISSUED_LEN + SIGNATURE_LEN || !nonce.is_ascii() {
+            return false;
+        }
+
+        let (issued, signature) = nonce.split_at(ISSUED_LEN);
+        let issued = match u64::from_str_radix(issued, 16) {
+            Ok(issued) => issued,
+            Err(_) => return false,
+        };
+        if !fixed_time_eq(signature.as_bytes(), self.signature(issued).as_bytes()) {
+            return false;
+        }
+
+        self.started.elapsed().as_secs().saturating_sub(issued) < NONCE_LIFETIME.as_secs()
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    env, io,
+    net::{IpAddr, SocketAddr},
+    process,
+    str::FromStr,
+};
+
+use tokio::net::UdpSocket;
+
+const USAGE: &str = "usage: stun-server [--other-address ADDRESS] [--relay IP] [--realm REALM] [--user NAME:PASSWORD]... [ADDRESS]";
+const SOFTWARE: &str = concat!("rtcrs ", env!("CARGO_PKG_VERSION"));
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(arg: Option<String>) -> T {
+    match arg.map(|arg| arg.parse()) {
+        Some(Ok(parsed)) => parsed,
+        _ => usage(),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let mut bind_address: SocketAddr = "0.0.0.0:3478".parse().unwrap();
+    let mut other_address: Option<SocketAddr> = None;
+    let mut relay_ip: Option<IpAddr> = None;
+    let mut realm = None;
+    let mut users = vec![];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--other-address" => other_address = Some(parse(args.next())),
+            "--relay" => relay_ip = Some(parse(args.next())),
+            "--realm" => match args.next() {
+                Some(arg) => realm = Some(arg),
+                None => usage(),
+            },
+            "--user" => match args.next().as_ref().and_then(|user| user.split_once(':')) {
+                Some((username, password)) => {
+                    users.push((username.to_string(), password.to_string()))
+                }
+                None => usage(),
+            },
+            "-h" | "--help" => usage(),
+            _ => bind_address = parse(Some(arg)),
+        }
+    }
+
+    // the TURN server doesn't take part in NAT behaviour discovery
+    if relay_ip.is_some() && other_address.is_some() {
+        eprintln!("--other-address can't be used with --relay");
+        usage();
+    }
+
+    let socket = UdpSocket::bind(bind_address).await?;
+
+    // relaying turns the server into a TURN server, which still answers
+    // Binding requests
+    if let Some(relay_ip) = relay_ip {
+        let mut server = stun::TurnServer::new(relay_ip).with_software(SOFTWARE);
+        if let Some(realm) = &realm {
+            server = server.with_realm(realm);
+        }
+        for (username, password) in &users {
+            server = server.and_user(username, password);
+        }
+
+        return server.serve(socket).await;
+    }
+
+    let mut server = stun::Server::new().with_software(SOFTWARE);
+    if let Some(other_address) = other_address {
+        server = server.with_other_address(other_address);
+    }
+    if let Some(realm) = &realm {
+        server = server.with_realm(realm);
+    }
+    for (username, password) in &users {
+        server = server.and_user(username, password);
+    }
+
+    server.serve(socket).await
+}
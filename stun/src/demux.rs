@@ -0,0 +1,68 @@
+// The protocols that can share a socket with STUN in WebRTC.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    Stun,
+    Zrtp,
+    Dtls,
+    ChannelData,
+    Rtp,
+}
+
+//                  +----------------+
+//                  |        [0..3] -+--> forward to STUN
+//                  |                |
+//                  |      [16..19] -+--> forward to ZRTP
+//                  |                |
+//      packet -->  |      [20..63] -+--> forward to DTLS
+//                  |                |
+//                  |      [64..79] -+--> forward to TURN Channel
+//                  |                |
+//                  |    [128..191] -+--> forward to RTP/RTCP
+//                  +----------------+
+//
+//           Figure 3: The Demultiplexing Algorithm
+//
+// Returns None for anything outside of these ranges, which should be
+// dropped.
+//
+// https://tools.ietf.org/html/rfc7983#section-7
+pub fn demultiplex(packet: &[u8]) -> Option<Protocol> {
+    let protocol = match packet.first()? {
+        0..=3 => Protocol::Stun,
+        16..=19 => Protocol::Zrtp,
+        20..=63 => Protocol::Dtls,
+        64..=79 => Protocol::ChannelData,
+        128..=191 => Protocol::Rtp,
+        _ => return None,
+    };
+
+    Some(protocol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{turn::ChannelData, Class, Header, Message, Method, TransactionId};
+
+    #[test]
+    fn demultiplex_first_byte() {
+        let stun = Message::base(Header::new(
+            Method::Binding,
+            Class::Request,
+            TransactionId::new(),
+        ))
+        .to_bytes();
+        assert_eq!(demultiplex(&stun), Some(Protocol::Stun));
+
+        let channel_data = ChannelData::new(0x_4FFF, b"hello").to_bytes();
+        assert_eq!(demultiplex(&channel_data), Some(Protocol::ChannelData));
+
+        // a DTLS handshake record, an RTP packet and a ZRTP packet
+        assert_eq!(demultiplex(&[0x_16, 0x_FE, 0x_FD]), Some(Protocol::Dtls));
+        assert_eq!(demultiplex(&[0x_80, 0x_60]), Some(Protocol::Rtp));
+        assert_eq!(demultiplex(&[0x_10, 0x_00]), Some(Protocol::Zrtp));
+
+        assert_eq!(demultiplex(&[0x_FF]), None);
+        assert_eq!(demultiplex(&[]), None);
+    }
+}
@@ -1,9 +1,25 @@
 mod attribute;
+mod auth;
+mod demux;
+mod server;
+mod transaction;
+mod turn;
+
+use std::{
+    borrow::Cow,
+    convert::{TryFrom, TryInto},
+};
 
-use std::convert::{TryFrom, TryInto};
-
-use crc::crc32;
-use crypto::{hmac::Hmac, mac::Mac, sha1::Sha1};
+use bytes::BytesMut;
+use crc::{crc32, Hasher32};
+use crypto::{
+    digest::Digest,
+    hmac::Hmac,
+    mac::{Mac, MacResult},
+    md5::Md5,
+    sha1::Sha1,
+    util::fixed_time_eq,
+};
 use fehler::{throw, throws};
 use nom::{
     bits::{
@@ -20,23 +36,69 @@ use nom::{
 use num_enum::TryFromPrimitive;
 use rand::Rng;
 
-pub use crate::attribute::Attribute;
-use crate::attribute::{attribute, fingerprint::Fingerprint};
+use crate::attribute::{
+    attribute,
+    fingerprint::{self, Fingerprint},
+    goog_message_integrity_32::{self, GOOG_MESSAGE_INTEGRITY_32_LEN},
+    message_integrity, pad_len, Tlv,
+};
+pub use crate::{
+    attribute::{
+        error_code::NumericCode, password_algorithm::Algorithm as PasswordAlgorithm, Attribute,
+    },
+    demux::{demultiplex, Protocol},
+    server::Server,
+    transaction::{Client, Retransmission, Transport},
+    turn::{channel_data, Allocation, ChannelData, TurnServer},
+};
 
 const MAGIC_COOKIE: u32 = 0x_2112_A442;
+const HEADER_LEN: usize = 20;
 
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum Error {
+    #[error("buffer too small, {0} bytes needed")]
+    BufferTooSmall(usize),
+    #[error("error response ({0:?})")]
+    ErrorResponse(NumericCode),
+    #[error("fingerprint does not match message")]
+    FingerprintMismatch,
+    #[error("invalid address family ({0})")]
+    InvalidAddressFamily(u8),
+    #[error("invalid channel number ({0})")]
+    InvalidChannelNumber(u16),
     #[error("invalid class ({0})")]
     InvalidClass(u8),
     #[error("invalid error code ({0})")]
     InvalidErrorCode(u16),
+    #[error("invalid goog message integrity 32 ({0:?})")]
+    InvalidGoogMessageIntegrity32(Vec<u8>),
     #[error("invalid message integrity ({0:?})")]
     InvalidMessageIntegrity(Vec<u8>),
+    #[error("invalid message integrity sha256 ({0:?})")]
+    InvalidMessageIntegritySha256(Vec<u8>),
     #[error("invalid method ({0})")]
     InvalidMethod(u16),
+    #[error("invalid password algorithm ({0})")]
+    InvalidPasswordAlgorithm(u16),
+    #[error("invalid string ({0:?})")]
+    InvalidString(Vec<u8>),
     #[error("invalid transaction id ({0:?})")]
     InvalidTransactionId(Vec<u8>),
+    #[error("invalid userhash ({0:?})")]
+    InvalidUserhash(Vec<u8>),
+    #[error("message integrity does not match message")]
+    MessageIntegrityMismatch,
+    #[error("missing attribute ({0})")]
+    MissingAttribute(u16),
+    #[error("missing fingerprint")]
+    MissingFingerprint,
+    #[error("missing message integrity")]
+    MissingMessageIntegrity,
+    #[error("transaction timed out")]
+    TransactionTimedOut,
+    #[error("transport error ({0:?})")]
+    Transport(std::io::ErrorKind),
     #[error("unimplemented attribute ({0})")]
     UnimplementedAttribute(u16),
 }
@@ -129,7 +191,7 @@ fn message_type(input: &[u8]) -> IResult<&[u8], (Method, Class), ParseError<&[u8
         .try_into()
         .map_err(|_| nom::Err::Error(Error::InvalidClass(c).into()))?;
 
-    let m = (m_11_7 << 6) | (m_6_4 << 3) | m_3_0;
+    let m = (m_11_7 << 7) | (m_6_4 << 4) | m_3_0;
     let method = m
         .try_into()
         .map_err(|_| nom::Err::Error(Error::InvalidMethod(m).into()))?;
@@ -149,7 +211,7 @@ const TRANSACTION_ID_LEN: usize = 12;
 
 type TransactionIdBuf = [u8; TRANSACTION_ID_LEN];
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct TransactionId(TransactionIdBuf);
 
 impl TransactionId {
@@ -200,7 +262,7 @@ fn transaction_id(input: &[u8]) -> IResult<&[u8], TransactionId, ParseError<&[u8
     Ok((remainder, transaction_id))
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Header {
     pub method: Method,
     pub class: Class,
@@ -218,7 +280,7 @@ impl Header {
         }
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
         let m = self.method as u16;
         let c = self.class as u16;
 
@@ -231,11 +293,17 @@ impl Header {
 
         let mt = (m_11_7 << 9) | (c_1 << 8) | (m_6_4 << 5) | (c_0 << 4) | m_3_0;
 
-        let mut header_bytes = vec![];
-        header_bytes.extend(&mt.to_be_bytes());
-        header_bytes.extend(&self.length.to_be_bytes());
-        header_bytes.extend(&MAGIC_COOKIE.to_be_bytes());
-        header_bytes.extend(&self.transaction_id.0);
+        buf[..2].copy_from_slice(&mt.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.length.to_be_bytes());
+        buf[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf[8..HEADER_LEN].copy_from_slice(&self.transaction_id.0);
+
+        HEADER_LEN
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header_bytes = vec![0x_00; HEADER_LEN];
+        self.encode(&mut header_bytes);
 
         header_bytes
     }
@@ -280,33 +348,81 @@ pub fn header(input: &[u8]) -> IResult<&[u8], Header, ParseError<&[u8]>> {
     )(input)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Message {
     pub header: Header,
     pub attributes: Vec<Attribute>,
+    // The bytes this message was decoded from, if any, so that
+    // MESSAGE-INTEGRITY and FINGERPRINT can be checked against exactly
+    // what was received rather than a re-encoding of it.
+    raw: Option<Vec<u8>>,
+}
+
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.attributes == other.attributes
+    }
 }
 
 pub fn message(input: &[u8]) -> IResult<&[u8], Message, ParseError<&[u8]>> {
     let (remainder, header) = header(input)?;
     let (remainder, attributes) =
         map_parser(take_bytes(header.length), all_consuming(many0(attribute)))(remainder)?;
+    let attributes = attributes
+        .into_iter()
+        .map(|attribute| {
+            attribute
+                .xor_transaction_id(&header.transaction_id)
+                .unwrap_or(attribute)
+        })
+        .collect();
 
-    let message = Message { header, attributes };
+    let raw = input[..input.len() - remainder.len()].to_vec();
+    let message = Message {
+        header,
+        attributes,
+        raw: Some(raw),
+    };
 
     Ok((remainder, message))
 }
 
 impl Message {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut attributes_bytes = vec![];
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + usize::from(self.header.length)
+    }
+
+    #[throws]
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let encoded_len = self.encoded_len();
+        if buf.len() < encoded_len {
+            throw!(Error::BufferTooSmall(encoded_len));
+        }
+
+        let mut offset = self.header.encode(buf);
         for attribute in &self.attributes {
-            attributes_bytes.extend(attribute.to_bytes());
+            offset += match attribute.xor_transaction_id(&self.header.transaction_id) {
+                Some(attribute) => attribute.encode(&mut buf[offset..]),
+                None => attribute.encode(&mut buf[offset..]),
+            };
         }
 
-        let header_bytes = self.header.to_bytes();
+        offset
+    }
 
-        let mut message_bytes = header_bytes;
-        message_bytes.extend(attributes_bytes);
+    pub fn encode_to(&self, buf: &mut BytesMut) {
+        let start = buf.len();
+        buf.resize(start + self.encoded_len(), 0x_00);
+
+        // SAFE: we've just made room for the whole message
+        self.encode(&mut buf[start..]).unwrap();
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message_bytes = vec![0x_00; self.encoded_len()];
+
+        // SAFE: the buffer is exactly as long as the message
+        self.encode(&mut message_bytes).unwrap();
 
         message_bytes
     }
@@ -317,62 +433,347 @@ impl Message {
         Self {
             header,
             attributes: vec![],
+            raw: None,
         }
     }
 
     pub fn with_attributes(mut self, attributes: Vec<Attribute>) -> Self {
         let mut length = 0;
         for attribute in &attributes {
-            let attribute_length: u16 = attribute.to_bytes().len().try_into().unwrap();
+            let attribute_length: u16 = attribute.encoded_len().try_into().unwrap();
             length += attribute_length;
         }
         self.header.length = length;
         self.attributes = attributes;
+        self.raw = None;
 
         self
     }
 
     pub fn and_attribute(mut self, attribute: Attribute) -> Self {
-        let attribute_length: u16 = attribute.to_bytes().len().try_into().unwrap();
+        let attribute_length: u16 = attribute.encoded_len().try_into().unwrap();
         self.header.length += attribute_length;
         self.attributes.push(attribute);
+        self.raw = None;
         self
     }
 
     pub fn with_message_integrity(mut self, key: &[u8]) -> Self {
-        // account for the MESSAGE-INTEGRITY attribute itself
-        self.header.length += 24;
-
-        let mut mac = Hmac::new(Sha1::new(), key);
-        mac.input(&self.to_bytes());
+        let mac = message_integrity_mac(&self.to_bytes(), key, MESSAGE_INTEGRITY_ATTRIBUTE_LEN);
 
         let inner = mac
-            .result()
             .code()
             .try_into()
             .expect("hmac generated an invalid message integrity");
         let attribute = Attribute::MessageIntegrity(inner);
 
+        // account for the MESSAGE-INTEGRITY attribute itself
+        self.header.length += MESSAGE_INTEGRITY_ATTRIBUTE_LEN as u16;
         self.attributes.push(attribute);
+        self.raw = None;
 
         self
     }
 
-    pub fn with_fingerprint(mut self) -> Self {
-        // account for the FINGERPRINT attribute itself
-        self.header.length += 8;
+    pub fn with_goog_message_integrity_32(mut self, key: &[u8]) -> Self {
+        let mac = message_integrity_mac(
+            &self.to_bytes(),
+            key,
+            GOOG_MESSAGE_INTEGRITY_32_ATTRIBUTE_LEN,
+        );
+
+        let inner = mac.code()[..GOOG_MESSAGE_INTEGRITY_32_LEN]
+            .try_into()
+            .expect("hmac generated an invalid goog message integrity 32");
+        let attribute = Attribute::GoogMessageIntegrity32(inner);
 
-        let checksum = crc32::checksum_ieee(&self.to_bytes());
+        // account for the GOOG-MESSAGE-INTEGRITY-32 attribute itself
+        self.header.length += GOOG_MESSAGE_INTEGRITY_32_ATTRIBUTE_LEN as u16;
+        self.attributes.push(attribute);
+        self.raw = None;
+
+        self
+    }
+
+    pub fn with_fingerprint(mut self) -> Self {
+        let checksum = fingerprint_checksum(&self.to_bytes());
 
         let inner = Fingerprint::new(checksum);
         let attribute = Attribute::Fingerprint(inner);
 
+        // account for the FINGERPRINT attribute itself
+        self.header.length += FINGERPRINT_ATTRIBUTE_LEN as u16;
         self.attributes.push(attribute);
+        self.raw = None;
 
         self
     }
 }
 
+impl Message {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        match &self.raw {
+            Some(raw) => Cow::Borrowed(raw),
+            None => Cow::Owned(self.to_bytes()),
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc5389#section-15.4
+    #[throws]
+    pub fn verify_integrity(&self, key: &[u8]) {
+        let bytes = self.bytes();
+        verify_integrity(&bytes, key)?;
+    }
+
+    #[throws]
+    pub fn verify_goog_message_integrity_32(&self, key: &[u8]) {
+        let bytes = self.bytes();
+        verify_goog_message_integrity_32(&bytes, key)?;
+    }
+
+    // https://tools.ietf.org/html/rfc5389#section-15.5
+    #[throws]
+    pub fn verify_fingerprint(&self) {
+        let bytes = self.bytes();
+        verify_fingerprint(&bytes)?;
+    }
+}
+
+// A borrowed view over an encoded message. Only the header is decoded up
+// front; attributes are framed lazily and decoded on demand.
+#[derive(Debug, PartialEq)]
+pub struct MessageRef<'a> {
+    pub header: Header,
+    raw: &'a [u8],
+}
+
+pub fn message_ref(input: &[u8]) -> IResult<&[u8], MessageRef<'_>, ParseError<&[u8]>> {
+    let (remainder, header) = header(input)?;
+    let (remainder, attributes) = take_bytes(header.length)(remainder)?;
+
+    let mut attribute_iter = RawAttributeIter(attributes, header.transaction_id);
+    while attribute_iter.next().is_some() {}
+    if !attribute_iter.0.is_empty() {
+        return Err(nom::Err::Error(ParseError::Nom(
+            attribute_iter.0,
+            nom::error::ErrorKind::LengthValue,
+        )));
+    }
+
+    let raw = &input[..HEADER_LEN + attributes.len()];
+    let message = MessageRef { header, raw };
+
+    Ok((remainder, message))
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn attributes(&self) -> RawAttributeIter<'a> {
+        RawAttributeIter(&self.raw[HEADER_LEN..], self.header.transaction_id)
+    }
+
+    pub fn attribute(&self, typ: u16) -> Option<RawAttribute<'a>> {
+        self.attributes().find(|attribute| attribute.typ == typ)
+    }
+
+    pub fn to_message(&self) -> Result<Message, ParseError<&'a [u8]>> {
+        let (_, message) = message(self.raw).map_err(|err| match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => err,
+            // SAFE: all the parsers we use are complete
+            nom::Err::Incomplete(_) => unreachable!(),
+        })?;
+
+        Ok(message)
+    }
+
+    #[throws]
+    pub fn verify_integrity(&self, key: &[u8]) {
+        verify_integrity(self.raw, key)?;
+    }
+
+    #[throws]
+    pub fn verify_fingerprint(&self) {
+        verify_fingerprint(self.raw)?;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RawAttribute<'a> {
+    pub typ: u16,
+    pub value: &'a [u8],
+    raw: &'a [u8],
+    transaction_id: TransactionId,
+}
+
+impl<'a> RawAttribute<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn decode(&self) -> Result<Attribute, ParseError<&'a [u8]>> {
+        let (_, attribute) = attribute(self.raw).map_err(|err| match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => err,
+            // SAFE: all the parsers we use are complete
+            nom::Err::Incomplete(_) => unreachable!(),
+        })?;
+
+        Ok(attribute
+            .xor_transaction_id(&self.transaction_id)
+            .unwrap_or(attribute))
+    }
+}
+
+// Iterates over the attributes of a message without decoding them. Stops
+// at the first attribute whose length runs past the end of the message.
+#[derive(Clone, Debug)]
+pub struct RawAttributeIter<'a>(&'a [u8], TransactionId);
+
+impl<'a> Iterator for RawAttributeIter<'a> {
+    type Item = RawAttribute<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.0;
+        if bytes.len() < 4 {
+            return None;
+        }
+
+        let typ = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let length = usize::from(u16::from_be_bytes([bytes[2], bytes[3]]));
+        let encoded_len = 4 + length + pad_len(length);
+        if bytes.len() < encoded_len {
+            return None;
+        }
+
+        let (raw, remainder) = bytes.split_at(encoded_len);
+        self.0 = remainder;
+
+        Some(RawAttribute {
+            typ,
+            value: &raw[4..4 + length],
+            raw,
+            transaction_id: self.1,
+        })
+    }
+}
+
+const MESSAGE_INTEGRITY_ATTRIBUTE_LEN: usize = 24;
+const GOOG_MESSAGE_INTEGRITY_32_ATTRIBUTE_LEN: usize = 8;
+const FINGERPRINT_ATTRIBUTE_LEN: usize = 8;
+
+// Walks the attributes following the header and returns the offset of the
+// first one with the given type.
+fn attribute_offset(bytes: &[u8], typ: u16) -> Option<usize> {
+    let transaction_id =
+        TransactionId::try_from(bytes.get(HEADER_LEN - TRANSACTION_ID_LEN..HEADER_LEN)?).ok()?;
+
+    let mut offset = HEADER_LEN;
+    for attribute in RawAttributeIter(bytes.get(HEADER_LEN..)?, transaction_id) {
+        if attribute.typ == typ {
+            return Some(offset);
+        }
+
+        offset += attribute.raw.len();
+    }
+
+    None
+}
+
+// key = MD5(username ":" OpaqueString(realm) ":" OpaqueString(password))
+//
+// https://tools.ietf.org/html/rfc8489#section-9.2.2
+pub fn long_term_key(username: &str, realm: &str, password: &str) -> Vec<u8> {
+    let mut md5 = Md5::new();
+    md5.input_str(&format!("{}:{}:{}", username, realm, password));
+
+    let mut key = vec![0x_00; md5.output_bytes()];
+    md5.result(&mut key);
+
+    key
+}
+
+// The HMAC is computed over the message up to (but excluding) the
+// MESSAGE-INTEGRITY attribute, with the header length adjusted to point
+// to the end of the MESSAGE-INTEGRITY attribute. GOOG-MESSAGE-INTEGRITY-32
+// is computed the same way, over a header length pointing to its own end.
+fn message_integrity_mac(prefix: &[u8], key: &[u8], attribute_len: usize) -> MacResult {
+    let length: u16 = (prefix.len() - HEADER_LEN + attribute_len)
+        .try_into()
+        .unwrap();
+
+    let mut mac = Hmac::new(Sha1::new(), key);
+    mac.input(&prefix[..2]);
+    mac.input(&length.to_be_bytes());
+    mac.input(&prefix[4..]);
+
+    mac.result()
+}
+
+// The CRC-32 is computed over the message up to (but excluding) the
+// FINGERPRINT attribute, with the header length adjusted to point to the
+// end of the FINGERPRINT attribute.
+fn fingerprint_checksum(prefix: &[u8]) -> u32 {
+    let length: u16 = (prefix.len() - HEADER_LEN + FINGERPRINT_ATTRIBUTE_LEN)
+        .try_into()
+        .unwrap();
+
+    let mut digest = crc32::Digest::new(crc32::IEEE);
+    digest.write(&prefix[..2]);
+    digest.write(&length.to_be_bytes());
+    digest.write(&prefix[4..]);
+
+    digest.sum32()
+}
+
+#[throws]
+fn verify_integrity(bytes: &[u8], key: &[u8]) {
+    let offset =
+        attribute_offset(bytes, message_integrity::TYPE).ok_or(Error::MissingMessageIntegrity)?;
+    let received = bytes
+        .get(offset + 4..offset + MESSAGE_INTEGRITY_ATTRIBUTE_LEN)
+        .ok_or(Error::MissingMessageIntegrity)?;
+
+    // MacResult compares in constant time
+    if message_integrity_mac(&bytes[..offset], key, MESSAGE_INTEGRITY_ATTRIBUTE_LEN)
+        != MacResult::new(received)
+    {
+        throw!(Error::MessageIntegrityMismatch);
+    }
+}
+
+#[throws]
+fn verify_goog_message_integrity_32(bytes: &[u8], key: &[u8]) {
+    let offset = attribute_offset(bytes, goog_message_integrity_32::TYPE)
+        .ok_or(Error::MissingMessageIntegrity)?;
+    let received = bytes
+        .get(offset + 4..offset + GOOG_MESSAGE_INTEGRITY_32_ATTRIBUTE_LEN)
+        .ok_or(Error::MissingMessageIntegrity)?;
+
+    let mac = message_integrity_mac(
+        &bytes[..offset],
+        key,
+        GOOG_MESSAGE_INTEGRITY_32_ATTRIBUTE_LEN,
+    );
+    if !fixed_time_eq(&mac.code()[..GOOG_MESSAGE_INTEGRITY_32_LEN], received) {
+        throw!(Error::MessageIntegrityMismatch);
+    }
+}
+
+#[throws]
+fn verify_fingerprint(bytes: &[u8]) {
+    let offset = attribute_offset(bytes, fingerprint::TYPE).ok_or(Error::MissingFingerprint)?;
+    let received = bytes
+        .get(offset + 4..offset + FINGERPRINT_ATTRIBUTE_LEN)
+        .ok_or(Error::MissingFingerprint)?;
+
+    let expected = Fingerprint::new(fingerprint_checksum(&bytes[..offset]));
+    if expected.value() != received {
+        throw!(Error::FingerprintMismatch);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -397,6 +798,23 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    // libwebrtc's GOOG_PING_REQUEST
+    #[test]
+    fn parse_goog_ping_header() {
+        #[rustfmt::skip]
+        let input = vec![
+            0x_02, 0x_00, 0x_00, 0x_00,
+            0x_21, 0x_12, 0x_A4, 0x_42,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_00, 0x_00, 0x_00,
+        ];
+        let actual = header(&input).unwrap().1;
+        assert_eq!(actual.method, Method::GoogPing);
+        assert_eq!(actual.class, Class::Request);
+        assert_eq!(actual.to_bytes(), input);
+    }
+
     #[test]
     fn serialize_header() {
         let header = Header {
@@ -443,4 +861,239 @@ mod tests {
 
         assert_eq!(message.header.length, 36);
     }
+
+    // Sample request from https://tools.ietf.org/html/rfc5769#section-2.1
+    #[rustfmt::skip]
+    const SAMPLE_REQUEST: [u8; 108] = [
+        0x_00, 0x_01, 0x_00, 0x_58,
+        0x_21, 0x_12, 0x_A4, 0x_42,
+        0x_B7, 0x_E7, 0x_A7, 0x_01,
+        0x_BC, 0x_34, 0x_D6, 0x_86,
+        0x_FA, 0x_87, 0x_DF, 0x_AE,
+        0x_80, 0x_22, 0x_00, 0x_10,
+        0x_53, 0x_54, 0x_55, 0x_4E,
+        0x_20, 0x_74, 0x_65, 0x_73,
+        0x_74, 0x_20, 0x_63, 0x_6C,
+        0x_69, 0x_65, 0x_6E, 0x_74,
+        0x_00, 0x_24, 0x_00, 0x_04,
+        0x_6E, 0x_00, 0x_01, 0x_FF,
+        0x_80, 0x_29, 0x_00, 0x_08,
+        0x_93, 0x_2F, 0x_F9, 0x_B1,
+        0x_51, 0x_26, 0x_3B, 0x_36,
+        0x_00, 0x_06, 0x_00, 0x_09,
+        0x_65, 0x_76, 0x_74, 0x_6A,
+        0x_3A, 0x_68, 0x_36, 0x_76,
+        0x_59, 0x_20, 0x_20, 0x_20,
+        0x_00, 0x_08, 0x_00, 0x_14,
+        0x_9A, 0x_EA, 0x_A7, 0x_0C,
+        0x_BF, 0x_D8, 0x_CB, 0x_56,
+        0x_78, 0x_1E, 0x_F2, 0x_B5,
+        0x_B2, 0x_D3, 0x_F2, 0x_49,
+        0x_C1, 0x_B5, 0x_71, 0x_A2,
+        0x_80, 0x_28, 0x_00, 0x_04,
+        0x_E5, 0x_7A, 0x_3B, 0x_CF,
+    ];
+    const SAMPLE_PASSWORD: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+    const MTU: usize = 1500;
+
+    #[test]
+    #[throws]
+    fn verify_sample_request() {
+        let (_, message) = message(&SAMPLE_REQUEST).unwrap();
+
+        message.verify_integrity(SAMPLE_PASSWORD)?;
+        message.verify_fingerprint()?;
+    }
+
+    #[test]
+    fn verify_integrity_with_wrong_key() {
+        let (_, message) = message(&SAMPLE_REQUEST).unwrap();
+
+        let actual = message.verify_integrity(b"wrong password");
+        assert_eq!(actual, Err(Error::MessageIntegrityMismatch));
+    }
+
+    #[test]
+    fn verify_corrupted_fingerprint() {
+        let mut input = SAMPLE_REQUEST;
+        input[66] ^= 0x_20;
+        let (_, message) = message(&input).unwrap();
+
+        assert_eq!(
+            message.verify_fingerprint(),
+            Err(Error::FingerprintMismatch)
+        );
+        assert_eq!(
+            message.verify_integrity(SAMPLE_PASSWORD),
+            Err(Error::MessageIntegrityMismatch)
+        );
+    }
+
+    #[test]
+    fn verify_missing_attributes() {
+        let message = Message::base(Header::new(
+            Method::Binding,
+            Class::Request,
+            TransactionId::new(),
+        ))
+        .with_attributes(vec![Attribute::username("knuth")]);
+
+        assert_eq!(
+            message.verify_integrity(&[1, 2, 3, 4]),
+            Err(Error::MissingMessageIntegrity)
+        );
+        assert_eq!(message.verify_fingerprint(), Err(Error::MissingFingerprint));
+    }
+
+    #[test]
+    #[throws]
+    fn verify_round_trip() {
+        let key = [1, 2, 3, 4];
+        let bytes = Message::base(Header::new(
+            Method::Binding,
+            Class::Success,
+            TransactionId::new(),
+        ))
+        .with_attributes(vec![
+            Attribute::username("knuth"),
+            Attribute::xor_mapped_address([192, 0, 2, 1].into(), 32853),
+        ])
+        .with_message_integrity(&key)
+        .with_fingerprint()
+        .to_bytes();
+        let (_, message) = message(&bytes).unwrap();
+
+        message.verify_integrity(&key)?;
+        message.verify_fingerprint()?;
+    }
+
+    #[test]
+    #[throws]
+    fn verify_goog_message_integrity_32_round_trip() {
+        let key = [1, 2, 3, 4];
+        let bytes = Message::base(Header::new(
+            Method::GoogPing,
+            Class::Request,
+            TransactionId::new(),
+        ))
+        .with_goog_message_integrity_32(&key)
+        .with_fingerprint()
+        .to_bytes();
+        let (_, message) = message(&bytes).unwrap();
+
+        assert_eq!(message.header.length, 16);
+        message.verify_goog_message_integrity_32(&key)?;
+        message.verify_fingerprint()?;
+        assert_eq!(
+            message.verify_goog_message_integrity_32(b"wrong password"),
+            Err(Error::MessageIntegrityMismatch)
+        );
+        assert_eq!(
+            message.verify_integrity(&key),
+            Err(Error::MissingMessageIntegrity)
+        );
+    }
+
+    #[test]
+    #[throws]
+    fn message_ref_sample_request() {
+        let (_, message) = message_ref(&SAMPLE_REQUEST).unwrap();
+
+        let types: Vec<u16> = message.attributes().map(|a| a.typ).collect();
+        assert_eq!(
+            types,
+            vec![0x_8022, 0x_0024, 0x_8029, 0x_0006, 0x_0008, 0x_8028]
+        );
+
+        let username = message.attribute(0x_0006).unwrap();
+        assert_eq!(username.value, b"evtj:h6vY");
+        assert_eq!(username.decode().unwrap(), Attribute::username("evtj:h6vY"));
+
+        message.verify_integrity(SAMPLE_PASSWORD)?;
+        message.verify_fingerprint()?;
+    }
+
+    #[test]
+    fn message_ref_to_message() {
+        let (_, message_ref) = message_ref(&SAMPLE_REQUEST).unwrap();
+        let (_, expected) = message(&SAMPLE_REQUEST).unwrap();
+
+        assert_eq!(message_ref.to_message().unwrap(), expected);
+    }
+
+    #[test]
+    fn ipv6_xor_mapped_address() {
+        // The XOR-MAPPED-ADDRESS of the sample IPv6 response from
+        // https://tools.ietf.org/html/rfc5769#section-2.2
+        #[rustfmt::skip]
+        let input = [
+            0x_01, 0x_01, 0x_00, 0x_18,
+            0x_21, 0x_12, 0x_A4, 0x_42,
+            0x_B7, 0x_E7, 0x_A7, 0x_01,
+            0x_BC, 0x_34, 0x_D6, 0x_86,
+            0x_FA, 0x_87, 0x_DF, 0x_AE,
+            0x_00, 0x_20, 0x_00, 0x_14,
+            0x_00, 0x_02, 0x_A1, 0x_47,
+            0x_01, 0x_13, 0x_A9, 0x_FA,
+            0x_A5, 0x_D3, 0x_F1, 0x_79,
+            0x_BC, 0x_25, 0x_F4, 0x_B5,
+            0x_BE, 0x_D2, 0x_B9, 0x_D9,
+        ];
+        let expected = Attribute::xor_mapped_address(
+            "2001:db8:1234:5678:11:2233:4455:6677".parse().unwrap(),
+            32853,
+        );
+
+        let (_, message) = message(&input).unwrap();
+        assert_eq!(message.attributes, vec![expected.clone()]);
+        assert_eq!(message.to_bytes(), input);
+
+        let (_, message_ref) = message_ref(&input).unwrap();
+        assert_eq!(
+            message_ref.attribute(0x_0020).unwrap().decode().unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn message_ref_truncated_attribute() {
+        #[rustfmt::skip]
+        let input = [
+            0x_00, 0x_01, 0x_00, 0x_08,
+            0x_21, 0x_12, 0x_A4, 0x_42,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_00, 0x_00, 0x_00,
+            0x_00, 0x_06, 0x_00, 0x_09,
+            0x_65, 0x_76, 0x_74, 0x_6A,
+        ];
+
+        assert!(message_ref(&input).is_err());
+    }
+
+    #[test]
+    fn encode_into_buffer() {
+        let message = Message::base(Header::new(
+            Method::Binding,
+            Class::Request,
+            TransactionId::new(),
+        ))
+        .with_attributes(vec![Attribute::username("knuth")])
+        .with_fingerprint();
+        let expected = message.to_bytes();
+
+        let mut buf = [0_u8; MTU];
+        let len = message.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+
+        let mut buf = BytesMut::from(&b"prefix"[..]);
+        message.encode_to(&mut buf);
+        assert_eq!(&buf[6..], &expected[..]);
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(
+            message.encode(&mut buf),
+            Err(Error::BufferTooSmall(expected.len()))
+        );
+    }
 }
@@ -0,0 +1,340 @@
+use std::{io, net::SocketAddr};
+
+use log::{debug, trace, warn};
+use tokio::net::UdpSocket;
+
+use crate::{
+    auth::{LongTermCredentials, Rejection},
+    message, Attribute, Class, Header, Message, Method, NumericCode,
+};
+
+const MTU: usize = 1500;
+
+// ICMP errors caused by earlier sends can be reported by a later receive,
+// and shouldn't take the server down with them.
+pub(crate) fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+// A Binding server, optionally requiring the long-term credential
+// mechanism. Handling requests is kept apart from the socket loop in
+// `serve`, so that it can be driven without a socket.
+//
+// https://tools.ietf.org/html/rfc8489#section-6.3
+#[derive(Debug, Default)]
+pub struct Server {
+    software: Option<String>,
+    other_address: Option<SocketAddr>,
+    credentials: LongTermCredentials,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_software(mut self, software: &str) -> Self {
+        self.software = Some(software.to_string());
+        self
+    }
+
+    // https://tools.ietf.org/html/rfc5780#section-7.4
+    pub fn with_other_address(mut self, other_address: SocketAddr) -> Self {
+        self.other_address = Some(other_address);
+        self
+    }
+
+    // Setting a realm turns on the long-term credential mechanism.
+    pub fn with_realm(mut self, realm: &str) -> Self {
+        self.credentials.set_realm(realm);
+        self
+    }
+
+    pub fn and_user(mut self, username: &str, password: &str) -> Self {
+        self.credentials.add_user(username, password);
+        self
+    }
+
+    // Works out the response to a request received on local from source.
+    // Returns None for anything that doesn't warrant a response.
+    pub fn handle(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        local: SocketAddr,
+    ) -> Option<Message> {
+        if request.header.class != Class::Request {
+            return None;
+        }
+
+        let has_fingerprint = request
+            .attributes
+            .iter()
+            .any(|attribute| matches!(attribute, Attribute::Fingerprint(_)));
+        if has_fingerprint {
+            if let Err(err) = request.verify_fingerprint() {
+                warn!("Dropping request from {}: {}", source, err);
+                return None;
+            }
+        }
+
+        if request.header.method != Method::Binding {
+            return Some(self.error_response(request, (NumericCode::BadRequest, "Bad Request")));
+        }
+
+        let key = match self.credentials.authenticate(request) {
+            Ok(key) => key,
+            Err(rejection) => return Some(self.error_response(request, rejection)),
+        };
+
+        let mut attributes = vec![
+            Attribute::xor_mapped_address(source.ip(), source.port()),
+            Attribute::response_origin(local.ip(), local.port()),
+        ];
+        if let Some(other_address) = self.other_address {
+            attributes.push(Attribute::other_address(
+                other_address.ip(),
+                other_address.port(),
+            ));
+        }
+        if let Some(software) = &self.software {
+            attributes.push(Attribute::software(software));
+        }
+
+        let mut response = Message::base(Header::new(
+            Method::Binding,
+            Class::Success,
+            request.header.transaction_id,
+        ))
+        .with_attributes(attributes);
+        if let Some(key) = key {
+            response = response.with_message_integrity(&key);
+        }
+
+        Some(response.with_fingerprint())
+    }
+
+    fn error_response(&mut self, request: &Message, rejection: Rejection) -> Message {
+        let (numeric_code, reason_phrase) = rejection;
+
+        let mut attributes = vec![Attribute::error_code(numeric_code, reason_phrase)];
+        attributes.extend(self.credentials.challenge(numeric_code));
+        if let Some(software) = &self.software {
+            attributes.push(Attribute::software(software));
+        }
+
+        Message::base(Header::new(
+            request.header.method,
+            Class::Error,
+            request.header.transaction_id,
+        ))
+        .with_attributes(attributes)
+        .with_fingerprint()
+    }
+
+    pub async fn serve(mut self, socket: UdpSocket) -> io::Result<()> {
+        let local = socket.local_addr()?;
+        debug!("Serving STUN on {}", local);
+
+        let mut buf = [0; MTU];
+        loop {
+            let (bytes_rcvd, source) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(err) if is_transient(&err) => {
+                    debug!("Ignoring transient receive error: {}", err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let request = match message(&buf[..bytes_rcvd]) {
+                Ok((_, request)) => request,
+                Err(err) => {
+                    warn!("Dropping malformed message from {}: {}", source, err);
+                    continue;
+                }
+            };
+            trace!("Received {:?} from {}", request, source);
+
+            if let Some(response) = self.handle(&request, source, local) {
+                trace!("Sending {:?} to {}", response, source);
+                if let Err(err) = socket.send_to(&response.to_bytes(), source).await {
+                    warn!("Failed to send response to {}: {}", source, err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{long_term_key, TransactionId};
+
+    fn source() -> SocketAddr {
+        "192.0.2.1:32853".parse().unwrap()
+    }
+
+    fn local() -> SocketAddr {
+        "192.0.2.2:3478".parse().unwrap()
+    }
+
+    // Requests are sent over the wire before a server sees them, so make
+    // sure ours carry their raw bytes too.
+    fn received(request: Message) -> Message {
+        let (_, request) = message(&request.to_bytes()).unwrap();
+
+        request
+    }
+
+    fn binding_request() -> Message {
+        Message::base(Header::new(
+            Method::Binding,
+            Class::Request,
+            TransactionId::new(),
+        ))
+    }
+
+    fn error_code(response: &Message) -> Option<NumericCode> {
+        response
+            .attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::ErrorCode(error_code) => Some(error_code.numeric_code()),
+                _ => None,
+            })
+    }
+
+    fn nonce(response: &Message) -> String {
+        response
+            .attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Nonce(nonce) => Some(nonce.as_str().to_string()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn authenticated_request(nonce: &str, password: &str) -> Message {
+        let key = long_term_key("user", "example.org", password);
+
+        received(
+            binding_request()
+                .with_attributes(vec![
+                    Attribute::username("user"),
+                    Attribute::realm("example.org"),
+                    Attribute::nonce(nonce),
+                ])
+                .with_message_integrity(&key)
+                .with_fingerprint(),
+        )
+    }
+
+    #[test]
+    fn binding_response() {
+        let mut server = Server::new()
+            .with_software("rtcrs")
+            .with_other_address("192.0.2.3:3479".parse().unwrap());
+        let request = received(binding_request().with_fingerprint());
+
+        let response = server.handle(&request, source(), local()).unwrap();
+
+        assert_eq!(response.header.class, Class::Success);
+        assert_eq!(
+            response.header.transaction_id,
+            request.header.transaction_id
+        );
+        assert_eq!(
+            response.attributes[..4],
+            [
+                Attribute::xor_mapped_address(source().ip(), source().port()),
+                Attribute::response_origin(local().ip(), local().port()),
+                Attribute::other_address([192, 0, 2, 3].into(), 3479),
+                Attribute::software("rtcrs"),
+            ]
+        );
+        assert_eq!(received(response).verify_fingerprint(), Ok(()));
+    }
+
+    #[test]
+    fn indications_are_ignored() {
+        let mut server = Server::new();
+        let indication = Message::base(Header::new(
+            Method::Binding,
+            Class::Indication,
+            TransactionId::new(),
+        ));
+
+        assert!(server.handle(&indication, source(), local()).is_none());
+    }
+
+    #[test]
+    fn long_term_credentials() {
+        let mut server = Server::new()
+            .with_realm("example.org")
+            .and_user("user", "password");
+
+        let challenge = server
+            .handle(&received(binding_request()), source(), local())
+            .unwrap();
+        assert_eq!(error_code(&challenge), Some(NumericCode::Unauthenticated));
+
+        let request = authenticated_request(&nonce(&challenge), "password");
+        let response = server.handle(&request, source(), local()).unwrap();
+        assert_eq!(response.header.class, Class::Success);
+
+        let key = long_term_key("user", "example.org", "password");
+        assert_eq!(received(response).verify_integrity(&key), Ok(()));
+    }
+
+    #[test]
+    fn wrong_password() {
+        let mut server = Server::new()
+            .with_realm("example.org")
+            .and_user("user", "password");
+
+        let challenge = server
+            .handle(&received(binding_request()), source(), local())
+            .unwrap();
+
+        let request = authenticated_request(&nonce(&challenge), "wrong");
+        let response = server.handle(&request, source(), local()).unwrap();
+        assert_eq!(error_code(&response), Some(NumericCode::Unauthenticated));
+    }
+
+    #[test]
+    fn stale_nonce() {
+        let mut server = Server::new()
+            .with_realm("example.org")
+            .and_user("user", "password");
+
+        let request = authenticated_request("not-a-nonce", "password");
+        let response = server.handle(&request, source(), local()).unwrap();
+        assert_eq!(error_code(&response), Some(NumericCode::StaleNonce));
+    }
+
+    #[test]
+    fn nonces_from_another_server() {
+        let mut other = Server::new()
+            .with_realm("example.org")
+            .and_user("user", "password");
+        let mut server = Server::new()
+            .with_realm("example.org")
+            .and_user("user", "password");
+
+        let challenge = other
+            .handle(&received(binding_request()), source(), local())
+            .unwrap();
+
+        let request = authenticated_request(&nonce(&challenge), "password");
+        let response = server.handle(&request, source(), local()).unwrap();
+        assert_eq!(error_code(&response), Some(NumericCode::StaleNonce));
+    }
+}
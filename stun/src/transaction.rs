@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{net::UdpSocket, sync::oneshot, time};
+
+use crate::{Class, Error, Message, TransactionId};
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize>;
+}
+
+#[async_trait]
+impl Transport for UdpSocket {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, target).await
+    }
+}
+
+// https://tools.ietf.org/html/rfc5389#section-7.2.1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Retransmission {
+    pub rto: Duration,
+    pub rc: u32,
+    pub rm: u32,
+}
+
+impl Default for Retransmission {
+    fn default() -> Self {
+        Self {
+            rto: Duration::from_millis(500),
+            rc: 7,
+            rm: 16,
+        }
+    }
+}
+
+type Pending = Arc<Mutex<HashMap<TransactionId, oneshot::Sender<Message>>>>;
+
+// Removes a transaction from the pending table however the request
+// completes, including when its future is dropped.
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    transaction_id: TransactionId,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.transaction_id);
+    }
+}
+
+// Sends requests over a transport and matches responses to them by
+// transaction ID. The client doesn't read from the transport itself;
+// whoever owns the receiving end passes responses to `handle_response`.
+pub struct Client<T: ?Sized> {
+    transport: Arc<T>,
+    retransmission: Retransmission,
+    pending: Pending,
+}
+
+impl<T: ?Sized> fmt::Debug for Client<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("retransmission", &self.retransmission)
+            .field("pending", &self.pending.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Clone for Client<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: Arc::clone(&self.transport),
+            retransmission: self.retransmission,
+            pending: Arc::clone(&self.pending),
+        }
+    }
+}
+
+impl<T: Transport + ?Sized> Client<T> {
+    pub fn new(transport: Arc<T>) -> Self {
+        Self {
+            transport,
+            retransmission: Retransmission::default(),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_retransmission(mut self, retransmission: Retransmission) -> Self {
+        self.retransmission = retransmission;
+        self
+    }
+
+    pub fn transport(&self) -> &Arc<T> {
+        &self.transport
+    }
+
+    // Requests are sent at 0, RTO, 3*RTO, 7*RTO, ... until Rc requests
+    // have been sent, after which we wait a further Rm*RTO for a response.
+    pub async fn request(&self, request: &Message, target: SocketAddr) -> Result<Message, Error> {
+        let transaction_id = request.header.transaction_id;
+
+        let (sender, mut receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(transaction_id, sender);
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            transaction_id,
+        };
+
+        let request_bytes = request.to_bytes();
+        let Retransmission { rto, rc, rm } = self.retransmission;
+
+        let mut interval = rto;
+        for transmission in 1..=rc {
+            self.transport
+                .send_to(&request_bytes, target)
+                .await
+                .map_err(|err| Error::Transport(err.kind()))?;
+
+            let wait = if transmission == rc {
+                rto * rm
+            } else {
+                interval
+            };
+            match time::timeout(wait, &mut receiver).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(_)) => break,
+                Err(_) => interval *= 2,
+            }
+        }
+
+        Err(Error::TransactionTimedOut)
+    }
+
+    // Completes the outstanding request the given response belongs to.
+    // Anything that isn't a response to one of our requests is handed
+    // back to the caller.
+    pub fn handle_response(&self, response: Message) -> Option<Message> {
+        if response.header.class != Class::Success && response.header.class != Class::Error {
+            return Some(response);
+        }
+
+        let maybe_sender = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&response.header.transaction_id);
+
+        match maybe_sender {
+            Some(sender) => {
+                // the requester may have given up in the meantime
+                let _ = sender.send(response);
+                None
+            }
+            None => Some(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        sync::mpsc,
+        task,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{message, Attribute, Header, Method};
+
+    type Sent = (Vec<u8>, SocketAddr, Instant);
+
+    struct MemoryTransport(mpsc::UnboundedSender<Sent>);
+
+    #[async_trait]
+    impl Transport for MemoryTransport {
+        async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+            self.0
+                .send((buf.to_vec(), target, Instant::now()))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+            Ok(buf.len())
+        }
+    }
+
+    fn client() -> (Client<MemoryTransport>, mpsc::UnboundedReceiver<Sent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = Client::new(Arc::new(MemoryTransport(sender)));
+
+        (client, receiver)
+    }
+
+    fn target() -> SocketAddr {
+        "192.0.2.1:3478".parse().unwrap()
+    }
+
+    fn binding_request() -> Message {
+        Message::base(Header::new(
+            Method::Binding,
+            Class::Request,
+            TransactionId::new(),
+        ))
+    }
+
+    fn success_response(request_bytes: &[u8]) -> Message {
+        let (_, request) = message(request_bytes).unwrap();
+
+        Message::base(Header::new(
+            Method::Binding,
+            Class::Success,
+            request.header.transaction_id,
+        ))
+        .with_attributes(vec![Attribute::xor_mapped_address(
+            [192, 0, 2, 2].into(),
+            32853,
+        )])
+    }
+
+    #[tokio::test]
+    async fn request_and_response() {
+        let (client, mut sent) = client();
+
+        let responder = client.clone();
+        task::spawn(async move {
+            let (request_bytes, sent_to, _) = sent.recv().await.unwrap();
+            assert_eq!(sent_to, target());
+
+            let unmatched = responder.handle_response(success_response(&request_bytes));
+            assert!(unmatched.is_none());
+        });
+
+        let response = client.request(&binding_request(), target()).await.unwrap();
+
+        assert_eq!(response.header.class, Class::Success);
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
+    // Tokio rounds timers up to the next millisecond, even with the clock
+    // paused, so each sleep can overshoot slightly.
+    fn assert_about(actual: Duration, expected: Duration) {
+        assert!(
+            actual >= expected && actual <= expected + Duration::from_millis(5),
+            "{:?} is not about {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn retransmits_with_rto_doubling() {
+        time::pause();
+        let (client, mut sent) = client();
+
+        let responder = client.clone();
+        let handle = task::spawn(async move {
+            let mut times = vec![];
+            let mut last_request = vec![];
+            for _ in 0..3 {
+                let (request_bytes, _, at) = sent.recv().await.unwrap();
+                times.push(at);
+                last_request = request_bytes;
+            }
+            responder.handle_response(success_response(&last_request));
+
+            times
+        });
+
+        client.request(&binding_request(), target()).await.unwrap();
+        let times = handle.await.unwrap();
+
+        assert_about(times[1] - times[0], Duration::from_millis(500));
+        assert_about(times[2] - times[1], Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        time::pause();
+        let (client, mut sent) = client();
+        let client = client.with_retransmission(Retransmission {
+            rto: Duration::from_millis(100),
+            rc: 3,
+            rm: 4,
+        });
+
+        let start = Instant::now();
+        let result = client.request(&binding_request(), target()).await;
+
+        assert_eq!(result, Err(Error::TransactionTimedOut));
+        assert_about(start.elapsed(), Duration::from_millis(700));
+
+        let mut transmissions = 0;
+        while sent.try_recv().is_ok() {
+            transmissions += 1;
+        }
+        assert_eq!(transmissions, 3);
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unmatched_responses_are_returned() {
+        let (client, _sent) = client();
+
+        let request = binding_request();
+        assert!(client.handle_response(request).is_some());
+
+        let response = success_response(&binding_request().to_bytes());
+        assert!(client.handle_response(response).is_some());
+    }
+}
@@ -0,0 +1,121 @@
+use std::convert::TryInto;
+
+use fehler::{throw, throws};
+use nom::{multi::length_data, number::complete::be_u16, IResult};
+
+use crate::{turn::CHANNEL_NUMBERS, Error, ParseError};
+
+const HEADER_LEN: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelData {
+    pub channel_number: u16,
+    pub data: Vec<u8>,
+}
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |         Channel Number        |            Length             |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                                                               |
+// /                       Application Data                        /
+// /                                                               /
+// |                                                               |
+// |                               +-------------------------------+
+// |                               |
+// +-------------------------------+
+//
+// Over UDP the padding to a multiple of four bytes is optional, so we
+// leave it off, and leave any that's received in the remainder.
+//
+// https://tools.ietf.org/html/rfc8656#section-12.4
+pub fn channel_data(input: &[u8]) -> IResult<&[u8], ChannelData, ParseError<&[u8]>> {
+    let (remainder, channel_number) = be_u16(input)?;
+    if !CHANNEL_NUMBERS.contains(&channel_number) {
+        return Err(nom::Err::Error(ParseError::Stun(
+            Error::InvalidChannelNumber(channel_number),
+        )));
+    }
+
+    let (remainder, data) = length_data(be_u16)(remainder)?;
+    let channel_data = ChannelData {
+        channel_number,
+        data: data.to_vec(),
+    };
+
+    Ok((remainder, channel_data))
+}
+
+impl ChannelData {
+    pub fn new(channel_number: u16, data: &[u8]) -> Self {
+        Self {
+            channel_number,
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.data.len()
+    }
+
+    #[throws]
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let encoded_len = self.encoded_len();
+        if buf.len() < encoded_len {
+            throw!(Error::BufferTooSmall(encoded_len));
+        }
+
+        // SAFE: data relayed over UDP always fits in a datagram
+        let length: u16 = self.data.len().try_into().unwrap();
+
+        buf[..2].copy_from_slice(&self.channel_number.to_be_bytes());
+        buf[2..4].copy_from_slice(&length.to_be_bytes());
+        buf[HEADER_LEN..encoded_len].copy_from_slice(&self.data);
+
+        encoded_len
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut channel_data_bytes = vec![0x_00; self.encoded_len()];
+
+        // SAFE: the buffer is exactly as long as the channel data
+        self.encode(&mut channel_data_bytes).unwrap();
+
+        channel_data_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turn::send_indication;
+
+    #[test]
+    fn round_trip_bytes() {
+        #[rustfmt::skip]
+        let input = [
+            0x_40, 0x_00, 0x_00, 0x_05,
+            0x_68, 0x_65, 0x_6C, 0x_6C,
+            0x_6F, 0x_00, 0x_00, 0x_00,
+        ];
+
+        let (remainder, channel_data) = channel_data(&input).unwrap();
+        assert_eq!(channel_data, ChannelData::new(0x_4000, b"hello"));
+        assert_eq!(remainder, [0x_00; 3]);
+
+        assert_eq!(channel_data.to_bytes(), input[..9]);
+    }
+
+    #[test]
+    fn stun_messages_are_not_channel_data() {
+        let message = send_indication("192.0.2.1:5000".parse().unwrap(), b"hello").to_bytes();
+
+        assert_eq!(
+            channel_data(&message),
+            Err(nom::Err::Error(ParseError::Stun(
+                Error::InvalidChannelNumber(0x_0016)
+            )))
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    attribute::{
+        error_code, requested_transport::PROTOCOL_UDP, xor_mapped_address, xor_relayed_address,
+    },
+    long_term_key,
+    turn::{peer_data, send_indication, ChannelData, CHANNEL_NUMBERS, DEFAULT_LIFETIME},
+    Attribute, Class, Client, Error, Header, Message, Method, NumericCode, TransactionId,
+    Transport,
+};
+
+// An unauthenticated request is challenged with a 401, and an
+// authenticated one may then still be met with a 438 once its nonce goes
+// stale, so that's as many attempts as a request should ever need.
+const MAX_ATTEMPTS: usize = 3;
+
+struct Challenge {
+    realm: String,
+    nonce: String,
+    key: Vec<u8>,
+}
+
+// Sends requests to a TURN server using the long-term credential
+// mechanism.
+//
+// https://tools.ietf.org/html/rfc8489#section-9.2.3
+struct Session<T: ?Sized> {
+    client: Client<T>,
+    server: SocketAddr,
+    username: String,
+    password: String,
+    challenge: Mutex<Option<Challenge>>,
+}
+
+impl<T: Transport + ?Sized> Session<T> {
+    async fn request(&self, method: Method, attributes: Vec<Attribute>) -> Result<Message, Error> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+
+            let request = self.authenticated(
+                Message::base(Header::new(method, Class::Request, TransactionId::new()))
+                    .with_attributes(attributes.clone()),
+            );
+            let response = self.client.request(&request, self.server).await?;
+
+            if response.header.class == Class::Success {
+                if let Some(key) = self.key() {
+                    response.verify_integrity(&key)?;
+                }
+
+                return Ok(response);
+            }
+
+            let numeric_code = response
+                .attributes
+                .iter()
+                .find_map(|attribute| match attribute {
+                    Attribute::ErrorCode(error_code) => Some(error_code.numeric_code()),
+                    _ => None,
+                })
+                .ok_or(Error::MissingAttribute(error_code::TYPE))?;
+
+            let challenged = numeric_code == NumericCode::Unauthenticated
+                || numeric_code == NumericCode::StaleNonce;
+            if !challenged || attempts == MAX_ATTEMPTS || !self.take_challenge(&response) {
+                return Err(Error::ErrorResponse(numeric_code));
+            }
+        }
+    }
+
+    fn key(&self) -> Option<Vec<u8>> {
+        self.challenge
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|challenge| challenge.key.clone())
+    }
+
+    fn authenticated(&self, request: Message) -> Message {
+        match &*self.challenge.lock().unwrap() {
+            Some(challenge) => request
+                .and_attribute(Attribute::username(&self.username))
+                .and_attribute(Attribute::realm(&challenge.realm))
+                .and_attribute(Attribute::nonce(&challenge.nonce))
+                .with_message_integrity(&challenge.key),
+            None => request,
+        }
+    }
+
+    // Picks up the realm and nonce from a 401 or 438 response, returning
+    // false if they're missing.
+    fn take_challenge(&self, response: &Message) -> bool {
+        let mut maybe_realm = None;
+        let mut maybe_nonce = None;
+        for attribute in &response.attributes {
+            match attribute {
+                Attribute::Realm(realm) => maybe_realm = Some(realm.as_str()),
+                Attribute::Nonce(nonce) => maybe_nonce = Some(nonce.as_str()),
+                _ => continue,
+            }
+        }
+
+        let (realm, nonce) = match (maybe_realm, maybe_nonce) {
+            (Some(realm), Some(nonce)) => (realm, nonce),
+            _ => return false,
+        };
+
+        *self.challenge.lock().unwrap() = Some(Challenge {
+            realm: realm.to_string(),
+            nonce: nonce.to_string(),
+            key: long_term_key(&self.username, realm, &self.password),
+        });
+
+        true
+    }
+}
+
+// A relayed transport address on a TURN server. Sending to a peer through
+// the allocation wraps the data in a Send indication, but the allocation
+// doesn't read from the transport itself; whoever owns the receiving end
+// passes Data indications to `peer_data`, and responses to the client.
+//
+// https://tools.ietf.org/html/rfc8656#section-7
+pub struct Allocation<T: ?Sized> {
+    session: Session<T>,
+    relayed_address: SocketAddr,
+    mapped_address: SocketAddr,
+    lifetime: Mutex<Duration>,
+    permissions: Mutex<HashSet<IpAddr>>,
+    channels: Mutex<HashMap<u16, SocketAddr>>,
+}
+
+impl<T: ?Sized> fmt::Debug for Allocation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Allocation")
+            .field("server", &self.session.server)
+            .field("relayed_address", &self.relayed_address)
+            .field("mapped_address", &self.mapped_address)
+            .field("lifetime", &*self.lifetime.lock().unwrap())
+            .finish()
+    }
+}
+
+fn relayed_address(response: &Message) -> Result<SocketAddr, Error> {
+    response
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            Attribute::XorRelayedAddress(relayed) => {
+                Some(SocketAddr::new(relayed.address(), relayed.port()))
+            }
+            _ => None,
+        })
+        .ok_or(Error::MissingAttribute(xor_relayed_address::TYPE))
+}
+
+fn mapped_address(response: &Message) -> Result<SocketAddr, Error> {
+    response
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            Attribute::XorMappedAddress(mapped) => {
+                Some(SocketAddr::new(mapped.address(), mapped.port()))
+            }
+            _ => None,
+        })
+        .ok_or(Error::MissingAttribute(xor_mapped_address::TYPE))
+}
+
+fn granted_lifetime(response: &Message) -> Option<Duration> {
+    response
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            Attribute::Lifetime(lifetime) => Some(Duration::from_secs(lifetime.seconds().into())),
+            _ => None,
+        })
+}
+
+impl<T: Transport + ?Sized> Allocation<T> {
+    // https://tools.ietf.org/html/rfc8656#section-7.1
+    pub async fn allocate(
+        client: Client<T>,
+        server: SocketAddr,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, Error> {
+        let session = Session {
+            client,
+            server,
+            username: username.to_string(),
+            password: password.to_string(),
+            challenge: Mutex::new(None),
+        };
+
+        let response = session
+            .request(
+                Method::Allocate,
+                vec![Attribute::requested_transport(PROTOCOL_UDP)],
+            )
+            .await?;
+
+        Ok(Self {
+            session,
+            relayed_address: relayed_address(&response)?,
+            mapped_address: mapped_address(&response)?,
+            lifetime: Mutex::new(granted_lifetime(&response).unwrap_or(DEFAULT_LIFETIME)),
+            permissions: Mutex::new(HashSet::new()),
+            channels: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.session.server
+    }
+
+    pub fn relayed_address(&self) -> SocketAddr {
+        self.relayed_address
+    }
+
+    // Our address as seen by the server, which makes for a server
+    // reflexive candidate without a separate Binding request.
+    pub fn mapped_address(&self) -> SocketAddr {
+        self.mapped_address
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        *self.lifetime.lock().unwrap()
+    }
+
+    // Asks for the allocation to last a further lifetime, returning the
+    // lifetime the server actually granted.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-8
+    pub async fn refresh(&self, lifetime: Duration) -> Result<Duration, Error> {
+        let seconds = lifetime.as_secs().try_into().unwrap_or(u32::MAX);
+        let response = self
+            .session
+            .request(Method::Refresh, vec![Attribute::lifetime(seconds)])
+            .await?;
+
+        let granted = granted_lifetime(&response).unwrap_or(lifetime);
+        *self.lifetime.lock().unwrap() = granted;
+
+        Ok(granted)
+    }
+
+    pub async fn deallocate(&self) -> Result<(), Error> {
+        self.refresh(Duration::from_secs(0)).await?;
+
+        Ok(())
+    }
+
+    // https://tools.ietf.org/html/rfc8656#section-10
+    pub async fn create_permission(&self, peers: &[IpAddr]) -> Result<(), Error> {
+        let attributes = peers
+            .iter()
+            .map(|peer| Attribute::xor_peer_address(*peer, 0))
+            .collect();
+        self.session
+            .request(Method::CreatePermission, attributes)
+            .await?;
+
+        self.permissions.lock().unwrap().extend(peers);
+
+        Ok(())
+    }
+
+    pub fn has_permission(&self, peer: IpAddr) -> bool {
+        self.permissions.lock().unwrap().contains(&peer)
+    }
+
+    // Permissions only last five minutes, so need refreshing more often
+    // than the allocation itself.
+    pub async fn refresh_permissions(&self) -> Result<(), Error> {
+        let peers: Vec<IpAddr> = self.permissions.lock().unwrap().iter().cloned().collect();
+        if peers.is_empty() {
+            return Ok(());
+        }
+
+        self.create_permission(&peers).await
+    }
+
+    // Binding (or rebinding, to refresh) a channel also installs or
+    // refreshes a permission for the peer.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-12.1
+    pub async fn bind_channel(&self, channel_number: u16, peer: SocketAddr) -> Result<(), Error> {
+        if !CHANNEL_NUMBERS.contains(&channel_number) {
+            return Err(Error::InvalidChannelNumber(channel_number));
+        }
+
+        self.session
+            .request(
+                Method::ChannelBind,
+                vec![
+                    Attribute::channel_number(channel_number),
+                    Attribute::xor_peer_address(peer.ip(), peer.port()),
+                ],
+            )
+            .await?;
+
+        self.channels.lock().unwrap().insert(channel_number, peer);
+        self.permissions.lock().unwrap().insert(peer.ip());
+
+        Ok(())
+    }
+
+    pub fn channel(&self, channel_number: u16) -> Option<SocketAddr> {
+        self.channels.lock().unwrap().get(&channel_number).cloned()
+    }
+
+    // Responses to our requests arrive on the same transport as everything
+    // else from the server, so need handing back to the client.
+    pub fn handle_response(&self, response: Message) -> Option<Message> {
+        self.session.client.handle_response(response)
+    }
+
+    // Unwraps a Data indication relayed from a peer.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-11.4
+    pub fn peer_data<'a>(&self, message: &'a Message) -> Option<(SocketAddr, &'a [u8])> {
+        if message.header.method != Method::Data {
+            return None;
+        }
+
+        peer_data(message)
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for Allocation<T> {
+    // Data for a peer with a channel bound goes over the channel, which
+    // saves the overhead of a Send indication.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-11.1
+    // https://tools.ietf.org/html/rfc8656#section-12.5
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let maybe_channel_number = self
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, peer)| **peer == target)
+            .map(|(channel_number, _)| *channel_number);
+
+        let bytes = match maybe_channel_number {
+            Some(channel_number) => ChannelData::new(channel_number, buf).to_bytes(),
+            None => send_indication(target, buf).to_bytes(),
+        };
+        self.session
+            .client
+            .transport()
+            .send_to(&bytes, self.session.server)
+            .await?;
+
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{sync::mpsc, task};
+
+    use super::*;
+    use crate::{message, turn::data_indication};
+
+    struct MemoryTransport(mpsc::UnboundedSender<Vec<u8>>);
+
+    #[async_trait]
+    impl Transport for MemoryTransport {
+        async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+            Ok(buf.len())
+        }
+    }
+
+    const REALM: &str = "example.org";
+    const NONCE: &str = "f7yaGg4cbZ8qBsnS";
+
+    fn server() -> SocketAddr {
+        "192.0.2.1:3478".parse().unwrap()
+    }
+
+    fn relayed() -> SocketAddr {
+        "192.0.2.1:49152".parse().unwrap()
+    }
+
+    fn mapped() -> SocketAddr {
+        "192.0.2.2:32853".parse().unwrap()
+    }
+
+    fn response(request: &Message, class: Class, attributes: Vec<Attribute>) -> Message {
+        Message::base(Header::new(
+            request.header.method,
+            class,
+            request.header.transaction_id,
+        ))
+        .with_attributes(attributes)
+    }
+
+    // Challenges anything without a MESSAGE-INTEGRITY, and grants anything
+    // with a valid one.
+    fn server_response(request: &Message) -> Message {
+        let key = long_term_key("user", REALM, "password");
+        if request.verify_integrity(&key).is_err() {
+            return response(
+                request,
+                Class::Error,
+                vec![
+                    Attribute::error_code(NumericCode::Unauthenticated, "Unauthenticated"),
+                    Attribute::realm(REALM),
+                    Attribute::nonce(NONCE),
+                ],
+            );
+        }
+
+        let attributes = match request.header.method {
+            Method::Allocate => vec![
+                Attribute::xor_relayed_address(relayed().ip(), relayed().port()),
+                Attribute::xor_mapped_address(mapped().ip(), mapped().port()),
+                Attribute::lifetime(600),
+            ],
+            Method::Refresh => vec![Attribute::lifetime(300)],
+            _ => vec![],
+        };
+
+        response(request, Class::Success, attributes).with_message_integrity(&key)
+    }
+
+    async fn allocation(password: &str) -> Result<Allocation<MemoryTransport>, Error> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let client = Client::new(Arc::new(MemoryTransport(sender)));
+
+        let responder = client.clone();
+        task::spawn(async move {
+            while let Some(request_bytes) = receiver.recv().await {
+                let (_, request) = message(&request_bytes).unwrap();
+                if request.header.class != Class::Request {
+                    continue;
+                }
+
+                let (_, response) = message(&server_response(&request).to_bytes()).unwrap();
+                responder.handle_response(response);
+            }
+        });
+
+        Allocation::allocate(client, server(), "user", password).await
+    }
+
+    #[tokio::test]
+    async fn allocate_after_challenge() {
+        let allocation = allocation("password").await.unwrap();
+
+        assert_eq!(allocation.relayed_address(), relayed());
+        assert_eq!(allocation.mapped_address(), mapped());
+        assert_eq!(allocation.lifetime(), Duration::from_secs(600));
+    }
+
+    #[tokio::test]
+    async fn wrong_password() {
+        let result = allocation("wrong").await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            Error::ErrorResponse(NumericCode::Unauthenticated)
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_and_permissions() {
+        let allocation = allocation("password").await.unwrap();
+
+        let granted = allocation.refresh(Duration::from_secs(600)).await.unwrap();
+        assert_eq!(granted, Duration::from_secs(300));
+
+        let peer: IpAddr = [192, 0, 2, 3].into();
+        allocation.create_permission(&[peer]).await.unwrap();
+        assert!(allocation.has_permission(peer));
+
+        let peer = SocketAddr::new(peer, 5000);
+        allocation.bind_channel(0x_4000, peer).await.unwrap();
+        assert_eq!(allocation.channel(0x_4000), Some(peer));
+
+        let result = allocation.bind_channel(0x_5000, peer).await;
+        assert_eq!(result, Err(Error::InvalidChannelNumber(0x_5000)));
+    }
+
+    #[tokio::test]
+    async fn peer_data_from_indication() {
+        let allocation = allocation("password").await.unwrap();
+        let peer: SocketAddr = "192.0.2.3:5000".parse().unwrap();
+
+        let indication = data_indication(peer, b"hello");
+        let (_, indication) = message(&indication.to_bytes()).unwrap();
+        assert_eq!(
+            allocation.peer_data(&indication),
+            Some((peer, &b"hello"[..]))
+        );
+
+        let send = send_indication(peer, b"hello");
+        assert_eq!(allocation.peer_data(&send), None);
+    }
+}
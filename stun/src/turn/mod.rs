@@ -0,0 +1,65 @@
+mod channel_data;
+mod client;
+mod server;
+
+use std::{net::SocketAddr, ops::RangeInclusive, time::Duration};
+
+use crate::{Attribute, Class, Header, Message, Method, TransactionId};
+
+pub use crate::turn::{
+    channel_data::{channel_data, ChannelData},
+    client::Allocation,
+    server::TurnServer,
+};
+
+// https://tools.ietf.org/html/rfc8656#section-6.2
+pub(crate) const DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+pub(crate) const MAX_LIFETIME: Duration = Duration::from_secs(3600);
+
+// https://tools.ietf.org/html/rfc8656#section-9
+pub(crate) const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+
+// https://tools.ietf.org/html/rfc8656#section-12
+pub(crate) const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+
+// https://tools.ietf.org/html/rfc8656#section-12
+pub(crate) const CHANNEL_NUMBERS: RangeInclusive<u16> = 0x_4000..=0x_4FFF;
+
+// https://tools.ietf.org/html/rfc8656#section-11
+fn indication(method: Method, peer: SocketAddr, data: &[u8]) -> Message {
+    Message::base(Header::new(method, Class::Indication, TransactionId::new())).with_attributes(
+        vec![
+            Attribute::xor_peer_address(peer.ip(), peer.port()),
+            Attribute::data(data),
+        ],
+    )
+}
+
+pub(crate) fn send_indication(peer: SocketAddr, data: &[u8]) -> Message {
+    indication(Method::Send, peer, data)
+}
+
+pub(crate) fn data_indication(peer: SocketAddr, data: &[u8]) -> Message {
+    indication(Method::Data, peer, data)
+}
+
+// Pulls the peer address and data out of a Send or Data indication.
+pub(crate) fn peer_data(message: &Message) -> Option<(SocketAddr, &[u8])> {
+    if message.header.class != Class::Indication {
+        return None;
+    }
+
+    let mut maybe_peer = None;
+    let mut maybe_data = None;
+    for attribute in &message.attributes {
+        match attribute {
+            Attribute::XorPeerAddress(peer) => {
+                maybe_peer = Some(SocketAddr::new(peer.address(), peer.port()))
+            }
+            Attribute::Data(data) => maybe_data = Some(data.as_bytes()),
+            _ => continue,
+        }
+    }
+
+    Some((maybe_peer?, maybe_data?))
+}
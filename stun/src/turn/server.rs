@@ -0,0 +1,815 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace, warn};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc,
+    task::{self, JoinHandle},
+    time,
+};
+
+use crate::{
+    attribute::requested_transport::PROTOCOL_UDP,
+    auth::{LongTermCredentials, Rejection},
+    demux::{demultiplex, Protocol},
+    message,
+    server::is_transient,
+    turn::{
+        channel_data, data_indication, peer_data, ChannelData, CHANNEL_LIFETIME, CHANNEL_NUMBERS,
+        DEFAULT_LIFETIME, MAX_LIFETIME, PERMISSION_LIFETIME,
+    },
+    Attribute, Class, Header, Message, Method, NumericCode, TransactionId,
+};
+
+const MTU: usize = 1500;
+
+// How often expired allocations, permissions and channels are cleared
+// out.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Data received from a peer on a relayed address, to be passed on to the
+// client that owns the allocation.
+#[derive(Debug)]
+struct PeerData {
+    client: SocketAddr,
+    peer: SocketAddr,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Channel {
+    peer: SocketAddr,
+    expires: Instant,
+}
+
+// https://tools.ietf.org/html/rfc8656#section-2.2
+#[derive(Debug)]
+struct Relay {
+    socket: Arc<UdpSocket>,
+    relayed_address: SocketAddr,
+    transaction_id: TransactionId,
+    key: Option<Vec<u8>>,
+    expires: Instant,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, Channel>,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Relay {
+    fn permits(&self, peer: IpAddr) -> bool {
+        match self.permissions.get(&peer) {
+            Some(expires) => *expires > Instant::now(),
+            None => false,
+        }
+    }
+
+    // The peer bound to a channel, while both the binding and the
+    // permission for the peer last. Expired ones are only removed every so
+    // often, so they have to be checked for here.
+    fn channel_peer(&self, channel_number: u16) -> Option<SocketAddr> {
+        let channel = self.channels.get(&channel_number)?;
+        if channel.expires <= Instant::now() || !self.permits(channel.peer.ip()) {
+            return None;
+        }
+
+        Some(channel.peer)
+    }
+
+    fn channel_for(&self, peer: SocketAddr) -> Option<u16> {
+        self.channels
+            .iter()
+            .find(|(_, channel)| channel.peer == peer)
+            .map(|(channel_number, _)| *channel_number)
+    }
+
+    fn allocate_response(&self, client: SocketAddr, lifetime: Duration) -> Vec<Attribute> {
+        vec![
+            Attribute::xor_relayed_address(self.relayed_address.ip(), self.relayed_address.port()),
+            Attribute::lifetime(seconds(lifetime)),
+            Attribute::xor_mapped_address(client.ip(), client.port()),
+        ]
+    }
+}
+
+fn seconds(duration: Duration) -> u32 {
+    duration.as_secs().try_into().unwrap_or(u32::MAX)
+}
+
+// Requests for anything other than deleting an allocation get at least
+// the default lifetime, and no more than the maximum.
+//
+// https://tools.ietf.org/html/rfc8656#section-7.2
+fn requested_lifetime(request: &Message) -> Duration {
+    let maybe_seconds = request
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            Attribute::Lifetime(lifetime) => Some(lifetime.seconds()),
+            _ => None,
+        });
+
+    match maybe_seconds {
+        Some(0) => Duration::from_secs(0),
+        Some(seconds) => {
+            let requested = Duration::from_secs(seconds.into());
+            requested.clamp(DEFAULT_LIFETIME, MAX_LIFETIME)
+        }
+        None => DEFAULT_LIFETIME,
+    }
+}
+
+// Reads whatever peers send to a relayed address.
+async fn read_peers(
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    sender: mpsc::UnboundedSender<PeerData>,
+) {
+    let mut buf = [0; MTU];
+    loop {
+        let (bytes_rcvd, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) if is_transient(&err) => continue,
+            Err(err) => {
+                warn!("Failed to receive on relayed address: {}", err);
+                break;
+            }
+        };
+
+        let peer_data = PeerData {
+            client,
+            peer,
+            data: buf[..bytes_rcvd].to_vec(),
+        };
+        if sender.send(peer_data).is_err() {
+            break;
+        }
+    }
+}
+
+// A TURN server relaying UDP, with relayed addresses allocated on the
+// given IP. Allocations are keyed on the client's address alone, since
+// the server only listens on the one UDP socket.
+//
+// https://tools.ietf.org/html/rfc8656#section-7.2
+#[derive(Debug)]
+pub struct TurnServer {
+    relay_ip: IpAddr,
+    software: Option<String>,
+    credentials: LongTermCredentials,
+    relays: HashMap<SocketAddr, Relay>,
+}
+
+impl TurnServer {
+    pub fn new(relay_ip: IpAddr) -> Self {
+        Self {
+            relay_ip,
+            software: None,
+            credentials: LongTermCredentials::default(),
+            relays: HashMap::new(),
+        }
+    }
+
+    pub fn with_software(mut self, software: &str) -> Self {
+        self.software = Some(software.to_string());
+        self
+    }
+
+    // Setting a realm turns on the long-term credential mechanism.
+    pub fn with_realm(mut self, realm: &str) -> Self {
+        self.credentials.set_realm(realm);
+        self
+    }
+
+    pub fn and_user(mut self, username: &str, password: &str) -> Self {
+        self.credentials.add_user(username, password);
+        self
+    }
+
+    pub async fn serve(mut self, socket: UdpSocket) -> io::Result<()> {
+        let local = socket.local_addr()?;
+        debug!("Serving TURN on {}", local);
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut expiry = time::interval(EXPIRY_INTERVAL);
+
+        let mut buf = [0; MTU];
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let (bytes_rcvd, source) = match received {
+                        Ok(received) => received,
+                        Err(err) if is_transient(&err) => {
+                            debug!("Ignoring transient receive error: {}", err);
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    let maybe_response = self
+                        .handle_client(&buf[..bytes_rcvd], source, &sender)
+                        .await;
+
+                    if let Some(response) = maybe_response {
+                        trace!("Sending {:?} to {}", response, source);
+                        if let Err(err) = socket.send_to(&response.to_bytes(), source).await {
+                            warn!("Failed to send response to {}: {}", source, err);
+                        }
+                    }
+                }
+                Some(peer_data) = receiver.recv() => self.handle_peer(&socket, peer_data).await,
+                _ = expiry.tick() => self.expire(),
+            }
+        }
+    }
+
+    async fn handle_client(
+        &mut self,
+        bytes: &[u8],
+        source: SocketAddr,
+        sender: &mpsc::UnboundedSender<PeerData>,
+    ) -> Option<Message> {
+        match demultiplex(bytes) {
+            Some(Protocol::Stun) => {}
+            // https://tools.ietf.org/html/rfc8656#section-12.6
+            Some(Protocol::ChannelData) => {
+                let (_, channel_data) = channel_data(bytes).ok()?;
+                let relay = self.relays.get(&source)?;
+                let peer = match relay.channel_peer(channel_data.channel_number) {
+                    Some(peer) => peer,
+                    None => {
+                        trace!(
+                            "Dropping data for channel {:#06x} without a binding",
+                            channel_data.channel_number
+                        );
+                        return None;
+                    }
+                };
+
+                if let Err(err) = relay.socket.send_to(&channel_data.data, peer).await {
+                    warn!("Failed to relay data to {}: {}", peer, err);
+                }
+
+                return None;
+            }
+            _ => {
+                trace!("Dropping non-STUN packet from {}", source);
+                return None;
+            }
+        }
+
+        let message = match message(bytes) {
+            Ok((_, message)) => message,
+            Err(err) => {
+                warn!("Dropping malformed message from {}: {}", source, err);
+                return None;
+            }
+        };
+        trace!("Received {:?} from {}", message, source);
+
+        match (message.header.method, message.header.class) {
+            // https://tools.ietf.org/html/rfc8656#section-11.2
+            (Method::Send, Class::Indication) => {
+                let relay = self.relays.get(&source)?;
+                let (peer, data) = peer_data(&message)?;
+                if !relay.permits(peer.ip()) {
+                    trace!("Dropping data for {} without a permission", peer);
+                    return None;
+                }
+
+                if let Err(err) = relay.socket.send_to(data, peer).await {
+                    warn!("Failed to relay data to {}: {}", peer, err);
+                }
+
+                None
+            }
+            (_, Class::Request) => Some(self.handle_request(&message, source, sender).await),
+            _ => None,
+        }
+    }
+
+    async fn handle_request(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        sender: &mpsc::UnboundedSender<PeerData>,
+    ) -> Message {
+        // Binding requests don't need authenticating, so a TURN server can
+        // double as a STUN server.
+        if request.header.method == Method::Binding {
+            let attributes = vec![Attribute::xor_mapped_address(source.ip(), source.port())];
+            return self.success_response(request, attributes, None);
+        }
+
+        let key = match self.credentials.authenticate(request) {
+            Ok(key) => key,
+            Err(rejection) => return self.error_response(request, rejection),
+        };
+
+        let result = match request.header.method {
+            Method::Allocate => self.allocate(request, source, &key, sender).await,
+            Method::Refresh => self.refresh(request, source, &key),
+            Method::CreatePermission => self.create_permission(request, source, &key),
+            Method::ChannelBind => self.bind_channel(request, source, &key),
+            _ => Err((NumericCode::BadRequest, "Bad Request")),
+        };
+
+        match result {
+            Ok(attributes) => self.success_response(request, attributes, key),
+            Err(rejection) => self.error_response(request, rejection),
+        }
+    }
+
+    // Requests on an allocation have to come from the client that made it,
+    // using the same credentials.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-5
+    fn relay_mut(
+        &mut self,
+        source: SocketAddr,
+        key: &Option<Vec<u8>>,
+    ) -> Result<&mut Relay, Rejection> {
+        match self.relays.get_mut(&source) {
+            Some(relay) if relay.key == *key => Ok(relay),
+            Some(_) => Err((NumericCode::WrongCredentials, "Wrong Credentials")),
+            None => Err((NumericCode::AllocationMismatch, "Allocation Mismatch")),
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc8656#section-7.2
+    async fn allocate(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        key: &Option<Vec<u8>>,
+        sender: &mpsc::UnboundedSender<PeerData>,
+    ) -> Result<Vec<Attribute>, Rejection> {
+        if let Some(relay) = self.relays.get(&source) {
+            // a retransmission of the request that made the allocation
+            if relay.transaction_id == request.header.transaction_id && relay.key == *key {
+                let lifetime = relay.expires.saturating_duration_since(Instant::now());
+                return Ok(relay.allocate_response(source, lifetime));
+            }
+
+            return Err((NumericCode::AllocationMismatch, "Allocation Mismatch"));
+        }
+
+        let protocol = request
+            .attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::RequestedTransport(requested_transport) => {
+                    Some(requested_transport.protocol())
+                }
+                _ => None,
+            })
+            .ok_or((NumericCode::BadRequest, "Bad Request"))?;
+        if protocol != PROTOCOL_UDP {
+            return Err((
+                NumericCode::UnsupportedTransportProtocol,
+                "Unsupported Transport Protocol",
+            ));
+        }
+
+        let lifetime = requested_lifetime(request);
+        if lifetime == Duration::from_secs(0) {
+            return Err((NumericCode::BadRequest, "Bad Request"));
+        }
+
+        let socket = UdpSocket::bind((self.relay_ip, 0)).await.map_err(|err| {
+            warn!("Failed to bind relayed address: {}", err);
+            (NumericCode::InsufficientCapacity, "Insufficient Capacity")
+        })?;
+        let relayed_address = socket.local_addr().map_err(|err| {
+            warn!("Failed to bind relayed address: {}", err);
+            (NumericCode::InsufficientCapacity, "Insufficient Capacity")
+        })?;
+        let socket = Arc::new(socket);
+        let reader = task::spawn(read_peers(Arc::clone(&socket), source, sender.clone()));
+
+        debug!("Allocated {} for {}", relayed_address, source);
+
+        let relay = Relay {
+            socket,
+            relayed_address,
+            transaction_id: request.header.transaction_id,
+            key: key.clone(),
+            expires: Instant::now() + lifetime,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            reader,
+        };
+        let attributes = relay.allocate_response(source, lifetime);
+        self.relays.insert(source, relay);
+
+        Ok(attributes)
+    }
+
+    // https://tools.ietf.org/html/rfc8656#section-8
+    fn refresh(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        key: &Option<Vec<u8>>,
+    ) -> Result<Vec<Attribute>, Rejection> {
+        let lifetime = requested_lifetime(request);
+
+        let relay = self.relay_mut(source, key)?;
+        if lifetime == Duration::from_secs(0) {
+            debug!("Deleted allocation for {}", source);
+            self.relays.remove(&source);
+        } else {
+            relay.expires = Instant::now() + lifetime;
+        }
+
+        Ok(vec![Attribute::lifetime(seconds(lifetime))])
+    }
+
+    // https://tools.ietf.org/html/rfc8656#section-10.2
+    fn create_permission(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        key: &Option<Vec<u8>>,
+    ) -> Result<Vec<Attribute>, Rejection> {
+        let peers: Vec<IpAddr> = request
+            .attributes
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::XorPeerAddress(peer) => Some(peer.address()),
+                _ => None,
+            })
+            .collect();
+        if peers.is_empty() {
+            return Err((NumericCode::BadRequest, "Bad Request"));
+        }
+
+        let relay = self.relay_mut(source, key)?;
+        if peers
+            .iter()
+            .any(|peer| peer.is_ipv4() != relay.relayed_address.is_ipv4())
+        {
+            return Err((
+                NumericCode::PeerAddressFamilyMismatch,
+                "Peer Address Family Mismatch",
+            ));
+        }
+
+        let expires = Instant::now() + PERMISSION_LIFETIME;
+        for peer in peers {
+            relay.permissions.insert(peer, expires);
+        }
+
+        Ok(vec![])
+    }
+
+    // https://tools.ietf.org/html/rfc8656#section-12.2
+    fn bind_channel(
+        &mut self,
+        request: &Message,
+        source: SocketAddr,
+        key: &Option<Vec<u8>>,
+    ) -> Result<Vec<Attribute>, Rejection> {
+        let mut maybe_channel_number = None;
+        let mut maybe_peer = None;
+        for attribute in &request.attributes {
+            match attribute {
+                Attribute::ChannelNumber(channel_number) => {
+                    maybe_channel_number = Some(channel_number.as_u16())
+                }
+                Attribute::XorPeerAddress(peer) => {
+                    maybe_peer = Some(SocketAddr::new(peer.address(), peer.port()))
+                }
+                _ => continue,
+            }
+        }
+
+        let (channel_number, peer) = match (maybe_channel_number, maybe_peer) {
+            (Some(channel_number), Some(peer)) if CHANNEL_NUMBERS.contains(&channel_number) => {
+                (channel_number, peer)
+            }
+            _ => return Err((NumericCode::BadRequest, "Bad Request")),
+        };
+
+        let relay = self.relay_mut(source, key)?;
+        if peer.is_ipv4() != relay.relayed_address.is_ipv4() {
+            return Err((
+                NumericCode::PeerAddressFamilyMismatch,
+                "Peer Address Family Mismatch",
+            ));
+        }
+
+        // a channel can be refreshed, but not rebound to another peer, and
+        // a peer can't be bound to two channels at once
+        let bound_elsewhere = match relay.channels.get(&channel_number) {
+            Some(channel) => channel.peer != peer,
+            None => relay.channel_for(peer).is_some(),
+        };
+        if bound_elsewhere {
+            return Err((NumericCode::BadRequest, "Bad Request"));
+        }
+
+        let now = Instant::now();
+        relay.channels.insert(
+            channel_number,
+            Channel {
+                peer,
+                expires: now + CHANNEL_LIFETIME,
+            },
+        );
+        relay
+            .permissions
+            .insert(peer.ip(), now + PERMISSION_LIFETIME);
+
+        Ok(vec![])
+    }
+
+    // Passes data from a peer on to the client, over a channel if one is
+    // bound, otherwise in a Data indication.
+    //
+    // https://tools.ietf.org/html/rfc8656#section-11.3
+    async fn handle_peer(&self, socket: &UdpSocket, peer_data: PeerData) {
+        let PeerData { client, peer, data } = peer_data;
+
+        let relay = match self.relays.get(&client) {
+            Some(relay) => relay,
+            None => return,
+        };
+        if !relay.permits(peer.ip()) {
+            trace!("Dropping data from {} without a permission", peer);
+            return;
+        }
+
+        let bytes = match relay.channel_for(peer) {
+            Some(channel_number) => ChannelData::new(channel_number, &data).to_bytes(),
+            None => data_indication(peer, &data).to_bytes(),
+        };
+        if let Err(err) = socket.send_to(&bytes, client).await {
+            warn!("Failed to relay data to {}: {}", client, err);
+        }
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+
+        self.relays.retain(|client, relay| {
+            let expired = relay.expires <= now;
+            if expired {
+                debug!("Allocation for {} expired", client);
+            }
+
+            !expired
+        });
+
+        for relay in self.relays.values_mut() {
+            relay.permissions.retain(|_, expires| *expires > now);
+            relay.channels.retain(|_, channel| channel.expires > now);
+        }
+    }
+
+    fn success_response(
+        &self,
+        request: &Message,
+        mut attributes: Vec<Attribute>,
+        key: Option<Vec<u8>>,
+    ) -> Message {
+        if let Some(software) = &self.software {
+            attributes.push(Attribute::software(software));
+        }
+
+        let mut response = Message::base(Header::new(
+            request.header.method,
+            Class::Success,
+            request.header.transaction_id,
+        ))
+        .with_attributes(attributes);
+        if let Some(key) = key {
+            response = response.with_message_integrity(&key);
+        }
+
+        response.with_fingerprint()
+    }
+
+    fn error_response(&mut self, request: &Message, rejection: Rejection) -> Message {
+        let (numeric_code, reason_phrase) = rejection;
+
+        let mut attributes = vec![Attribute::error_code(numeric_code, reason_phrase)];
+        attributes.extend(self.credentials.challenge(numeric_code));
+        if let Some(software) = &self.software {
+            attributes.push(Attribute::software(software));
+        }
+
+        Message::base(Header::new(
+            request.header.method,
+            Class::Error,
+            request.header.transaction_id,
+        ))
+        .with_attributes(attributes)
+        .with_fingerprint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::{turn::Allocation, Client, Error};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn turn_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        let server = TurnServer::new([127, 0, 0, 1].into())
+            .with_realm("example.org")
+            .and_user("user", "password");
+        task::spawn(server.serve(socket));
+
+        address
+    }
+
+    // Allocates from a fresh socket, passing responses to the allocation
+    // and everything else from the server to the returned receiver.
+    async fn allocation(
+        server: SocketAddr,
+    ) -> (Arc<Allocation<UdpSocket>>, UnboundedReceiver<Vec<u8>>) {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = Client::new(Arc::clone(&socket));
+
+        let responder = client.clone();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        task::spawn(async move {
+            let mut buf = [0; MTU];
+            loop {
+                let (bytes_rcvd, _) = socket.recv_from(&mut buf).await.unwrap();
+                let bytes = &buf[..bytes_rcvd];
+
+                if let Ok((_, message)) = message(bytes) {
+                    if responder.handle_response(message).is_none() {
+                        continue;
+                    }
+                }
+
+                let _ = sender.send(bytes.to_vec());
+            }
+        });
+
+        let allocation = Allocation::allocate(client, server, "user", "password")
+            .await
+            .unwrap();
+
+        (Arc::new(allocation), receiver)
+    }
+
+    async fn receive(receiver: &mut UnboundedReceiver<Vec<u8>>) -> Vec<u8> {
+        time::timeout(TIMEOUT, receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn relay_through_permission() {
+        let server = turn_server().await;
+        let (allocation, mut receiver) = allocation(server).await;
+        assert_eq!(allocation.lifetime(), DEFAULT_LIFETIME);
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_address = peer.local_addr().unwrap();
+        allocation
+            .create_permission(&[peer_address.ip()])
+            .await
+            .unwrap();
+
+        crate::Transport::send_to(&*allocation, b"hello", peer_address)
+            .await
+            .unwrap();
+        let mut buf = [0; MTU];
+        let (bytes_rcvd, source) = time::timeout(TIMEOUT, peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..bytes_rcvd], b"hello");
+        assert_eq!(source, allocation.relayed_address());
+
+        peer.send_to(b"world", source).await.unwrap();
+        let bytes = receive(&mut receiver).await;
+        let (_, indication) = message(&bytes).unwrap();
+        assert_eq!(
+            allocation.peer_data(&indication),
+            Some((peer_address, &b"world"[..]))
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_through_channel() {
+        let server = turn_server().await;
+        let (allocation, mut receiver) = allocation(server).await;
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_address = peer.local_addr().unwrap();
+        allocation
+            .bind_channel(0x_4000, peer_address)
+            .await
+            .unwrap();
+
+        crate::Transport::send_to(&*allocation, b"hello", peer_address)
+            .await
+            .unwrap();
+        let mut buf = [0; MTU];
+        let (bytes_rcvd, _) = time::timeout(TIMEOUT, peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..bytes_rcvd], b"hello");
+
+        peer.send_to(b"world", allocation.relayed_address())
+            .await
+            .unwrap();
+        let bytes = receive(&mut receiver).await;
+        let (_, channel_data) = channel_data(&bytes).unwrap();
+        assert_eq!(channel_data, ChannelData::new(0x_4000, b"world"));
+    }
+
+    #[tokio::test]
+    async fn expired_channels_and_permissions_are_not_relayed_to() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let relayed_address = socket.local_addr().unwrap();
+        let peer = "127.0.0.1:6000".parse().unwrap();
+        let later = Instant::now() + CHANNEL_LIFETIME;
+        let mut relay = Relay {
+            socket,
+            relayed_address,
+            transaction_id: TransactionId::new(),
+            key: None,
+            expires: later,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            reader: task::spawn(async {}),
+        };
+
+        relay.channels.insert(
+            0x_4000,
+            Channel {
+                peer,
+                expires: later,
+            },
+        );
+        relay.permissions.insert(peer.ip(), later);
+        assert_eq!(relay.channel_peer(0x_4000), Some(peer));
+
+        relay.permissions.insert(peer.ip(), Instant::now());
+        assert_eq!(relay.channel_peer(0x_4000), None);
+
+        relay.permissions.insert(peer.ip(), later);
+        relay.channels.get_mut(&0x_4000).unwrap().expires = Instant::now();
+        assert_eq!(relay.channel_peer(0x_4000), None);
+    }
+
+    #[tokio::test]
+    async fn deallocate() {
+        let server = turn_server().await;
+        let (allocation, _receiver) = allocation(server).await;
+
+        allocation.deallocate().await.unwrap();
+
+        let result = allocation.refresh(DEFAULT_LIFETIME).await;
+        assert_eq!(
+            result,
+            Err(Error::ErrorResponse(NumericCode::AllocationMismatch))
+        );
+    }
+
+    #[test]
+    fn lifetimes_are_clamped() {
+        let request = |seconds| {
+            Message::base(Header::new(
+                Method::Refresh,
+                Class::Request,
+                TransactionId::new(),
+            ))
+            .with_attributes(vec![Attribute::lifetime(seconds)])
+        };
+
+        assert_eq!(requested_lifetime(&request(0)), Duration::from_secs(0));
+        assert_eq!(requested_lifetime(&request(60)), DEFAULT_LIFETIME);
+        assert_eq!(
+            requested_lifetime(&request(1200)),
+            Duration::from_secs(1200)
+        );
+        assert_eq!(requested_lifetime(&request(86400)), MAX_LIFETIME);
+    }
+}
@@ -9,8 +9,6 @@ use log::{debug, error};
 async fn main() {
     env_logger::init();
 
-    let mut ice_agent = ice::Agent::new();
-
     let mut offer = String::new();
     for line in std::io::stdin().lock().lines() {
         let line = line?;
@@ -23,6 +21,14 @@ async fn main() {
     let remote_description = sdp::SessionDescription::from_base64(&offer)?;
     debug!("{}", remote_description);
 
+    let mut ice_agent = ice::Agent::from_config(ice::AgentConfig {
+        renomination: true,
+        ..ice::AgentConfig::lite()
+    })
+    .with_remote_renomination(remote_description.supports_renomination());
+    if let Some((ufrag, pwd)) = remote_description.ice_credentials() {
+        ice_agent.set_remote_credentials(&ufrag, &pwd);
+    }
     for candidate_attribute in remote_description.candidates() {
         if let Err(err) = ice_agent.add_remote_candidate(candidate_attribute) {
             error!("{}", err);
@@ -34,6 +40,7 @@ async fn main() {
 
     ice_agent.gather().await;
     let mut candidates = ice_agent.candidate_attributes();
+    candidates.push(sdp::Attribute::end_of_candidates());
 
     let video_description = sdp::MediaDescription::base(sdp::Media {
         typ: sdp::MediaType::Video,
@@ -104,5 +111,8 @@ async fn main() {
     let answer = format!(r#"{{"type": "answer", "sdp": "{}"}}"#, sdp_string);
     println!("{}", base64::encode(&answer));
 
-    ice_agent.wait_till_completion().await;
+    ice_agent.wait_till_completion().await?;
+    while let Some(event) = ice_agent.next_event().await {
+        debug!("{:?}", event);
+    }
 }